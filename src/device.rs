@@ -1,32 +1,69 @@
 use core::cell::OnceCell;
-use core::cmp;
+use core::future::{poll_fn, Future};
 use core::num::NonZeroU8;
+use core::pin::pin;
+use core::task::Poll;
 
-use config::lora_config::LoraConfig;
-use defmt::{error, info, debug, warn, Display2Format};
+use config::lora_config::{rx_timeouts, Channel, LoraConfig};
+use defmt::{error, info, debug, warn, Display2Format, Format};
 use embassy_time::{Duration, Instant, Timer};
 use embedded_hal_async::delay::DelayNs;
 use heapless::{FnvIndexMap, Vec};
-use lora_phy::mod_params::RadioError;
+use lora_phy::mod_params::{PacketParams, PacketStatus, RadioError};
 use lora_phy::mod_traits::RadioKind;
 use lora_phy::{LoRa, RxMode};
 
-use crate::device::collections::MessageQueue;
+use crate::device::collections::{snapshot, CollectionError, MessageQueue};
 use crate::device::config::device_config::DeviceConfig;
+use crate::device::config::effective_config::EffectiveConfig;
+use crate::device::config::mesh_config::{AckMode, Handling, MeshConfig};
+use crate::device::dedup::DuplicateFilter;
 use crate::device::device_error::DeviceError;
+use crate::device::event::{DeviceEvent, EventQueue, RouteRemoval};
+use crate::device::forward::ForwardFilter;
+use crate::device::history::{TxHistory, TxOutcome};
+use crate::device::metrics::DeviceMetrics;
 use crate::device::pending_ack::*;
+use crate::device::rate_limit::{Pacing, RateLimiter};
+use crate::device::receipt::AwaitingReceipts;
+use crate::device::reorder::{Reorderer, Sequencer};
+use crate::device::repeat::{BroadcastRepeater, Repeat};
+use crate::device::reserved::ReservedUids;
+use crate::device::rng::{RngSource, XorShiftRng};
+use crate::device::schedule::Periodic;
+use crate::device::throttle::TokenBucket;
+use crate::device::trace::{Subsystem, TraceLevels, Verbosity};
 use crate::message::payload::ack::AckType;
+use crate::message::payload::command::CommandType;
+use crate::message::destination::Destination;
 use crate::message::payload::route::RouteType;
 use crate::message::payload::Payload::{self, Ack, Discovery};
-use crate::message::Message;
-use crate::message::payload::data::DataType;
+use crate::message::error::MessageError;
+use crate::message::{received_frame, Message, MessageId, MAX_TTL, MAX_WIRE_SIZE};
+use crate::message::payload::data::{DataType, TextDecoding};
+use crate::route::ring_search::RingSearches;
 use crate::route::routing_table::RoutingTable;
-use crate::route::Route;
+use crate::route::store::RouteStore;
+use crate::route::{Route, RoutePolicy, MAX_QUALITY, ROUTE_TTL};
 
 pub mod collections;
 pub mod config;
+pub mod dedup;
 pub mod device_error;
+pub mod event;
+pub mod forward;
+pub mod history;
+pub mod metrics;
 pub mod pending_ack;
+pub mod rate_limit;
+pub mod receipt;
+pub mod reorder;
+pub mod repeat;
+pub mod reserved;
+pub mod rng;
+pub mod schedule;
+pub mod throttle;
+pub mod trace;
 
 pub static mut DEVICE_CONFIG: OnceCell<Option<DeviceConfig>> = OnceCell::new();
 
@@ -36,26 +73,99 @@ const INQUEUE_SIZE: usize = 32;
 const OUTQUEUE_SIZE: usize = 32;
 const MAX_INQUEUE_PROCESS: usize = 5;
 const MAX_OUTQUEUE_TRANSMIT: usize = 5;
+const MAX_GROUPS: usize = 8;
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+const PING_TIMEOUT: Duration = Duration::from_secs(10);
 
 pub type Uid = NonZeroU8;
+/// Default incoming queue, which can be built in a `static`:
+///
+/// ```no_run
+/// use core::ptr::addr_of_mut;
+/// use quadranet::device::{InQueue, OutQueue};
+///
+/// static mut INQUEUE: InQueue = InQueue::new();
+/// static mut OUTQUEUE: OutQueue = OutQueue::new();
+///
+/// // Taken once, before handing them to `LoraDevice::new`
+/// let inqueue: &'static mut InQueue = unsafe { &mut *addr_of_mut!(INQUEUE) };
+/// let outqueue: &'static mut OutQueue = unsafe { &mut *addr_of_mut!(OUTQUEUE) };
+/// ```
 pub type InQueue = Vec<Message, INQUEUE_SIZE>;
+/// Default outgoing queue, see `InQueue`.
 pub type OutQueue = Vec<Message, OUTQUEUE_SIZE>;
 
-pub struct LoraDevice<RK, DLY, IN, OUT>
+/// Direct link to a neighbor, as measured by `LoraDevice::probe`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct LinkQuality {
+    /// Signal strength of the response, in dBm
+    pub rssi: i16,
+    /// Signal to noise ratio of the response, in dB
+    pub snr: i16,
+    /// Time between sending the probe and receiving the response
+    pub round_trip: Duration,
+}
+
+/// Pong addressed to this device, with the signal of the frame that carried it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Pong {
+    message_id: MessageId,
+    received_at: Instant,
+    rssi: i16,
+    snr: i16,
+}
+
+pub struct LoraDevice<RK, DLY, IN, OUT, RNG = XorShiftRng>
 where
     RK: RadioKind,
     DLY: DelayNs,
     IN: MessageQueue + 'static,
     OUT: MessageQueue + 'static,
+    RNG: RngSource,
 {
     uid: Uid,
     lora_config: LoraConfig,
+    mesh_config: MeshConfig,
     radio: LoRa<RK, DLY>,
     state: DeviceState,
     inqueue: &'static mut IN,
     outqueue: &'static mut OUT,
-    pending_acks: FnvIndexMap<u32, PendingAck, MAX_PENDING_ACKS>,
+    app_channel: Option<&'static mut dyn MessageQueue>,
+    reorderer: Reorderer,
+    sequencer: Sequencer,
+    repeater: BroadcastRepeater,
+    duplicates: DuplicateFilter,
+    relayed: DuplicateFilter,
+    pending_acks: FnvIndexMap<MessageId, PendingAck, MAX_PENDING_ACKS>,
+    awaiting_receipt: AwaitingReceipts,
     routing_table: RoutingTable,
+    last_cleanup: Periodic,
+    last_discovery: Instant,
+    started_at: Instant,
+    boot_discoveries: u8,
+    route_store: Option<&'static mut dyn RouteStore>,
+    last_route_save: Instant,
+    ring_searches: RingSearches,
+    groups: Vec<u8, MAX_GROUPS>,
+    relaying: bool,
+    promiscuous: bool,
+    forward_filter: Option<&'static mut dyn ForwardFilter>,
+    queued_relays: usize,
+    rate_limiter: RateLimiter,
+    tx_budget: TokenBucket,
+    relay_budget: TokenBucket,
+    metrics: DeviceMetrics,
+    events: EventQueue,
+    tx_history: TxHistory,
+    trace: TraceLevels,
+    last_pong: Option<Pong>,
+    last_rx_signal: (i16, i16),
+    last_tx: Option<(Uid, MessageId, Instant)>,
+    last_heard: Option<(Uid, Instant)>,
+    awaiting_answer: Option<Instant>,
+    rx_channel: Channel,
+    rng: RNG,
+    buffer: [u8; MAX_WIRE_SIZE],
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -76,15 +186,54 @@ pub enum DeviceState {
 /// - `DLY`: Delay trait for asynchronous operations.
 /// - `IN`: Message queue for incoming messages.
 /// - `OUT`: Message queue for outgoing messages.
+/// - `RNG`: Source of randomness, a xorshift seeded from the UID by default.
 ///
 /// # Fields
 /// - `uid`: Unique identifier of the device.
 /// - `lora_config`: Configuration settings for the LoRa radio.
+/// - `mesh_config`: Runtime tunables of the mesh layer.
 /// - `radio`: The LoRa radio instance.
 /// - `state`: Current state of the device (Idle, Transmitting, Receiving).
 /// - `inqueue`: Queue for incoming messages.
 /// - `outqueue`: Queue for outgoing messages.
+/// - `app_channel`: Optional queue receiving the processed inqueue messages.
+/// - `reorderer`: Messages held back to be delivered in order, when enabled.
+/// - `sequencer`: Next sequence number of the messages sent to each destination.
+/// - `repeater`: Broadcasts waiting for their next repetition.
+/// - `duplicates`: Recently heard broadcasts, to drop their copies.
+/// - `relayed`: Messages recently relayed by this device, to drop their echoes.
+/// - `awaiting_receipt`: Source and TTL of delivered messages awaiting an application receipt.
 /// - `routing_table`: Table for managing routes to other devices.
+/// - `last_cleanup`: Schedule of the routing table cleanup.
+/// - `last_discovery`: Last time a periodic discovery was sent.
+/// - `started_at`: When the mesh loop started, after the startup delay.
+/// - `boot_discoveries`: Discoveries of the boot burst sent so far.
+/// - `route_store`: Optional persistent storage for the routing table.
+/// - `last_route_save`: Last time the routing table was saved to the route store.
+/// - `ring_searches`: Expanding-ring route discoveries in progress.
+/// - `groups`: Multicast groups the device is subscribed to.
+/// - `relaying`: Whether messages of other nodes are forwarded.
+/// - `promiscuous`: Whether every decoded frame is captured instead of handled.
+/// - `forward_filter`: Optional application hook vetoing relays.
+/// - `queued_relays`: Flooded relays currently waiting in the outqueue.
+/// - `rate_limiter`: Per-destination send intervals and the messages deferred by them.
+/// - `tx_budget`: Token bucket enforcing `MeshConfig::max_throughput`.
+/// - `relay_budget`: Token bucket enforcing `MeshConfig::relay_budget`.
+/// - `metrics`: Forwarding and drop counters.
+/// - `events`: Notifications waiting to be polled by the application.
+/// - `tx_history`: Summaries of the last transmitted frames, for diagnostics.
+/// - `trace`: Log verbosity of every subsystem.
+/// - `last_pong`: ID, reception time and signal of the last pong addressed to us.
+/// - `last_rx_signal`: RSSI and SNR of the last decoded frame.
+/// - `last_tx`: Source, ID and time of the last transmitted frame, to ignore its echoes.
+/// - `last_heard`: Transmitter and time of the last unicast frame handled, answered on the
+///   control channel.
+/// - `awaiting_answer`: When the last TX awaiting an answer on the control channel ended.
+/// - `rx_channel`: Channel of the current listen window, the control one while awaiting an
+///   answer.
+/// - `rng`: Source of randomness for jitter and backoff.
+/// - `buffer`: Scratch buffer shared by TX and RX, reserving `MAX_WIRE_SIZE` bytes
+///   inside the device instead of on the stack of every radio operation.
 impl<RK, DLY, IN, OUT> LoraDevice<RK, DLY, IN, OUT>
 where
     RK: RadioKind,
@@ -99,6 +248,50 @@ where
         device_config: DeviceConfig,
         inqueue: &'static mut IN,
         outqueue: &'static mut OUT,
+    ) -> Self {
+        Self::new_with_rng(
+            uid,
+            radio,
+            lora_config,
+            device_config,
+            inqueue,
+            outqueue,
+            XorShiftRng::from_uid(uid),
+        )
+    }
+
+    /// Creates a device, refusing a UID that belongs to the `reserved` range.
+    pub fn new_checked(
+        uid: Uid,
+        radio: LoRa<RK, DLY>,
+        lora_config: LoraConfig,
+        device_config: DeviceConfig,
+        inqueue: &'static mut IN,
+        outqueue: &'static mut OUT,
+        reserved: ReservedUids,
+    ) -> Result<Self, DeviceError> {
+        reserved.check(uid)?;
+        Ok(Self::new(uid, radio, lora_config, device_config, inqueue, outqueue))
+    }
+}
+
+impl<RK, DLY, IN, OUT, RNG> LoraDevice<RK, DLY, IN, OUT, RNG>
+where
+    RK: RadioKind,
+    DLY: DelayNs,
+    IN: MessageQueue + 'static,
+    OUT: MessageQueue + 'static,
+    RNG: RngSource,
+{
+    /// Creates a device drawing all of its randomness from `rng`.
+    pub fn new_with_rng(
+        uid: Uid,
+        radio: LoRa<RK, DLY>,
+        lora_config: LoraConfig,
+        device_config: DeviceConfig,
+        inqueue: &'static mut IN,
+        outqueue: &'static mut OUT,
+        rng: RNG,
     ) -> Self {
         unsafe {
             DEVICE_CONFIG = OnceCell::from(Some(device_config));
@@ -108,10 +301,45 @@ where
             radio,
             state: DeviceState::Idle,
             lora_config,
+            mesh_config: MeshConfig::default(),
             inqueue,
             outqueue,
+            app_channel: None,
+            reorderer: Reorderer::default(),
+            sequencer: Sequencer::new(),
+            repeater: BroadcastRepeater::default(),
+            duplicates: DuplicateFilter::new(),
+            relayed: DuplicateFilter::new(),
             pending_acks: FnvIndexMap::new(),
+            awaiting_receipt: AwaitingReceipts::default(),
             routing_table: RoutingTable::default(),
+            last_cleanup: Periodic::new(),
+            last_discovery: Instant::MIN,
+            started_at: Instant::MIN,
+            boot_discoveries: 0,
+            route_store: None,
+            last_route_save: Instant::MIN,
+            ring_searches: RingSearches::default(),
+            groups: Vec::new(),
+            relaying: true,
+            promiscuous: false,
+            forward_filter: None,
+            queued_relays: 0,
+            rate_limiter: RateLimiter::default(),
+            tx_budget: TokenBucket::new(),
+            relay_budget: TokenBucket::new(),
+            metrics: DeviceMetrics::default(),
+            events: EventQueue::new(),
+            tx_history: TxHistory::new(),
+            trace: TraceLevels::new(),
+            last_pong: None,
+            last_rx_signal: (0, 0),
+            last_tx: None,
+            last_heard: None,
+            awaiting_answer: None,
+            rx_channel: Channel::Data,
+            rng,
+            buffer: [0; MAX_WIRE_SIZE],
         }
     }
 
@@ -119,91 +347,565 @@ where
         self.uid
     }
 
+    pub fn mesh_config(&self) -> &MeshConfig {
+        &self.mesh_config
+    }
+
+    pub fn set_mesh_config(&mut self, mesh_config: MeshConfig) {
+        self.mesh_config = mesh_config;
+    }
+
+    /// Every setting currently in effect, including those changed at runtime.
+    pub fn effective_config(&self) -> EffectiveConfig {
+        EffectiveConfig {
+            uid: self.uid.get(),
+            device: unsafe { DEVICE_CONFIG.get().copied().flatten().unwrap_or_default() },
+            radio: (&self.lora_config).into(),
+            mesh: (&self.mesh_config).into(),
+            relaying: self.relaying,
+            promiscuous: self.promiscuous,
+            short_message_id: cfg!(feature = "short-message-id"),
+        }
+    }
+
+    pub fn metrics(&self) -> &DeviceMetrics {
+        &self.metrics
+    }
+
+    /// Returns the counters accumulated since the previous call and zeroes them, for
+    /// reporting per-interval rates. See [`DeviceMetrics::take`].
+    pub fn take_metrics(&mut self) -> DeviceMetrics {
+        self.metrics.take()
+    }
+
+    /// Subscribes the device to a multicast group, so that messages addressed to it are
+    /// delivered locally and relayed.
+    pub fn subscribe(&mut self, group: u8) -> Result<(), DeviceError> {
+        if self.is_subscribed(group) {
+            return Ok(());
+        }
+        self.groups.push(group).map_err(|_| DeviceError::GroupLimitReached)
+    }
+
+    pub fn unsubscribe(&mut self, group: u8) {
+        self.groups.retain(|&g| g != group);
+    }
+
+    pub fn is_subscribed(&self, group: u8) -> bool {
+        self.groups.contains(&group)
+    }
+
+    /// Copies of the messages waiting in the inqueue, oldest first.
+    ///
+    /// The inqueue itself is left untouched, so this can be called at any time.
+    pub fn inqueue_snapshot(&self) -> Vec<Message, INQUEUE_SIZE> {
+        snapshot(&*self.inqueue)
+    }
+
+    /// Hands every message processed from the inqueue over to `channel`.
+    pub fn set_app_channel(&mut self, channel: &'static mut dyn MessageQueue) {
+        self.app_channel = Some(channel);
+    }
+
+    pub fn app_channel(&mut self) -> Option<&mut (dyn MessageQueue + 'static)> {
+        self.app_channel.as_deref_mut()
+    }
+
+    /// Queues a message originating from the application for transmission.
+    ///
+    /// With `MeshConfig::route_ttl_margin` set, the TTL of unicast messages is derived from
+    /// the route to their destination.
+    pub fn queue_outgoing(&mut self, mut message: Message) -> Result<(), DeviceError> {
+        if let (Some(margin), Destination::Unicast(destination)) =
+            (self.mesh_config.route_ttl_margin, message.destination())
+        {
+            message.set_ttl(self.routing_table.ttl_to(destination.get(), margin));
+        }
+        if !self.mesh_config.admits_own_message(self.outqueue.remaining()) {
+            return Err(DeviceError::QueueError {
+                error: CollectionError::Full,
+            });
+        }
+        self.sequencer.assign(&mut message);
+        self.push_outgoing(message)?;
+        Ok(())
+    }
+
+    /// Queues a broadcast or group message to be sent `repeat.count` more times, every
+    /// `repeat.interval`, for broadcasts that every neighbor should hear.
+    ///
+    /// Receivers drop the repetitions they already heard.
+    pub fn queue_repeated(&mut self, message: Message, repeat: Repeat) -> Result<(), DeviceError> {
+        if message.destination_id().is_some() {
+            return Err(DeviceError::NotABroadcast);
+        }
+        let id = message.message_id();
+        self.repeater
+            .push(message.clone(), repeat, Instant::now())
+            .map_err(|_| DeviceError::RepeatLimitReached)?;
+        if let Err(e) = self.queue_outgoing(message) {
+            // Repetitions must not go out without the first transmission
+            self.repeater.cancel(id);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Installs a route known by the application, such as one from provisioning, without
+    /// waiting for a discovery.
+    ///
+    /// The next hop must be the destination itself or a known neighbor. The route then
+    /// ages and gets replaced like any discovered route.
+    pub fn add_route(
+        &mut self,
+        destination: Uid,
+        next_hop: Uid,
+        hop_count: u8,
+        quality: u8,
+    ) -> Result<(), DeviceError> {
+        if next_hop != destination && !self.routing_table.is_neighbor(next_hop) {
+            return Err(DeviceError::UnknownNextHop);
+        }
+        let evicted = self.routing_table.update(
+            destination.get(),
+            Route {
+                next_hop,
+                hop_count,
+                quality: quality.min(MAX_QUALITY),
+                last_seen: Instant::now(),
+            },
+        );
+        self.route_evicted(evicted);
+        Ok(())
+    }
+
+    /// Direct neighbors the device currently has a route to.
+    pub fn neighbors(&self) -> impl Iterator<Item = Uid> + '_ {
+        self.routing_table.neighbors()
+    }
+
+    /// Attaches persistent route storage, restoring the routes it holds.
+    pub fn set_route_store(&mut self, store: &'static mut dyn RouteStore) {
+        if let Some(snapshot) = store.load() {
+            self.routing_table.import(&snapshot, Instant::now());
+        }
+        self.last_route_save = Instant::now();
+        self.route_store = Some(store);
+    }
+
+    /// Recently transmitted frames and their acknowledgement outcome.
+    pub fn tx_history(&self) -> &TxHistory {
+        &self.tx_history
+    }
+
+    /// Tunes the logs of a subsystem at runtime, within the global defmt level.
+    pub fn set_trace_level(&mut self, subsystem: Subsystem, verbosity: Verbosity) {
+        self.trace.set(subsystem, verbosity);
+    }
+
+    pub fn trace_level(&self, subsystem: Subsystem) -> Verbosity {
+        self.trace.level(subsystem)
+    }
+
+    /// Returns the oldest event raised by the device, if any.
+    pub fn poll_event(&mut self) -> Option<DeviceEvent> {
+        self.events.pop()
+    }
+
+    /// Draws the delay to wait before the first discovery.
+    pub fn startup_delay(&mut self) -> Duration {
+        self.rng.jitter(self.mesh_config.max_startup_jitter)
+    }
+
+    pub fn rng(&mut self) -> &mut RNG {
+        &mut self.rng
+    }
+
+    /// Turns relaying of other nodes' messages on or off, taking effect immediately.
+    ///
+    /// A device that does not relay still delivers messages addressed to it.
+    pub fn set_relaying(&mut self, relaying: bool) {
+        self.relaying = relaying;
+    }
+
+    pub fn is_relaying(&self) -> bool {
+        self.relaying
+    }
+
+    /// Turns the device into a passive monitor, or back into a mesh node.
+    ///
+    /// In promiscuous mode every frame that decodes is delivered to the inqueue whatever its
+    /// destination and carrying its signal in `Message::rx_signal`, and the device neither
+    /// relays, acks, answers discoveries nor learns routes.
+    pub fn set_promiscuous(&mut self, promiscuous: bool) {
+        self.promiscuous = promiscuous;
+    }
+
+    pub fn is_promiscuous(&self) -> bool {
+        self.promiscuous
+    }
+
+    /// Consults `filter` before relaying any message of another node, awaiting its decision.
+    pub fn set_forward_filter(&mut self, filter: &'static mut dyn ForwardFilter) {
+        self.forward_filter = Some(filter);
+    }
+
+    /// Sends messages to `destination` at most once every `min_interval`.
+    ///
+    /// Messages sent too early are deferred rather than dropped, as long as there is room.
+    pub fn set_rate_limit(&mut self, destination: Uid, min_interval: Duration) -> Result<(), DeviceError> {
+        self.rate_limiter.set_limit(destination, min_interval)
+    }
+
+    pub fn clear_rate_limit(&mut self, destination: Uid) {
+        self.rate_limiter.clear_limit(destination);
+    }
+
     pub fn update_state(&self) {
         unsafe {
             DEVICE_STATE = self.state;
         }
     }
 
-    pub async fn enqueue_message(&mut self, message: Message) {
-        if let Some(receiver) = message.destination_id() {
-            if receiver.get() == self.uid.get() {
-                if let Err(e) = self.inqueue.enqueue(message) {
-                    error!("Error enqueueing message: {:?}", e);
+    pub async fn enqueue_message(&mut self, mut message: Message) {
+        message.mark_arrival(Instant::now());
+        let destination = message.destination();
+        match destination {
+            Destination::Unicast(receiver) if receiver != self.uid => {
+                if message.is_expired() {
+                    self.metrics.messages_dropped_expired += 1;
+                } else if let Some(message) = self.admit_forward(message).await {
+                    let source = message.source_id();
+                    match self.route_message(message).await {
+                        Ok(()) => self.spend_relay_budget(source),
+                        Err(e) => error!("Error routing message: {:?}", e),
+                    }
+                }
+            }
+            _ => {
+                let subscribed = match destination {
+                    Destination::Group(group) => self.is_subscribed(group),
+                    _ => false,
+                };
+                let config = &self.mesh_config;
+                let handling =
+                    local_handling(&message, self.uid, subscribed, config, &mut self.metrics);
+                if handling == Handling::IGNORE {
+                    return;
+                }
+                let relay = if handling.relay {
+                    self.admit_forward(message.clone()).await
+                } else {
+                    None
+                };
+                if let Some(relay) = relay {
+                    let max_queued = self.mesh_config.max_queued_relays;
+                    if admits_relay(self.queued_relays, max_queued, &mut self.metrics) {
+                        let relayed = relay.clone();
+                        if enqueue_relay(self.outqueue, &mut self.metrics, relay) {
+                            // A relay dropped for lack of room sends no echo to ignore
+                            self.relayed.remember(&relayed);
+                            self.spend_relay_budget(relayed.source_id());
+                            self.queued_relays += 1;
+                            self.metrics.broadcasts_relayed += 1;
+                        }
+                    }
                 }
-            } else if !message.is_expired() {
-                if let Err(e) = self.route_message(message).await {
-                    error!("Error routing message: {:?}", e);
+                if handling.deliver {
+                    self.deliver(message);
                 }
             }
-        } else if !message.is_expired() {
-            self.outqueue.enqueue(message.clone()).unwrap();
-            if let Err(e) = self.inqueue.enqueue(message) {
-                error!("Error enqueueing message: {:?}", e);
+        }
+    }
+
+    /// Applies the local-only flag, the relaying switch, the forward filter and the relay
+    /// budget to a message about to be sent on.
+    ///
+    /// Messages originating from this device are always admitted.
+    async fn admit_forward(&mut self, message: Message) -> Option<Message> {
+        if self.is_stale(&message) {
+            self.metrics.messages_dropped_stale += 1;
+            return None;
+        }
+        if message.source_id() == self.uid {
+            return Some(message);
+        }
+        let filter = self.forward_filter.as_deref_mut();
+        let forwarded = admit_relay(message, self.relaying, filter, &mut self.metrics).await?;
+        if let Some(budget) = self.mesh_config.relay_budget {
+            // The token is only spent once the relay is queued or transmitted
+            if !self.relay_budget.is_available(Instant::now(), &budget) {
+                self.metrics.relays_over_budget += 1;
+                return None;
+            }
+        }
+        Some(forwarded)
+    }
+
+    /// Spends a token of the relay budget on a message of `source` just queued or
+    /// transmitted, messages of this device being exempt.
+    fn spend_relay_budget(&mut self, source: Uid) {
+        if let (Some(budget), true) = (self.mesh_config.relay_budget, source != self.uid) {
+            self.relay_budget.take(Instant::now(), &budget);
+        }
+    }
+
+    /// Hands a message over to the inqueue, once its predecessors are when ordering is on.
+    fn deliver(&mut self, message: Message) {
+        if let Payload::Command(CommandType::Ping) = message.payload() {
+            // Pings are answered by the mesh layer and never reach the application
+            let ack = AckType::Pong { message_id: message.message_id() };
+            self.reply(message.source_id(), message.ttl(), ack);
+            return;
+        }
+        if self.mesh_config.reorder_timeout.is_none() {
+            self.hand_over(message);
+            return;
+        }
+        if let Some(message) = self.reorderer.push(message, Instant::now()) {
+            self.hand_over(message);
+        }
+        self.flush_reordered();
+    }
+
+    /// Puts a message in the inqueue, then acknowledges it.
+    ///
+    /// The transport ack, the receipt tracking and a requested configuration change only
+    /// happen once the message is in the inqueue. A sender is never told a message was
+    /// delivered when it was dropped for lack of room or is still held back for ordering,
+    /// and a configuration is never changed without its sender being answered.
+    fn hand_over(&mut self, message: Message) {
+        let (id, source, ttl) = (message.message_id(), message.source_id(), message.ttl());
+        let ack_mode = self.mesh_config.ack_mode;
+        let Some(owed) = hand_over_to(self.inqueue, &mut self.metrics, message, ack_mode) else {
+            return;
+        };
+        if owed.receipt {
+            self.awaiting_receipt.insert(id, source, ttl, Instant::now());
+        }
+        if let Some(requested) = owed.config {
+            let config = self.apply_config(&requested);
+            self.reply(source, ttl, AckType::ConfigApplied { message_id: id, config });
+        } else if owed.ack {
+            self.ack_success(id, source, ttl);
+        }
+    }
+
+    /// Hands over the messages held back for ordering that are now in order or whose gap
+    /// timed out.
+    fn flush_reordered(&mut self) {
+        if let Some(timeout) = self.mesh_config.reorder_timeout {
+            let now = Instant::now();
+            while let Some(message) = self.reorderer.pop(now, timeout) {
+                self.hand_over(message);
+            }
+        }
+    }
+
+    /// Applies a configuration requested remotely, returning the one actually in effect.
+    fn apply_config(&mut self, requested: &DeviceConfig) -> DeviceConfig {
+        unsafe {
+            let current = DEVICE_CONFIG.get().copied().flatten().unwrap_or_default();
+            let applied = current.apply(requested);
+            DEVICE_CONFIG = OnceCell::from(Some(applied));
+            applied
+        }
+    }
+
+    fn reply(&mut self, destination: Uid, ttl: u8, ack: AckType) {
+        let res = self.push_outgoing(Message::new_ack(
+            self.uid,
+            Destination::Unicast(destination),
+            ack,
+            ttl,
+            false,
+        ));
+
+        if let Err(e) = res {
+            error!("Error enqueueing reply message: {:?}", e);
+        }
+    }
+
+    /// Checks whether `neighbor` is directly reachable and measures the link to it.
+    ///
+    /// Sends a ping and listens until the matching pong arrives, reporting its signal and
+    /// the round-trip time, or `DeviceError::Timeout` when the neighbor did not answer
+    /// within `PING_TIMEOUT`. The routing table is left untouched.
+    ///
+    /// Only direct neighbors can be measured. Relays forward unicast messages under their
+    /// own UID and message ID, so a node further away could not answer the original ping:
+    /// the ping is sent local-only and such a node times out.
+    ///
+    /// The device receives on its own while waiting, so this is meant for commissioning and
+    /// diagnostics rather than to be called while `run_quadranet` is running.
+    pub async fn probe(&mut self, neighbor: Uid) -> Result<LinkQuality, DeviceError> {
+        let ping = ping_message(self.uid, neighbor);
+        let id = ping.message_id();
+        let start = Instant::now();
+        self.last_pong = None;
+        self.send_message(ping).await?;
+
+        loop {
+            if let Some(outcome) = probe_outcome(self.last_pong, id, start, Instant::now()) {
+                return outcome;
             }
+            self.try_wait_message().await;
         }
     }
 
+    /// Stops trying to deliver a message sent earlier, such as a command made obsolete by
+    /// a newer one.
+    ///
+    /// Returns whether the message was still pending or queued.
+    pub fn cancel(&mut self, message_id: MessageId) -> bool {
+        let pending = self.pending_acks.remove(&message_id).is_some();
+        // Relayed messages of other nodes may carry the same ID, they are left alone
+        let queued = self.outqueue.remove(self.uid, message_id)
+            + self.rate_limiter.remove(self.uid, message_id);
+        self.queued_relays = count_relays(self.outqueue, self.uid);
+        pending || queued > 0
+    }
+
+    /// Sends an application receipt for a message the application has finished processing.
+    ///
+    /// The transport ack is sent automatically on reception; this second-level ack tells the
+    /// sender that the message was actually consumed from the inqueue. Messages not
+    /// confirmed within `RECEIPT_TIMEOUT` can no longer be.
+    pub fn confirm_processed(&mut self, message_id: MessageId) -> Result<(), DeviceError> {
+        let (source, ttl) = self
+            .awaiting_receipt
+            .remove(message_id, Instant::now())
+            .ok_or(DeviceError::UnknownMessage)?;
+        self.push_outgoing(Message::new_ack(
+            self.uid,
+            Destination::Unicast(source),
+            AckType::AppReceipt { message_id },
+            ttl,
+            false,
+        ))?;
+        Ok(())
+    }
+
+    /// Forwards a unicast message of another node towards its destination.
+    ///
+    /// Without a route, the message is dropped and, unless
+    /// `MeshConfig::relay_known_only` is set, its destination is discovered.
     async fn route_message(&mut self, mut message: Message) -> Result<(), DeviceError> {
-        if let Ack(AckType::AckDiscovered {
-            hops: _hops,
-            last_hop,
-        }) = *message.payload()
-        {
-            let payload = AckType::AckDiscovered {
-                hops: 0,
-                last_hop: self.uid,
-            };
-            message = Message::new_ack(
-                self.uid,
-                Some(last_hop),
-                payload,
-                message.ttl(),
-                message.req_ack(),
-            );
+        if let Ack(AckType::AckDiscovered { last_hop, .. }) = *message.payload() {
+            // The response ends here, so this relay acks it in place of its destination,
+            // or its source would retry it until giving up
+            if message.req_ack() && self.mesh_config.ack_mode.acknowledges() {
+                self.ack_success(message.message_id(), message.source_id(), message.ttl());
+            }
+            message = hop_discovery_ack(self.uid, last_hop, message.ttl());
         }
 
-        if let Some(route) = self
-            .routing_table
-            .lookup_route(message.destination_id().unwrap().get())
-        {
-            message = Message::new(
-                self.uid,
-                Some(route.next_hop),
-                message.payload().clone(),
-                message.ttl(),
-                message.req_ack(),
-            );
-            message.decrement_ttl();
+        let destination = message.destination_id().unwrap();
+        let forwarding =
+            forwarding(&self.routing_table, destination, Instant::now(), &self.mesh_config);
+        if let Forwarding::Via(next_hop) = forwarding {
+            message = forwarded(&message, self.uid, next_hop);
+            if self.trace.enabled(Subsystem::Routing, Verbosity::Debug) {
+                debug!("Forwarding to {} via {}", destination, next_hop);
+            }
+            self.relayed.remember(&message);
             self.tx_message(message).await?;
+            self.metrics.messages_forwarded += 1;
         } else {
+            self.metrics.messages_dropped_no_route += 1;
+            if forwarding == (Forwarding::Drop { discover: true }) {
+                self.initiate_route_discovery(destination).await;
+            }
             return Err(DeviceError::RouteNotFound);
         }
         Ok(())
     }
 
+    /// Hands up to `MAX_INQUEUE_PROCESS` messages of the inqueue over to the application
+    /// channel.
+    ///
+    /// Messages were already processed by the mesh layer when they were received, so they
+    /// are not processed again here.
     pub async fn process_inqueue(&mut self) -> Result<(), RadioError> {
-        let to_process = cmp::min(self.inqueue.len(), MAX_INQUEUE_PROCESS);
-        // Not happy with this
-        for _ in 0..to_process {
-            let message: Message = self.inqueue.dequeue().unwrap(); // Handle this unwrap appropriately
-            self.process_message(&message).await;
-        }
+        drain_inqueue(
+            self.inqueue,
+            self.app_channel.as_deref_mut(),
+            &mut self.metrics,
+            MAX_INQUEUE_PROCESS,
+        );
         Ok(())
     }
 
     pub async fn process_outqueue(&mut self) -> Result<(), RadioError> {
-        let to_transmit = cmp::min(self.outqueue.len(), MAX_OUTQUEUE_TRANSMIT);
-        for _ in 0..to_transmit {
-            let message: Message = self.outqueue.dequeue().expect("Outqueue is empty");
+        for _ in 0..MAX_OUTQUEUE_TRANSMIT {
+            let Some(message) = self.next_outgoing() else {
+                break;
+            };
             self.send_message(message).await?;
         }
         Ok(())
     }
 
+    /// Transmits the queued outgoing messages, returning how many were sent.
+    ///
+    /// Stops after `OUTQUEUE_SIZE` messages or `FLUSH_TIMEOUT`, so messages enqueued
+    /// while flushing cannot keep it going forever, or once `MeshConfig::max_throughput`
+    /// is exhausted.
+    pub async fn flush_outqueue(&mut self) -> Result<usize, DeviceError> {
+        let deadline = Instant::now() + FLUSH_TIMEOUT;
+        let mut sent = 0;
+        while flush_goes_on(sent, Instant::now(), deadline) {
+            let Some(message) = self.next_outgoing() else {
+                break;
+            };
+            self.send_message(message).await?;
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Dequeues the next message to transmit, see `Outbox::next`.
+    fn next_outgoing(&mut self) -> Option<Message> {
+        let mut outbox = Outbox {
+            outqueue: &mut *self.outqueue,
+            inqueue: &mut *self.inqueue,
+            rate_limiter: &mut self.rate_limiter,
+            tx_budget: &mut self.tx_budget,
+            queued_relays: &mut self.queued_relays,
+            metrics: &mut self.metrics,
+        };
+        outbox.next(self.uid, &self.mesh_config, Instant::now())
+    }
+
+    /// Queues a message for transmission, stamping when it entered the outqueue.
+    fn push_outgoing(&mut self, mut message: Message) -> Result<(), CollectionError> {
+        message.mark_arrival(Instant::now());
+        self.outqueue.enqueue(message)
+    }
+
+    /// Queues the broadcast repetitions that are due.
+    fn queue_repetitions(&mut self) {
+        while let Some(message) = self.repeater.next_due(Instant::now()) {
+            if let Err(e) = self.push_outgoing(message) {
+                error!("Error enqueueing repeated broadcast: {:?}", e);
+            }
+        }
+    }
+
+    fn is_stale(&self, message: &Message) -> bool {
+        self.mesh_config
+            .max_message_age
+            .is_some_and(|max_age| message.is_stale(Instant::now(), max_age))
+    }
+
     pub async fn process_message(&mut self, message: &Message) {
+        if !self.mesh_config.processes(message.payload()) {
+            return;
+        }
+        let trace_rx = self.trace.enabled(Subsystem::Radio, Verbosity::Debug);
+        let trace_acks = self.trace.enabled(Subsystem::Acks, Verbosity::Info);
         match message.payload() {
-            Payload::Data(data) => {
+            Payload::Data(data) if trace_rx => {
                 match data {
                     DataType::Text(text) => {
                         debug!("Received text message: {}", text);
@@ -212,39 +914,92 @@ where
                         debug!("Received data: {:?}", defmt::Debug2Format(data));
                     }
                 }
-                if message.req_ack() {
-                    self.ack_success(&message);
+            }
+            Payload::Data(_) => {}
+            Payload::App { type_id, bytes } => {
+                if trace_rx {
+                    debug!("Received app payload {} ({} bytes)", type_id, bytes.as_bytes().len());
                 }
             }
             Payload::Command(command) => {
-                debug!("Received command: {:?}", defmt::Debug2Format(command));
-                if message.req_ack() {
-                    self.ack_success(&message);
+                if trace_rx {
+                    debug!("Received command: {:?}", defmt::Debug2Format(command));
                 }
             }
             Ack(ack) => match ack {
-                AckType::Success { .. } => {}
+                AckType::Success { message_id } => {
+                    if message.destination_id() == Some(self.uid) {
+                        acknowledge(&mut self.pending_acks, &mut self.routing_table, *message_id);
+                        self.tx_history.set_outcome(*message_id, TxOutcome::Acknowledged);
+                    }
+                }
+                AckType::SuccessWithRoute { message_id, .. } => {
+                    if message.destination_id() == Some(self.uid) {
+                        acknowledge(&mut self.pending_acks, &mut self.routing_table, *message_id);
+                        self.tx_history.set_outcome(*message_id, TxOutcome::Acknowledged);
+                        let evicted = learn_route_hint(
+                            ack,
+                            message.ttl(),
+                            &mut self.routing_table,
+                            &mut self.ring_searches,
+                            Instant::now(),
+                            &self.mesh_config.route_policy,
+                        );
+                        self.route_evicted(evicted);
+                    }
+                }
                 AckType::AckDiscovered { hops, last_hop } => {
                     // Always update the routing table
-                    self.routing_table.update(
+                    self.ring_searches.resolve(message.source_id().get());
+                    let evicted = self.routing_table.update_if_better(
                         message.source_id().get(),
                         Route {
                             next_hop: *last_hop,
                             hop_count: *hops,
+                            quality: self.mesh_config.route_policy.initial_quality,
+                            last_seen: Instant::now(),
                         },
+                        &self.mesh_config.route_policy,
                     );
+                    self.route_evicted(evicted);
 
                     // Only update pending_acks if we originated the discovery
                     if message.source_id() == self.uid {
                         if let Some(pending_ack) = self.pending_acks.get_mut(&message.message_id()) {
-                            info!("ACK Complete for Message {}", message.message_id());
+                            if trace_acks {
+                                info!("ACK Complete for Message {}", message.message_id());
+                            }
                             pending_ack.is_acknowledged = true;
+                            self.tx_history
+                                .set_outcome(message.message_id(), TxOutcome::Acknowledged);
                         } else {
                             warn!("Received unexpected AckDiscovered for our message ID: {}", message.message_id());
                         }
                     }
                 }
                 AckType::Failure { .. } => {}
+                AckType::AppReceipt { message_id } => {
+                    if trace_acks {
+                        info!("Application receipt for message {}", message_id);
+                    }
+                }
+                AckType::ConfigApplied { message_id, config } => {
+                    if trace_acks {
+                        info!("Configuration applied for message {}: {}", message_id, config);
+                    }
+                }
+                AckType::Pong { message_id } => {
+                    if message.destination_id() == Some(self.uid) {
+                        // Decoded from the frame just received, whose signal is the link's
+                        let (rssi, snr) = self.last_rx_signal;
+                        self.last_pong = Some(Pong {
+                            message_id: *message_id,
+                            received_at: Instant::now(),
+                            rssi,
+                            snr,
+                        });
+                    }
+                }
             },
             Payload::Route(route) => match route {
                 RouteType::Request => {}
@@ -253,15 +1008,15 @@ where
             },
             Discovery(discovery) => {
                 let hops = discovery.original_ttl - message.ttl();
-                let res = self.outqueue.enqueue(Message::new_ack(
+                let res = self.push_outgoing(Message::new_ack(
                     self.uid,
-                    Some(message.source_id()),
+                    Destination::Unicast(message.source_id()),
                     AckType::AckDiscovered {
                         hops,
                         last_hop: self.uid,
                     },
                     message.ttl(),
-                    false,
+                    self.mesh_config.reliable_discovery_acks,
                 ));
 
                 if let Err(e) = res {
@@ -271,14 +1026,13 @@ where
         }
     }
 
-    fn ack_success(&mut self, message: &Message) {
-        let res = self.outqueue.enqueue(Message::new_ack(
+    fn ack_success(&mut self, message_id: MessageId, source: Uid, ttl: u8) {
+        let ack = success_ack(message_id, self.uid, ttl, self.mesh_config.ack_route_hints);
+        let res = self.push_outgoing(Message::new_ack(
             self.uid,
-            Some(message.source_id()),
-            AckType::Success {
-                message_id: message.message_id(),
-            },
-            message.ttl(),
+            Destination::Unicast(source),
+            ack,
+            ttl,
             false,
         ));
 
@@ -287,164 +1041,2398 @@ where
         }
     }
 
-    async fn send_message(&mut self, message: Message) -> Result<(), RadioError> {
-        if message.req_ack() {
-            let pending_ack = PendingAck::new(
-                message.payload().clone(),
-                message.destination_id(),
-                message.ttl(),
-            );
-            if self.pending_acks.contains_key(&message.message_id()) {
-            } else {
-                self.pending_acks
-                    .insert(message.message_id(), pending_ack)
-                    .unwrap_or_else(|_| {
-                        error!("Error inserting pending ack");
-                        None
-                    });
+    async fn send_message(&mut self, mut message: Message) -> Result<(), RadioError> {
+        track_ack(&mut message, self.uid, self.mesh_config.ack_mode, &mut self.pending_acks);
+        self.tx_message(message).await?;
+        Ok(())
+    }
+
+    pub async fn discover_nodes(&mut self) {
+        self.enqueue_discovery(3);
+    }
 
+    /// Looks for a route to `destination` with an expanding-ring search, starting with
+    /// direct neighbors and widening up to `MeshConfig::max_ring_ttl` hops.
+    pub async fn initiate_route_discovery(&mut self, destination: Uid) {
+        if !self.mesh_config.allows_discovery(self.discoveries_in_flight()) {
+            if self.trace.enabled(Subsystem::Routing, Verbosity::Info) {
+                info!("Too many discoveries in flight, not discovering {}", destination);
             }
+            return;
+        }
+        if let Some(ttl) = self.ring_searches.start(destination.get(), Instant::now()) {
+            self.enqueue_discovery(ttl);
         }
+    }
 
-        self.tx_message(message).await?;
-        Ok(())
+    /// Discoveries of this device waiting in the outqueue or for their ack.
+    fn discoveries_in_flight(&self) -> usize {
+        discoveries_in_flight(self.outqueue, &self.pending_acks, self.uid)
     }
 
-    pub async fn discover_nodes(&mut self) {
-        let res = self.outqueue.enqueue(Message::new_discovery(
-            self.uid,
-            None,
-            3,
-            true
-        ));
+    /// Widens the route discoveries that are still unanswered.
+    pub async fn advance_route_discoveries(&mut self) {
+        let max_ttl = self.mesh_config.max_ring_ttl;
+        if let Some(ttl) = self.ring_searches.advance(Instant::now(), max_ttl) {
+            self.enqueue_discovery(ttl);
+        }
+    }
 
-        if let Err(e) = res {
-            error!("Error enqueueing discovery message: {:?}", e);
+    /// Queues a discovery flood, unless `MeshConfig::max_discoveries_in_flight` of them
+    /// already are in flight, whichever path it comes from.
+    fn enqueue_discovery(&mut self, ttl: u8) {
+        let mut discovery = Message::new_discovery(self.uid, Destination::Broadcast, ttl, true);
+        discovery.mark_arrival(Instant::now());
+        let config = &self.mesh_config;
+        match queue_discovery(self.outqueue, &self.pending_acks, self.uid, config, discovery) {
+            Ok(true) => {}
+            Ok(false) => {
+                if self.trace.enabled(Subsystem::Routing, Verbosity::Info) {
+                    info!("Too many discoveries in flight, not flooding {} hops", ttl);
+                }
+            }
+            Err(e) => error!("Error enqueueing discovery message: {:?}", e),
         }
     }
 
     async fn tx_message(&mut self, message: Message) -> Result<(), RadioError> {
-        let buffer: [u8; 70] = message.into();
+        self.buffer.fill(0);
+        let size = match encode_frame(&message, &mut self.buffer, &mut self.metrics) {
+            Ok(size) => size,
+            Err(e) => {
+                // Sending an empty frame would still record the message as transmitted
+                error!("Dropping message {} that failed to encode: {:?}", message.message_id(), e);
+                return Ok(());
+            }
+        };
+        let channel = self.lora_config.send_channel(&message, self.last_heard, Instant::now());
         let params = &mut self.lora_config.tx_pkt_params;
-        
+        let modulation = match (channel, &self.lora_config.control) {
+            (Channel::Control, Some(control)) => &control.modulation,
+            _ => &self.lora_config.modulation,
+        };
+
         self.radio
             .prepare_for_tx(
-                &self.lora_config.modulation,
+                modulation,
                 params,
                 self.lora_config.tx_power,
-                &buffer,
+                &self.buffer[..size],
             )
             .await?;
 
         self.state = DeviceState::Transmitting;
         Timer::after(Duration::from_millis(100)).await;
-        debug!("Sending message: {:?}", buffer);
+        if self.trace.enabled(Subsystem::Radio, Verbosity::Debug) {
+            debug!("Sending message: {:?}", &self.buffer[..size]);
+        }
         self.radio
             .tx()
             .await?;
+        let now = Instant::now();
+        self.tx_history.record(&message, size, now);
+        self.last_tx = Some((message.source_id(), message.message_id(), now));
+        if Channel::awaits_answer(&message) {
+            self.awaiting_answer = Some(now);
+        }
         self.state = DeviceState::Idle;
         Ok(())
     }
 
-    async fn try_wait_message(&mut self, buf: &mut [u8]) {
+    async fn try_wait_message(&mut self) {
         self.state = DeviceState::Receiving;
-        self.radio
+        let (channel, window) =
+            self.lora_config.listen_window(Instant::now(), self.awaiting_answer);
+        self.rx_channel = channel;
+        let (radio_timeout, host_timeout) = rx_timeouts(window);
+        let prepared = self
+            .radio
             .prepare_for_rx(
-                RxMode::Single(10000),
-                &self.lora_config.modulation,
+                RxMode::Single(radio_timeout),
+                self.lora_config.channel_modulation(self.rx_channel),
                 &self.lora_config.rx_pkt_params,
             )
-            .await
-            .expect("Failed to prepare for RX");
+            .await;
+        if let Err(e) = prepared {
+            // Skip this cycle, the next one prepares the radio again
+            record_rx_error(&mut self.metrics, &e);
+            error!("Error preparing for RX: {:?}", e);
+            self.state = DeviceState::Idle;
+            return;
+        }
 
         Timer::after(Duration::from_millis(50)).await;
-        match self.radio.rx(&self.lora_config.rx_pkt_params, buf).await {
-            Ok((size, _status)) => {
-                match Message::try_from(&mut buf[..size as usize]) {
+        let mut rx = LoraRx {
+            radio: &mut self.radio,
+            params: &self.lora_config.rx_pkt_params,
+            buffer: &mut self.buffer,
+        };
+        let received = receive_until(&mut rx, Timer::after(host_timeout)).await;
+        match received {
+            Some(Ok((size, status))) => {
+                let Some(frame) = received_frame(&mut self.buffer, size as usize) else {
+                    self.metrics.oversized_frames_dropped += 1;
+                    warn!("Dropping oversized frame of {} bytes", size);
+                    self.state = DeviceState::Idle;
+                    return;
+                };
+                let decoded =
+                    decode_frame(frame, self.mesh_config.text_decoding, &mut self.metrics);
+                if decoded.is_ok() {
+                    self.last_rx_signal = (status.rssi, status.snr);
+                }
+                match decoded {
+                    Ok(message) if self.promiscuous => {
+                        capture(self.inqueue, &mut self.metrics, message, self.last_rx_signal);
+                    }
                     Ok(message) => {
-                        self.process_message(&message).await;
-                        self.enqueue_message(message).await;
+                        let screening = screen(
+                            &message,
+                            self.last_tx,
+                            &self.relayed,
+                            &mut self.duplicates,
+                            Instant::now(),
+                            self.mesh_config.echo_window,
+                        );
+                        match screening {
+                            Screening::Handle => {
+                                if self.rx_channel == Channel::Control {
+                                    // The answer arrived, back to the data channel
+                                    self.awaiting_answer = None;
+                                }
+                                if message.destination_id().is_some() {
+                                    // Routed unicast frames carry the UID of their last
+                                    // transmitter
+                                    self.last_heard = Some((message.source_id(), Instant::now()));
+                                }
+                                self.process_message(&message).await;
+                                self.enqueue_message(message).await;
+                            }
+                            Screening::TxEcho => self.metrics.tx_echoes_dropped += 1,
+                            // A neighbor repeating what we just relayed, already handled
+                            Screening::RelayEcho => self.metrics.relay_echoes_dropped += 1,
+                            Screening::Duplicate => self.metrics.broadcasts_duplicate += 1,
+                        }
                     }
                     Err(e) => {
                         warn!("Received invalid message:{}", Display2Format(&e));
+                        if self.mesh_config.decode_failure_events {
+                            self.events.push(DeviceEvent::DecodeFailed {
+                                error: e,
+                                rssi: status.rssi,
+                                snr: status.snr,
+                            });
+                        }
                     }
                 }
             }
-            Err(RadioError::ReceiveTimeout) => {
+            Some(Err(RadioError::ReceiveTimeout)) => {
                 // Do nothing
             }
-            Err(e) => {
+            Some(Err(e)) => {
+                record_rx_error(&mut self.metrics, &e);
                 error!("Error receiving message: {:?}", e);
             }
+            // The radio missed its own timeout and was put back in standby
+            None => {}
         }
         self.state = DeviceState::Idle;
     }
 
-    pub async fn check_pending_acks(&mut self) {
-        let now = Instant::now();
-        for (id, ack) in self.pending_acks.iter_mut() {
-            if now.duration_since(ack.timestamp) > Duration::from_secs(ACK_WAIT_TIME) {
-                if ack.attempts < MAX_ACK_ATTEMPTS {
-                    let mut message = Message::new(
-                        self.uid,
-                        ack.destination_uid(),
-                        ack.payload().clone(),
-                        ack.ttl(),
-                        true,
-                    );
-                    message.set_message_id(*id);
-                    self.outqueue.enqueue(message).unwrap_or_else(|e| {
-                        error!("Error enqueueing message: {:?}", e);
+    /// Removes routes that have not been refreshed, notifying lost direct neighbors, and
+    /// forgets the receipts the application did not confirm in time.
+    pub fn cleanup(&mut self) {
+        self.awaiting_receipt.remove_expired(Instant::now());
+        let events = &mut self.events;
+        let route_events = self.mesh_config.route_events;
+        self.routing_table
+            .remove_expired(Instant::now(), ROUTE_TTL, |destination, route| {
+                if route_events {
+                    events.push(DeviceEvent::RouteRemoved {
+                        destination,
+                        reason: RouteRemoval::Expired,
                     });
-                    ack.timestamp = Instant::now();
-                    ack.attempts += 1;
-                    debug!("Attempt {} for message: {}", ack.attempts, id);
-                } else {
-                    warn!("Max attempts reached for message: {}", id);
-                    ack.is_acknowledged = true;
                 }
-            }
+                if route.is_direct(destination) {
+                    if let Some(uid) = Uid::new(destination) {
+                        warn!("Neighbor {} is down", destination);
+                        events.push(DeviceEvent::NeighborDown { uid });
+                    }
+                }
+            });
+    }
+
+    fn route_evicted(&mut self, evicted: Option<u8>) {
+        if let (Some(destination), true) = (evicted, self.mesh_config.route_events) {
+            self.events.push(DeviceEvent::RouteRemoved {
+                destination,
+                reason: RouteRemoval::Evicted,
+            });
         }
-        self.pending_acks.retain(|_id, ack| !ack.is_acknowledged);
     }
-}
 
-pub async fn run_quadranet<RK, DLY, IN, OUT>(
-    mut device: LoraDevice<RK, DLY, IN, OUT>,
-    buf: &mut [u8],
-) where
-    RK: RadioKind,
-    DLY: DelayNs,
-    IN: MessageQueue + 'static,
-    OUT: MessageQueue + 'static,
-{
-    device.discover_nodes().await;
-    loop {
+    /// Time left before the next cleanup of stale routes.
+    pub fn until_cleanup(&self) -> Duration {
+        self.last_cleanup
+            .remaining(Instant::now(), self.mesh_config.cleanup_interval)
+    }
+
+    /// Time left before the next periodic discovery, or `None` when discovery only runs on
+    /// route misses. Discoveries of a boot burst are not accounted for.
+    pub fn until_discovery(&self) -> Option<Duration> {
+        self.mesh_config
+            .discovery_strategy
+            .remaining(self.last_discovery.elapsed())
+    }
+
+    /// Time left before the routing table may be saved to the route store again.
+    pub fn until_route_save(&self) -> Duration {
+        (self.last_route_save + self.mesh_config.route_save_interval)
+            .saturating_duration_since(Instant::now())
+    }
+
+    /// Saves the routing table to the route store, if any, once the save interval elapsed.
+    pub fn save_routes(&mut self) {
+        if self.last_route_save.elapsed() < self.mesh_config.route_save_interval {
+            return;
+        }
+        if let Some(store) = self.route_store.as_deref_mut() {
+            store.save(&self.routing_table.export());
+            self.last_route_save = Instant::now();
+        }
+    }
+
+    /// Waits for the startup jitter, then runs the initial discovery.
+    pub async fn start(&mut self) {
+        Timer::after(self.startup_delay()).await;
+        self.last_discovery = Instant::now();
+        self.started_at = self.last_discovery;
+        // A boot burst sends its first discovery on the first poll
+        if self.mesh_config.boot_discovery_burst.is_none()
+            && self.mesh_config.discovery_strategy.interval().is_some()
+        {
+            self.discover_nodes().await;
+        }
+    }
+
+    /// Runs one iteration of the mesh loop.
+    pub async fn poll(&mut self) {
         // Wait for a message
-        device.try_wait_message(buf).await;
+        self.try_wait_message().await;
+        self.flush_reordered();
+        self.queue_repetitions();
 
         // Process InQueue
-        if !device.inqueue.len() == INQUEUE_SIZE - 1{
-            if let Err(e) = device.process_inqueue().await {
+        if !self.inqueue.is_empty() {
+            if let Err(e) = self.process_inqueue().await {
                 error!("Error processing inqueue: {:?}", e);
             }
         }
 
         // Process OutQueue
-        if !device.outqueue.is_empty() {
-            if let Err(e) = device.process_outqueue().await {
+        if !self.outqueue.is_empty() || self.rate_limiter.has_deferred() {
+            if let Err(e) = self.process_outqueue().await {
                 error!("Error processing outqueue: {:?}", e);
             }
         }
 
         // Check for pending acks
-        device.check_pending_acks().await;
+        self.check_pending_acks().await;
+
+        // Drop stale routes
+        if self
+            .last_cleanup
+            .poll(Instant::now(), self.mesh_config.cleanup_interval)
+        {
+            self.cleanup();
+        }
+        self.save_routes();
+
+        // Widen unanswered route discoveries
+        self.advance_route_discoveries().await;
+
+        // Startup burst, the periodic cadence resumes from its last discovery
+        if let Some(burst) = self.mesh_config.boot_discovery_burst {
+            if burst.is_due(self.boot_discoveries, self.started_at.elapsed()) {
+                self.discover_nodes().await;
+                self.boot_discoveries += 1;
+                self.last_discovery = Instant::now();
+            }
+        }
+
+        // Periodic discovery
+        if self
+            .mesh_config
+            .discovery_strategy
+            .is_due(self.last_discovery.elapsed())
+        {
+            self.discover_nodes().await;
+            self.last_discovery = Instant::now();
+        }
 
         // Add a delay or yield the task to prevent it from hogging the CPU
         Timer::after(Duration::from_millis(10)).await;
     }
+
+    pub async fn check_pending_acks(&mut self) {
+        let now = Instant::now();
+        let mut flood = None;
+        for (id, ack) in self.pending_acks.iter_mut() {
+            let unreachable = fails_early(
+                ack,
+                self.mesh_config.fail_unreachable_after,
+                &self.routing_table,
+                &self.ring_searches,
+                now,
+                &self.mesh_config.route_policy,
+            );
+            if unreachable {
+                warn!("Giving up on message {} to an unreachable destination", id);
+                ack.is_acknowledged = true;
+                self.tx_history.set_outcome(*id, TxOutcome::Failed);
+                continue;
+            }
+            if now.duration_since(ack.timestamp) > Duration::from_secs(ACK_WAIT_TIME) {
+                ack_timed_out(ack, &mut self.routing_table);
+                if ack.attempts < MAX_ACK_ATTEMPTS {
+                    let mut message = Message::new(
+                        self.uid,
+                        ack.destination(),
+                        ack.payload().clone(),
+                        match self.mesh_config.retry_ttl_step {
+                            Some(step) => retry_ttl(ack.ttl(), ack.attempts, step),
+                            None => ack.ttl(),
+                        },
+                        true,
+                    );
+                    message.set_message_id(*id);
+                    message.mark_arrival(now);
+                    // Retries go out before newer traffic so they keep to their schedule
+                    self.outqueue.enqueue_front(message).unwrap_or_else(|e| {
+                        error!("Error enqueueing message: {:?}", e);
+                    });
+                    ack.timestamp = Instant::now();
+                    ack.attempts += 1;
+                    if self.trace.enabled(Subsystem::Acks, Verbosity::Debug) {
+                        debug!("Attempt {} for message: {}", ack.attempts, id);
+                    }
+                } else {
+                    warn!("Max attempts reached for message: {}", id);
+                    ack.is_acknowledged = true;
+                    self.tx_history.set_outcome(*id, TxOutcome::Failed);
+                    if let (true, Some(destination)) =
+                        (self.mesh_config.rediscover_on_failure, ack.destination().uid())
+                    {
+                        let ttl = rediscover(
+                            &mut self.routing_table,
+                            &mut self.ring_searches,
+                            self.mesh_config.route_events.then_some(&mut self.events),
+                            destination.get(),
+                            now,
+                        );
+                        flood = flood.max(ttl);
+                    }
+                }
+            }
+        }
+        self.pending_acks.retain(|_id, ack| !ack.is_acknowledged);
+        if let Some(ttl) = flood {
+            self.enqueue_discovery(ttl);
+        }
+    }
+}
+
+/// Outgoing side of a device, borrowed to pick the next message to transmit.
+struct Outbox<'a, IN, OUT> {
+    outqueue: &'a mut OUT,
+    inqueue: &'a mut IN,
+    rate_limiter: &'a mut RateLimiter,
+    tx_budget: &'a mut TokenBucket,
+    queued_relays: &'a mut usize,
+    metrics: &'a mut DeviceMetrics,
+}
+
+impl<IN, OUT> Outbox<'_, IN, OUT>
+where
+    IN: MessageQueue,
+    OUT: MessageQueue,
+{
+    /// Dequeues the next message the device `uid` transmits at `now`, dropping the stale
+    /// ones.
+    ///
+    /// Deferred messages whose destination became due go first; messages to a destination
+    /// that is not due yet are deferred, and messages addressed to this device are looped
+    /// back to the inqueue instead of being transmitted.
+    fn next(&mut self, uid: Uid, config: &MeshConfig, now: Instant) -> Option<Message> {
+        let throughput = config.max_throughput;
+        if throughput.is_some_and(|throughput| !self.tx_budget.is_available(now, &throughput)) {
+            // Messages wait in the outqueue until the budget allows sending them
+            return None;
+        }
+        loop {
+            let message = match self.rate_limiter.next_ready(now) {
+                Some(message) => message,
+                None => {
+                    let message = self.outqueue.dequeue().ok()?;
+                    // Flooded on behalf of another node, routed messages are sent on under
+                    // the uid of this device
+                    if message.source_id() != uid {
+                        *self.queued_relays = self.queued_relays.saturating_sub(1);
+                    }
+                    if is_loop_back(&message, uid) {
+                        loop_back(self.inqueue, self.metrics, message);
+                        continue;
+                    }
+                    match self.rate_limiter.pace(message, now) {
+                        Pacing::Send(message) => message,
+                        Pacing::Deferred => continue,
+                        Pacing::Dropped => {
+                            self.metrics.messages_dropped_rate_limit += 1;
+                            continue;
+                        }
+                    }
+                }
+            };
+            let stale = config
+                .max_message_age
+                .is_some_and(|max_age| message.is_stale(now, max_age));
+            if !stale {
+                if let Some(arrived_at) = message.arrived_at() {
+                    self.metrics
+                        .record_outqueue_residence(now.saturating_duration_since(arrived_at));
+                }
+                self.rate_limiter.record(&message, now);
+                if let Some(throughput) = throughput {
+                    self.tx_budget.take(now, &throughput);
+                }
+                return Some(message);
+            }
+            self.metrics.messages_dropped_stale += 1;
+        }
+    }
+}
+
+/// Acknowledgements owed to the sender of a message once it is in the inqueue.
+#[derive(Debug, PartialEq, Eq)]
+struct Owed {
+    /// Transport ack
+    ack: bool,
+    /// Application receipt, once the application confirms it processed the message
+    receipt: bool,
+    /// Configuration requested by the message, answered with the one applied in place of
+    /// the transport ack
+    config: Option<DeviceConfig>,
+}
+
+/// Enqueues a message for the application, returning what its sender is owed, or `None`
+/// when the inqueue is full and the sender must not be told it was delivered.
+fn hand_over_to<IN>(
+    inqueue: &mut IN,
+    metrics: &mut DeviceMetrics,
+    message: Message,
+    ack_mode: AckMode,
+) -> Option<Owed>
+where
+    IN: MessageQueue,
+{
+    let requested = message.req_ack() && ack_mode.acknowledges();
+    let owed = Owed {
+        ack: requested
+            && matches!(
+                message.payload(),
+                Payload::Data(_)
+                    | Payload::App { .. }
+                    | Payload::Command(_)
+                    | Ack(AckType::AckDiscovered { .. })
+            ),
+        receipt: requested && message.destination_id().is_some(),
+        config: match message.payload() {
+            Payload::Command(CommandType::SetConfig(config)) => Some(*config),
+            _ => None,
+        },
+    };
+    enqueue_delivered(inqueue, metrics, message).then_some(owed)
+}
+
+/// Handling of a received message that is not routed on, once its TTL is accounted for.
+///
+/// A message whose TTL ran out is never relayed, and is dropped and counted unless it is
+/// addressed to this device: a routed message spends its last hop on the way here.
+fn arrival_handling(
+    message: &Message,
+    uid: Uid,
+    handling: Handling,
+    metrics: &mut DeviceMetrics,
+) -> Handling {
+    if !message.is_expired() || handling == Handling::IGNORE {
+        return handling;
+    }
+    let deliver = handling.deliver && message.destination() == Destination::Unicast(uid);
+    if handling.relay || !deliver {
+        metrics.messages_dropped_expired += 1;
+    }
+    Handling { deliver, relay: false }
+}
+
+/// Handling of a received message that is not routed on, following the delivery policy
+/// and the TTL left.
+///
+/// Messages whose payload kind is not in `MeshConfig::local_payloads` are only relayed,
+/// never delivered.
+fn local_handling(
+    message: &Message,
+    uid: Uid,
+    subscribed: bool,
+    config: &MeshConfig,
+    metrics: &mut DeviceMetrics,
+) -> Handling {
+    let handling = config.delivery_policy.handling(message.destination(), subscribed);
+    let handling = arrival_handling(message, uid, handling, metrics);
+    Handling {
+        deliver: handling.deliver && config.processes(message.payload()),
+        relay: handling.relay,
+    }
+}
+
+/// Whether a queued message was addressed by the device to itself, relays of messages to
+/// its address being sent on like any other.
+fn is_loop_back(message: &Message, uid: Uid) -> bool {
+    message.destination_id() == Some(uid) && message.source_id() == uid
+}
+
+/// Delivers a message the device addressed to itself straight to the inqueue.
+fn loop_back<IN>(inqueue: &mut IN, metrics: &mut DeviceMetrics, message: Message)
+where
+    IN: MessageQueue,
+{
+    match inqueue.enqueue(message) {
+        Ok(()) => metrics.messages_looped_back += 1,
+        Err(e) => {
+            metrics.messages_dropped_inqueue_full += 1;
+            error!("Error looping back message: {:?}", e);
+        }
+    }
+}
+
+/// Single receive of a radio prepared for RX.
+trait Receiver {
+    type Received;
+
+    /// Receives a frame, ending when one arrives or the radio timeout elapses.
+    async fn receive(&mut self) -> Result<Self::Received, RadioError>;
+
+    /// Aborts the receive in flight, putting the radio back in standby.
+    async fn abort(&mut self) -> Result<(), RadioError>;
+}
+
+/// Receive of a LoRa radio into the frame buffer of the device.
+struct LoraRx<'a, RK, DLY>
+where
+    RK: RadioKind,
+    DLY: DelayNs,
+{
+    radio: &'a mut LoRa<RK, DLY>,
+    params: &'a PacketParams,
+    buffer: &'a mut [u8],
+}
+
+impl<RK, DLY> Receiver for LoraRx<'_, RK, DLY>
+where
+    RK: RadioKind,
+    DLY: DelayNs,
+{
+    type Received = (u8, PacketStatus);
+
+    async fn receive(&mut self) -> Result<Self::Received, RadioError> {
+        self.radio.rx(self.params, self.buffer).await
+    }
+
+    async fn abort(&mut self) -> Result<(), RadioError> {
+        self.radio.enter_standby().await
+    }
+}
+
+/// Receives with `receiver` until `deadline` completes, the host side bound on a receive.
+///
+/// Returns `None` when the deadline came first, the receive still in flight then being
+/// aborted so the radio is left in standby for the next TX or RX.
+async fn receive_until<R, D>(
+    receiver: &mut R,
+    deadline: D,
+) -> Option<Result<R::Received, RadioError>>
+where
+    R: Receiver,
+    D: Future<Output = ()>,
+{
+    let received = {
+        let mut receive = pin!(receiver.receive());
+        let mut deadline = pin!(deadline);
+        poll_fn(|cx| match receive.as_mut().poll(cx) {
+            Poll::Ready(received) => Poll::Ready(Some(received)),
+            Poll::Pending => deadline.as_mut().poll(cx).map(|()| None),
+        })
+        .await
+    };
+    if received.is_none() {
+        if let Err(e) = receiver.abort().await {
+            error!("Error aborting RX: {:?}", e);
+        }
+    }
+    received
+}
+
+/// Delivers a decoded frame as is in promiscuous mode, whatever its destination and
+/// without handling it, tagged with the RSSI and SNR it was received with.
+fn capture<IN>(
+    inqueue: &mut IN,
+    metrics: &mut DeviceMetrics,
+    mut message: Message,
+    rx_signal: (i16, i16),
+) where
+    IN: MessageQueue,
+{
+    message.set_rx_signal(rx_signal.0, rx_signal.1);
+    if enqueue_delivered(inqueue, metrics, message) {
+        metrics.messages_captured += 1;
+    }
+}
+
+/// Enqueues a message for the application, counting it when the inqueue is full.
+fn enqueue_delivered<IN>(inqueue: &mut IN, metrics: &mut DeviceMetrics, message: Message) -> bool
+where
+    IN: MessageQueue,
+{
+    match inqueue.enqueue(message) {
+        Ok(()) => true,
+        Err(e) => {
+            metrics.messages_dropped_inqueue_full += 1;
+            error!("Error enqueueing message: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Moves up to `max` messages from the inqueue to the application channel, returning how
+/// many were taken. Without a channel, the messages are taken all the same.
+fn drain_inqueue<IN>(
+    inqueue: &mut IN,
+    mut channel: Option<&mut (dyn MessageQueue + 'static)>,
+    metrics: &mut DeviceMetrics,
+    max: usize,
+) -> usize
+where
+    IN: MessageQueue,
+{
+    let mut taken = 0;
+    while taken < max {
+        let Ok(message) = inqueue.dequeue() else {
+            break;
+        };
+        taken += 1;
+        if let Some(channel) = channel.as_deref_mut() {
+            // The message is moved into the channel, not copied
+            if let Err(e) = channel.enqueue(message) {
+                metrics.app_channel_dropped += 1;
+                warn!("Error delivering message to application: {:?}", e);
+            }
+        }
+    }
+    taken
+}
+
+/// Whether another relay fits under `max_queued` with `queued` relays in the outqueue,
+/// counting the relays dropped for being over it.
+fn admits_relay(queued: usize, max_queued: usize, metrics: &mut DeviceMetrics) -> bool {
+    let admitted = queued < max_queued;
+    if !admitted {
+        metrics.broadcasts_dropped_relay_cap += 1;
+    }
+    admitted
+}
+
+/// Enqueues a broadcast relay, counting it when the outqueue is full.
+fn enqueue_relay<OUT>(outqueue: &mut OUT, metrics: &mut DeviceMetrics, relay: Message) -> bool
+where
+    OUT: MessageQueue,
+{
+    match outqueue.enqueue(relay) {
+        Ok(()) => true,
+        Err(e) => {
+            metrics.broadcasts_dropped_outqueue_full += 1;
+            error!("Error enqueueing relay: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Decodes a received frame, counting the frames that are not a valid message.
+fn decode_frame(
+    frame: &mut [u8],
+    text_decoding: TextDecoding,
+    metrics: &mut DeviceMetrics,
+) -> Result<Message, MessageError> {
+    Message::decode(frame, text_decoding).inspect_err(|_| metrics.frames_undecodable += 1)
+}
+
+/// Encodes an outgoing message into `buffer`, counting the messages that do not encode.
+fn encode_frame(
+    message: &Message,
+    buffer: &mut [u8],
+    metrics: &mut DeviceMetrics,
+) -> Result<usize, MessageError> {
+    message.encode_into(buffer).inspect_err(|_| metrics.messages_unencodable += 1)
+}
+
+/// Counts a failed reception, telling CRC failures apart from other radio errors.
+fn record_rx_error(metrics: &mut DeviceMetrics, error: &RadioError) {
+    match error {
+        RadioError::CRCErrorOnReceive => metrics.rx_crc_errors += 1,
+        _ => metrics.rx_errors += 1,
+    }
+}
+
+/// Registers the pending ack of an outgoing message of this device requesting one. Acks
+/// go to the source of a message, so relays never wait for them.
+///
+/// When acks are not waited for, the request is cleared from the messages of this device
+/// instead, so their receivers do not send acks nobody expects.
+fn track_ack(
+    message: &mut Message,
+    uid: Uid,
+    ack_mode: AckMode,
+    pending_acks: &mut FnvIndexMap<MessageId, PendingAck, MAX_PENDING_ACKS>,
+) {
+    if !message.req_ack() || message.source_id() != uid {
+        return;
+    }
+    if !ack_mode.retries() {
+        message.set_req_ack(false);
+        return;
+    }
+    if !pending_acks.contains_key(&message.message_id()) {
+        let pending_ack =
+            PendingAck::new(message.payload().clone(), message.destination(), message.ttl());
+        pending_acks
+            .insert(message.message_id(), pending_ack)
+            .unwrap_or_else(|_| {
+                error!("Error inserting pending ack");
+                None
+            });
+    }
+}
+
+/// Ack of the message `message_id` received by `acker` with `ttl` left, carrying a route
+/// hint when `route_hints` is set.
+fn success_ack(message_id: MessageId, acker: Uid, ttl: u8, route_hints: bool) -> AckType {
+    if route_hints {
+        AckType::SuccessWithRoute {
+            message_id,
+            acker,
+            original_ttl: ttl.min(MAX_TTL),
+            last_hop: acker,
+        }
+    } else {
+        AckType::Success { message_id }
+    }
+}
+
+/// Discovery response a relay sends back to `last_hop` in place of the one it received,
+/// telling it that the relay is its direct neighbor.
+///
+/// It requests no ack: relays send it once and never retry it.
+fn hop_discovery_ack(uid: Uid, last_hop: Uid, ttl: u8) -> Message {
+    let payload = AckType::AckDiscovered {
+        hops: 0,
+        last_hop: uid,
+    };
+    Message::new_ack(uid, Destination::Unicast(last_hop), payload, ttl, false)
+}
+
+/// Marks the message of `message_id` awaiting its ack as acknowledged, so it is no longer
+/// retried, crediting the route to its destination with the delivery.
+///
+/// Returns whether such a message was awaiting its ack.
+fn acknowledge(
+    pending_acks: &mut FnvIndexMap<MessageId, PendingAck, MAX_PENDING_ACKS>,
+    routing_table: &mut RoutingTable,
+    message_id: MessageId,
+) -> bool {
+    match pending_acks.get_mut(&message_id) {
+        Some(pending_ack) => {
+            if let (false, Some(destination)) =
+                (pending_ack.is_acknowledged, pending_ack.destination().uid())
+            {
+                routing_table.record_delivery(destination.get(), true);
+            }
+            pending_ack.is_acknowledged = true;
+            true
+        }
+        None => false,
+    }
+}
+
+/// Counts a message that went unacknowledged for `ACK_WAIT_TIME` against the route to its
+/// destination.
+fn ack_timed_out(pending_ack: &PendingAck, routing_table: &mut RoutingTable) {
+    if let Some(destination) = pending_ack.destination().uid() {
+        routing_table.record_delivery(destination.get(), false);
+    }
+}
+
+/// Ping to the direct neighbor `destination`, which relays do not forward.
+fn ping_message(source: Uid, destination: Uid) -> Message {
+    let unicast = Destination::Unicast(destination);
+    let mut ping = Message::new_command(source, unicast, CommandType::Ping, 1, false);
+    ping.set_local_only(true);
+    ping
+}
+
+/// What becomes of a unicast message of another node to relay.
+#[derive(Debug, PartialEq, Eq)]
+enum Forwarding {
+    /// Sent on through this next hop
+    Via(Uid),
+    /// Dropped for lack of a route, discovering one for the next messages if `discover`
+    Drop { discover: bool },
+}
+
+/// Forwarding of a unicast message of another node to `destination` at `now`.
+fn forwarding(
+    routing_table: &RoutingTable,
+    destination: Uid,
+    now: Instant,
+    config: &MeshConfig,
+) -> Forwarding {
+    match routing_table.active_route(destination.get(), now, &config.route_policy) {
+        Some(route) => Forwarding::Via(route.next_hop),
+        None => Forwarding::Drop {
+            discover: config.discovers_for_relays(),
+        },
+    }
+}
+
+/// Copy of a unicast message of another node that `uid` sends on to `next_hop`, one hop
+/// closer to its destination.
+///
+/// The sequence number and the local-only flag travel with it, the destination relying on
+/// them as much as on the payload. A route hint names `uid` as its last hop, so the sender
+/// learns its route back through the last relay.
+fn forwarded(message: &Message, uid: Uid, next_hop: Uid) -> Message {
+    let payload = match message.payload() {
+        Ack(ack) => Ack(relay_route_hint(*ack, uid)),
+        payload => payload.clone(),
+    };
+    let mut forwarded = Message::new(
+        uid,
+        Destination::Unicast(next_hop),
+        payload,
+        message.ttl(),
+        message.req_ack(),
+    );
+    forwarded.set_sequence(message.sequence());
+    forwarded.set_local_only(message.is_local_only());
+    forwarded.decrement_ttl();
+    forwarded
+}
+
+/// Whether a flush that sent `sent` messages and must end by `deadline` sends another.
+fn flush_goes_on(sent: usize, now: Instant, deadline: Instant) -> bool {
+    sent < OUTQUEUE_SIZE && now < deadline
+}
+
+/// Whether a message of another node must not be relayed at all, being local-only or
+/// relaying being off, counting the refusal.
+fn refuses_relay(message: &Message, relaying: bool, metrics: &mut DeviceMetrics) -> bool {
+    if message.is_local_only() {
+        metrics.relays_local_only += 1;
+        true
+    } else if !relaying {
+        metrics.relays_suppressed += 1;
+        true
+    } else {
+        false
+    }
+}
+
+/// Relay of a message of another node, once the local-only flag, the relaying switch and
+/// the forward filter allow it, counting the refusals.
+async fn admit_relay(
+    message: Message,
+    relaying: bool,
+    filter: Option<&mut dyn ForwardFilter>,
+    metrics: &mut DeviceMetrics,
+) -> Option<Message> {
+    if refuses_relay(&message, relaying, metrics) {
+        return None;
+    }
+    let forwarded = match filter {
+        Some(filter) => filter.filter(&message).await.apply(message),
+        None => Some(message),
+    };
+    if forwarded.is_none() {
+        metrics.relays_filtered += 1;
+    }
+    forwarded
+}
+
+/// Link measured by the pong answering the ping `id` sent at `start`, `None` while it can
+/// still arrive at `now`, or `DeviceError::Timeout` once `PING_TIMEOUT` elapsed without it.
+fn probe_outcome(
+    last_pong: Option<Pong>,
+    id: MessageId,
+    start: Instant,
+    now: Instant,
+) -> Option<Result<LinkQuality, DeviceError>> {
+    match last_pong {
+        Some(pong) if pong.message_id == id => Some(Ok(LinkQuality {
+            rssi: pong.rssi,
+            snr: pong.snr,
+            round_trip: pong.received_at.saturating_duration_since(start),
+        })),
+        _ if now.saturating_duration_since(start) >= PING_TIMEOUT => {
+            Some(Err(DeviceError::Timeout))
+        }
+        _ => None,
+    }
+}
+
+/// Number of discoveries of this device waiting in `outqueue`, relayed ones left out.
+fn queued_discoveries<Q: MessageQueue + ?Sized>(outqueue: &Q, uid: Uid) -> usize {
+    (0..outqueue.len())
+        .filter_map(|index| outqueue.get(index))
+        .filter(|message| message.source_id() == uid && matches!(message.payload(), Discovery(_)))
+        .count()
+}
+
+/// Discoveries of this device waiting in `outqueue` or for their ack.
+fn discoveries_in_flight<Q: MessageQueue + ?Sized>(
+    outqueue: &Q,
+    pending_acks: &FnvIndexMap<MessageId, PendingAck, MAX_PENDING_ACKS>,
+    uid: Uid,
+) -> usize {
+    let pending = pending_acks
+        .values()
+        .filter(|ack| matches!(ack.payload(), Discovery(_)))
+        .count();
+    queued_discoveries(outqueue, uid) + pending
+}
+
+/// Queues a discovery of this device unless `config` caps the discoveries in flight and
+/// the cap is reached, returning whether it was queued.
+fn queue_discovery<OUT>(
+    outqueue: &mut OUT,
+    pending_acks: &FnvIndexMap<MessageId, PendingAck, MAX_PENDING_ACKS>,
+    uid: Uid,
+    config: &MeshConfig,
+    discovery: Message,
+) -> Result<bool, CollectionError>
+where
+    OUT: MessageQueue,
+{
+    if !config.allows_discovery(discoveries_in_flight(outqueue, pending_acks, uid)) {
+        return Ok(false);
+    }
+    outqueue.enqueue(discovery).map(|()| true)
+}
+
+/// Number of flooded relays of other nodes waiting in `outqueue`.
+fn count_relays<Q: MessageQueue + ?Sized>(outqueue: &Q, uid: Uid) -> usize {
+    (0..outqueue.len())
+        .filter_map(|index| outqueue.get(index))
+        .filter(|message| message.source_id() != uid)
+        .count()
+}
+
+/// Fate of a decoded frame, decided before any processing.
+#[derive(Debug, PartialEq, Eq)]
+enum Screening {
+    /// Processed, then delivered or relayed
+    Handle,
+    /// Copy of the last transmitted frame
+    TxEcho,
+    /// Copy of a message this device relayed
+    RelayEcho,
+    /// Broadcast or group message already heard, such as a repetition
+    Duplicate,
+}
+
+/// Screens a decoded frame, so that copies of messages already handled are dropped before
+/// being processed again. Broadcast and group messages are remembered by `duplicates`.
+fn screen(
+    message: &Message,
+    last_tx: Option<(Uid, MessageId, Instant)>,
+    relayed: &DuplicateFilter,
+    duplicates: &mut DuplicateFilter,
+    now: Instant,
+    echo_window: Duration,
+) -> Screening {
+    if is_echo(last_tx, message, now, echo_window) {
+        Screening::TxEcho
+    } else if relayed.contains(message) {
+        Screening::RelayEcho
+    } else if message.destination_id().is_none() && duplicates.is_duplicate(message) {
+        Screening::Duplicate
+    } else {
+        Screening::Handle
+    }
+}
+
+/// Whether `message` is a copy of the last transmitted frame heard within `window` of
+/// its transmission.
+fn is_echo(
+    last_tx: Option<(Uid, MessageId, Instant)>,
+    message: &Message,
+    now: Instant,
+    window: Duration,
+) -> bool {
+    match last_tx {
+        Some((source, id, sent_at)) => {
+            source == message.source_id()
+                && id == message.message_id()
+                && now.duration_since(sent_at) < window
+        }
+        None => false,
+    }
+}
+
+/// Route to the acker of a `SuccessWithRoute` ack sent with `original_ttl` and heard with
+/// `ttl` left, through the last relay.
+fn hinted_route(
+    original_ttl: u8,
+    last_hop: Uid,
+    ttl: u8,
+    now: Instant,
+    policy: &RoutePolicy,
+) -> Route {
+    Route {
+        next_hop: last_hop,
+        hop_count: original_ttl.saturating_sub(ttl),
+        quality: policy.initial_quality,
+        last_seen: now,
+    }
+}
+
+/// A route hint relayed by `relay`, which becomes the last hop towards the acker.
+fn relay_route_hint(ack: AckType, relay: Uid) -> AckType {
+    match ack {
+        AckType::SuccessWithRoute {
+            message_id,
+            acker,
+            original_ttl,
+            ..
+        } => AckType::SuccessWithRoute {
+            message_id,
+            acker,
+            original_ttl,
+            last_hop: relay,
+        },
+        other => other,
+    }
+}
+
+/// Learns the route to the acker of a route hint received with `ttl` left, ending any
+/// ring search for it.
+///
+/// Returns the destination of the route evicted to make room, if any.
+fn learn_route_hint(
+    ack: &AckType,
+    ttl: u8,
+    routing_table: &mut RoutingTable,
+    ring_searches: &mut RingSearches,
+    now: Instant,
+    policy: &RoutePolicy,
+) -> Option<u8> {
+    let AckType::SuccessWithRoute {
+        acker,
+        original_ttl,
+        last_hop,
+        ..
+    } = *ack
+    else {
+        return None;
+    };
+    ring_searches.resolve(acker.get());
+    let route = hinted_route(original_ttl, last_hop, ttl, now, policy);
+    routing_table.update_if_better(acker.get(), route, policy)
+}
+
+/// Whether a pending ack is failed before all its attempts are spent, having been tried
+/// `fail_after` times to a unicast destination that became unreachable.
+fn fails_early(
+    ack: &PendingAck,
+    fail_after: Option<u8>,
+    routing_table: &RoutingTable,
+    ring_searches: &RingSearches,
+    now: Instant,
+    policy: &RoutePolicy,
+) -> bool {
+    match (fail_after, ack.destination()) {
+        (Some(attempts), Destination::Unicast(destination)) => {
+            ack.attempts >= attempts
+                && is_unreachable(routing_table, ring_searches, destination.get(), now, policy)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `destination` has no usable route and no discovery looking for one.
+fn is_unreachable(
+    routing_table: &RoutingTable,
+    ring_searches: &RingSearches,
+    destination: u8,
+    now: Instant,
+    policy: &RoutePolicy,
+) -> bool {
+    routing_table.active_route(destination, now, policy).is_none()
+        && !ring_searches.is_searching(destination)
+}
+
+/// Forgets the route to a destination that stopped acknowledging, reporting its removal
+/// to `events` if given, and starts searching for a new one.
+///
+/// Returns the TTL of the discovery flood to send, or `None` when a search for the
+/// destination is already in flight.
+fn rediscover(
+    routing_table: &mut RoutingTable,
+    ring_searches: &mut RingSearches,
+    events: Option<&mut EventQueue>,
+    destination: u8,
+    now: Instant,
+) -> Option<u8> {
+    if let (Some(_), Some(events)) = (routing_table.remove(destination), events) {
+        events.push(DeviceEvent::RouteRemoved {
+            destination,
+            reason: RouteRemoval::Failed,
+        });
+    }
+    ring_searches.start(destination, now)
+}
+
+pub async fn run_quadranet<RK, DLY, IN, OUT, RNG>(
+    mut device: LoraDevice<RK, DLY, IN, OUT, RNG>,
+) where
+    RK: RadioKind,
+    DLY: DelayNs,
+    IN: MessageQueue + 'static,
+    OUT: MessageQueue + 'static,
+    RNG: RngSource,
+{
+    device.start().await;
+    loop {
+        device.poll().await;
+    }
 }
 
 pub fn device_state() -> DeviceState {
     unsafe { DEVICE_STATE }
 }
+
+#[cfg(test)]
+mod test {
+    use core::future::{pending, ready, Future};
+    use core::pin::pin;
+    use core::task::{Context, Poll, Waker};
+
+    use embassy_time::{Duration, Instant};
+    use heapless::{Deque, FnvIndexMap, Vec};
+    use lora_phy::mod_params::RadioError;
+
+    use crate::device::collections::MessageQueue;
+    use crate::device::config::device_config::{DeviceCapabilities, DeviceClass, DeviceConfig};
+    use crate::device::config::mesh_config::{
+        AckMode, DeliveryPolicy, Handling, MeshConfig, Throughput,
+    };
+    use crate::device::{
+        ack_timed_out, acknowledge, admit_relay, admits_relay, arrival_handling, capture,
+        count_relays, decode_frame, discoveries_in_flight, drain_inqueue, encode_frame,
+        enqueue_delivered, enqueue_relay, fails_early, flush_goes_on, forwarded, forwarding,
+        hand_over_to, hop_discovery_ack, is_echo, is_loop_back, is_unreachable, learn_route_hint,
+        local_handling, loop_back, ping_message, probe_outcome, queue_discovery, queued_discoveries,
+        receive_until, record_rx_error, rediscover, refuses_relay, relay_route_hint, screen,
+        success_ack, track_ack, Forwarding, InQueue, LinkQuality, Outbox, Owed, Pong, Receiver,
+        Screening, FLUSH_TIMEOUT, OUTQUEUE_SIZE, PING_TIMEOUT,
+    };
+    use crate::device::dedup::DuplicateFilter;
+    use crate::device::device_error::DeviceError;
+    use crate::device::event::{DeviceEvent, EventQueue, RouteRemoval};
+    use crate::device::forward::{ForwardDecision, ForwardFilter};
+    use crate::device::metrics::DeviceMetrics;
+    use crate::device::pending_ack::MAX_ACK_ATTEMPTS;
+    use crate::device::rate_limit::RateLimiter;
+    use crate::device::reorder::{Reorderer, Sequencer};
+    use crate::device::throttle::TokenBucket;
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::error::MessageError;
+    use crate::message::payload::ack::AckType;
+    use crate::message::payload::command::CommandType;
+    use crate::message::payload::data::{DataType, TextDecoding};
+    use crate::message::payload::discovery::DiscoveryType;
+    use crate::message::payload::{Payload, PayloadKinds};
+    use crate::message::{Message, MAX_WIRE_SIZE};
+    use crate::route::ring_search::{RingSearches, RING_TIMEOUT};
+    use crate::route::routing_table::RoutingTable;
+    use crate::route::{Route, RoutePolicy, MAX_QUALITY};
+
+    #[test]
+    fn test_undecodable_frames_are_counted() {
+        let mut metrics = DeviceMetrics::default();
+
+        let mut garbage = [0x03, 0xFF, 0xFF, 0x00];
+        assert!(decode_frame(&mut garbage, TextDecoding::Strict, &mut metrics).is_err());
+        let mut truncated = [0x02, 0x01, 0x00];
+        assert_eq!(
+            decode_frame(&mut truncated, TextDecoding::Strict, &mut metrics),
+            Err(MessageError::Truncated)
+        );
+        assert_eq!(metrics.frames_undecodable, 2);
+
+        let message = Message::new_data(
+            Uid::try_from(1).unwrap(),
+            Destination::Broadcast,
+            DataType::new_text("valid"),
+            3,
+            false,
+        );
+        let mut buffer = [0u8; MAX_WIRE_SIZE];
+        let len = message.encode_into(&mut buffer).unwrap();
+        let decoded = decode_frame(&mut buffer[..len], TextDecoding::Strict, &mut metrics);
+        assert_eq!(decoded, Ok(message));
+        assert_eq!(metrics.frames_undecodable, 2);
+    }
+
+    #[test]
+    fn test_unencodable_messages_are_counted_not_sent_empty() {
+        let mut metrics = DeviceMetrics::default();
+        let message = Message::new_data(
+            Uid::try_from(1).unwrap(),
+            Destination::Broadcast,
+            DataType::new_text("too long for the buffer"),
+            3,
+            false,
+        );
+
+        let mut short = [0u8; 8];
+        assert_eq!(
+            encode_frame(&message, &mut short, &mut metrics),
+            Err(MessageError::SerializationError)
+        );
+        assert_eq!(metrics.messages_unencodable, 1);
+
+        let mut buffer = [0u8; MAX_WIRE_SIZE];
+        let len = encode_frame(&message, &mut buffer, &mut metrics).unwrap();
+        assert!(len > 0);
+        assert_eq!(metrics.messages_unencodable, 1);
+    }
+
+    #[test]
+    fn test_failed_destination_is_rediscovered_once() {
+        let mut table = RoutingTable::default();
+        let mut searches = RingSearches::default();
+        table.update(
+            5,
+            Route {
+                next_hop: Uid::try_from(2).unwrap(),
+                hop_count: 1,
+                quality: 100,
+                last_seen: Instant::from_secs(0),
+            },
+        );
+        let now = Instant::from_secs(30);
+
+        let mut events = EventQueue::new();
+
+        assert_eq!(rediscover(&mut table, &mut searches, Some(&mut events), 5, now), Some(1));
+        assert!(table.lookup_route(5).is_none());
+        assert!(searches.is_searching(5));
+        assert_eq!(
+            events.pop(),
+            Some(DeviceEvent::RouteRemoved { destination: 5, reason: RouteRemoval::Failed })
+        );
+        // Another message to the same destination failing does not flood again, and
+        // reports no route it no longer has
+        assert_eq!(rediscover(&mut table, &mut searches, Some(&mut events), 5, now), None);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn test_crc_errors_are_counted_apart() {
+        let mut metrics = DeviceMetrics::default();
+
+        record_rx_error(&mut metrics, &RadioError::CRCErrorOnReceive);
+        record_rx_error(&mut metrics, &RadioError::CRCErrorOnReceive);
+        record_rx_error(&mut metrics, &RadioError::InvalidConfiguration);
+
+        assert_eq!(metrics.rx_crc_errors, 2);
+        assert_eq!(metrics.rx_errors, 1);
+    }
+
+    #[test]
+    fn test_destination_without_route_or_search_is_unreachable() {
+        let mut table = RoutingTable::default();
+        let mut searches = RingSearches::default();
+        let policy = RoutePolicy::default();
+        let now = Instant::from_secs(10);
+        table.update(
+            5,
+            Route {
+                next_hop: Uid::try_from(2).unwrap(),
+                hop_count: 1,
+                quality: 100,
+                last_seen: Instant::from_secs(0),
+            },
+        );
+        searches.start(6, now);
+
+        assert!(!is_unreachable(&table, &searches, 5, now, &policy));
+        assert!(!is_unreachable(&table, &searches, 6, now, &policy));
+        assert!(is_unreachable(&table, &searches, 7, now, &policy));
+        // Once its search gave up, the destination is unreachable too
+        searches.resolve(6);
+        assert!(is_unreachable(&table, &searches, 6, now, &policy));
+    }
+
+    #[test]
+    fn test_self_echo_right_after_transmit_is_ignored() {
+        let uid = Uid::try_from(1).unwrap();
+        let sent = Message::new_data(uid, Destination::Broadcast, DataType::new_text("hi"), 3, false);
+        let sent_at = Instant::from_secs(10);
+        let last_tx = Some((uid, sent.message_id(), sent_at));
+        let window = Duration::from_millis(500);
+
+        let mut echo = sent.clone();
+        echo.decrement_ttl();
+        assert!(is_echo(last_tx, &echo, sent_at + Duration::from_millis(100), window));
+        assert!(!is_echo(last_tx, &echo, sent_at + Duration::from_secs(1), window));
+        assert!(!is_echo(last_tx, &echo, sent_at, Duration::from_secs(0)));
+
+        let other = Message::new_data(uid, Destination::Broadcast, DataType::new_text("hi"), 3, false);
+        assert!(!is_echo(last_tx, &other, sent_at, window));
+        assert!(!is_echo(None, &echo, sent_at, window));
+    }
+
+    #[test]
+    fn test_broadcast_relay_keeps_reserved_outqueue_room() {
+        let uid = Uid::try_from(1).unwrap();
+        let message =
+            || Message::new_data(uid, Destination::Broadcast, DataType::new_text("hi"), 3, false);
+        let mut outqueue: Deque<Message, 4> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+        let config = MeshConfig {
+            reserved_relay_slots: 1,
+            ..MeshConfig::default()
+        };
+
+        // The application fills the outqueue as far as it is allowed to
+        while config.admits_own_message(outqueue.remaining()) {
+            outqueue.enqueue(message()).unwrap();
+        }
+        assert_eq!(outqueue.remaining(), 1);
+        assert!(enqueue_relay(&mut outqueue, &mut metrics, message()));
+
+        // Without reserved room, the relay is dropped and counted instead of panicking
+        assert!(!enqueue_relay(&mut outqueue, &mut metrics, message()));
+        assert_eq!(metrics.broadcasts_dropped_outqueue_full, 1);
+    }
+
+    #[test]
+    fn test_delivered_message_reaches_recv() {
+        let source = Uid::try_from(2).unwrap();
+        let destination = Destination::Unicast(Uid::try_from(1).unwrap());
+        let message = Message::new_data(source, destination, DataType::new_text("hi"), 3, false);
+        let mut inqueue = InQueue::new();
+        let mut channel = InQueue::new();
+        let mut metrics = DeviceMetrics::default();
+
+        assert!(enqueue_delivered(&mut inqueue, &mut metrics, message.clone()));
+        // What `poll` does once per loop with a non-empty inqueue
+        assert_eq!(drain_inqueue(&mut inqueue, Some(&mut channel), &mut metrics, 5), 1);
+
+        // `MeshNode::recv` dequeues the application channel
+        assert_eq!(channel.dequeue().ok(), Some(message));
+        assert!(inqueue.is_empty());
+    }
+
+    #[test]
+    fn test_processed_messages_land_in_app_channel_until_full() {
+        let source = Uid::try_from(2).unwrap();
+        let message = |text| {
+            Message::new_data(source, Destination::Broadcast, DataType::new_text(text), 3, false)
+        };
+        let mut inqueue = InQueue::new();
+        let mut channel: Deque<Message, 2> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+        for text in ["a", "b", "c"] {
+            inqueue.enqueue(message(text)).unwrap();
+        }
+
+        assert_eq!(drain_inqueue(&mut inqueue, Some(&mut channel), &mut metrics, 5), 3);
+
+        assert_eq!(channel.len(), 2);
+        let oldest = channel.dequeue().unwrap();
+        assert_eq!(oldest.payload(), message("a").payload());
+        assert_eq!(metrics.app_channel_dropped, 1);
+    }
+
+    #[test]
+    fn test_inqueue_handoff_is_bounded_per_call() {
+        let source = Uid::try_from(2).unwrap();
+        let mut inqueue = InQueue::new();
+        let mut channel = InQueue::new();
+        let mut metrics = DeviceMetrics::default();
+        for _ in 0..7 {
+            let text = DataType::new_text("x");
+            inqueue.enqueue(Message::new_data(source, Destination::Broadcast, text, 3, false)).unwrap();
+        }
+
+        assert_eq!(drain_inqueue(&mut inqueue, Some(&mut channel), &mut metrics, 5), 5);
+        assert_eq!((inqueue.len(), channel.len()), (2, 5));
+        assert_eq!(metrics.app_channel_dropped, 0);
+    }
+
+    #[test]
+    fn test_route_hints_set_up_routes_both_ways() {
+        let policy = RoutePolicy::default();
+        let now = Instant::from_secs(100);
+        let uid = |uid: u8| Uid::try_from(uid).unwrap();
+        let config = MeshConfig::default();
+        // Routing table of `sender` once `acker`, two hops away through `relay`, acked
+        let hinted = |sender: Uid, acker: Uid, relay: Uid| {
+            let hint = AckType::SuccessWithRoute {
+                message_id: 7,
+                acker,
+                original_ttl: 4,
+                last_hop: acker,
+            };
+            let ack = Message::new_ack(acker, Destination::Unicast(sender), hint, 4, false);
+
+            // The relay, a neighbor of both, forwards the ack with itself as the last hop
+            let mut relay_table = RoutingTable::default();
+            let to_sender = Route {
+                next_hop: sender,
+                hop_count: 0,
+                quality: MAX_QUALITY,
+                last_seen: now,
+            };
+            relay_table.update(sender.get(), to_sender);
+            let Forwarding::Via(next_hop) = forwarding(&relay_table, sender, now, &config) else {
+                panic!("the relay has a route to the sender");
+            };
+            let relayed = forwarded(&ack, relay, next_hop);
+            let Payload::Ack(relayed_hint) = relayed.payload() else {
+                unreachable!();
+            };
+            assert_eq!(*relayed_hint, relay_route_hint(hint, relay));
+
+            // and the sender, searching for the acker, learns the route through the relay
+            let mut table = RoutingTable::default();
+            let mut searches = RingSearches::default();
+            searches.start(acker.get(), now);
+            let ttl = relayed.ttl();
+            let evicted =
+                learn_route_hint(relayed_hint, ttl, &mut table, &mut searches, now, &policy);
+            assert_eq!(evicted, None);
+            assert!(!searches.is_searching(acker.get()));
+            table
+        };
+
+        let at_one = hinted(uid(1), uid(3), uid(2));
+        let at_three = hinted(uid(3), uid(1), uid(2));
+
+        let to_three = at_one.lookup_route(3).unwrap();
+        assert_eq!((to_three.next_hop, to_three.hop_count), (uid(2), 1));
+        assert!(at_one.lookup_route(2).is_none());
+        let to_one = at_three.lookup_route(1).unwrap();
+        assert_eq!((to_one.next_hop, to_one.hop_count), (uid(2), 1));
+    }
+
+    #[test]
+    fn test_repeated_broadcast_is_screened_out_before_processing() {
+        let now = Instant::from_secs(10);
+        let window = Duration::from_millis(500);
+        let relayed = DuplicateFilter::new();
+        let mut duplicates = DuplicateFilter::new();
+        let source = Uid::try_from(2).unwrap();
+        let alert = Message::new_data(
+            source,
+            Destination::Broadcast,
+            DataType::new_text("alert"),
+            3,
+            false,
+        );
+        let unicast = Destination::Unicast(Uid::try_from(1).unwrap());
+        let command = Message::new_data(source, unicast, DataType::new_text("go"), 3, false);
+
+        let mut check =
+            |message: &Message| screen(message, None, &relayed, &mut duplicates, now, window);
+
+        assert_eq!(check(&alert), Screening::Handle);
+        // A repetition of the broadcast is not processed a second time
+        assert_eq!(check(&alert.clone()), Screening::Duplicate);
+        // Unicast messages are acknowledged and retried, their copies are handled
+        assert_eq!(check(&command), Screening::Handle);
+        assert_eq!(check(&command), Screening::Handle);
+    }
+
+    #[test]
+    fn test_cancel_only_removes_own_messages() {
+        let uid = Uid::try_from(1).unwrap();
+        let neighbor = Uid::try_from(2).unwrap();
+        let mut outqueue: Deque<Message, 4> = Deque::new();
+        let broadcast = |source, text| {
+            Message::new_data(source, Destination::Broadcast, DataType::new_text(text), 3, false)
+        };
+        let own = broadcast(uid, "own");
+        let mut relay = broadcast(neighbor, "relay");
+        relay.set_message_id(own.message_id());
+        outqueue.enqueue(own.clone()).unwrap();
+        outqueue.enqueue(relay.clone()).unwrap();
+
+        assert_eq!(outqueue.remove(uid, own.message_id()), 1);
+        assert_eq!(outqueue.dequeue().unwrap(), relay);
+
+        // Removing a relay takes it off the relay count
+        outqueue.enqueue(relay.clone()).unwrap();
+        assert_eq!(count_relays(&outqueue, uid), 1);
+        assert_eq!(outqueue.remove(neighbor, relay.message_id()), 1);
+        assert_eq!(count_relays(&outqueue, uid), 0);
+    }
+
+    #[test]
+    fn test_probe_measures_a_reachable_neighbor_from_its_pong() {
+        let uid = Uid::try_from(1).unwrap();
+        let neighbor = Uid::try_from(2).unwrap();
+        let ping = ping_message(uid, neighbor);
+        let id = ping.message_id();
+        let start = Instant::from_millis(1_000);
+        let pong = |message_id, at| {
+            let received_at = Instant::from_millis(at);
+            Some(Pong { message_id, received_at, rssi: -70, snr: 9 })
+        };
+
+        // Only direct neighbors hear the ping
+        assert!(ping.is_local_only());
+        assert_eq!(ping.ttl(), 1);
+        assert!(probe_outcome(None, id, start, Instant::from_millis(1_200)).is_none());
+        // A late pong of an earlier ping is ignored
+        let earlier = pong(id.wrapping_sub(1), 1_200);
+        assert!(probe_outcome(earlier, id, start, Instant::from_millis(1_200)).is_none());
+
+        // The link is the one the pong came over, whatever was heard since
+        let outcome = probe_outcome(pong(id, 1_350), id, start, Instant::from_millis(1_900));
+        let link = LinkQuality { rssi: -70, snr: 9, round_trip: Duration::from_millis(350) };
+        assert_eq!(outcome.unwrap().unwrap(), link);
+    }
+
+    #[test]
+    fn test_probe_of_an_unreachable_neighbor_times_out() {
+        let uid = Uid::try_from(1).unwrap();
+        let ping = ping_message(uid, Uid::try_from(9).unwrap());
+        let id = ping.message_id();
+        let start = Instant::from_secs(100);
+        let earlier = Pong {
+            message_id: id.wrapping_sub(1),
+            received_at: Instant::from_secs(101),
+            rssi: -70,
+            snr: 9,
+        };
+
+        for last_pong in [None, Some(earlier)] {
+            let waiting = start + PING_TIMEOUT - Duration::from_millis(1);
+            assert!(probe_outcome(last_pong, id, start, waiting).is_none());
+            let outcome = probe_outcome(last_pong, id, start, start + PING_TIMEOUT);
+            assert!(matches!(outcome, Some(Err(DeviceError::Timeout))));
+        }
+    }
+
+    #[test]
+    fn test_probe_is_not_relayed() {
+        let uid = Uid::try_from(1).unwrap();
+        let far = Uid::try_from(3).unwrap();
+        let mut metrics = DeviceMetrics::default();
+        let probe = ping_message(uid, far);
+        // The TTL alone does not stop a relay, it is only checked for 0 on reception
+        let mut ttl_one = probe.clone();
+        ttl_one.set_local_only(false);
+
+        assert!(refuses_relay(&probe, true, &mut metrics));
+        assert_eq!(metrics.relays_local_only, 1);
+        assert!(!refuses_relay(&ttl_one, true, &mut metrics));
+        assert!(refuses_relay(&ttl_one, false, &mut metrics));
+        assert_eq!(metrics.relays_suppressed, 1);
+    }
+
+    /// Polls `future` to completion, waking itself whenever it is pending.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Drops the relays of text data, after waiting once on every message.
+    #[derive(Default)]
+    struct NoTextRelay {
+        polls: usize,
+    }
+
+    impl ForwardFilter for NoTextRelay {
+        fn poll_filter(
+            &mut self,
+            message: &Message,
+            cx: &mut Context<'_>,
+        ) -> Poll<ForwardDecision> {
+            self.polls += 1;
+            if self.polls % 2 == 1 {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(match message.payload() {
+                Payload::Data(DataType::Text(_)) => ForwardDecision::Drop,
+                _ => ForwardDecision::Forward,
+            })
+        }
+    }
+
+    #[test]
+    fn test_forward_filter_decision_is_awaited() {
+        let neighbor = Uid::try_from(2).unwrap();
+        let text = DataType::new_text("deprecated");
+        let text = Message::new_data(neighbor, Destination::Broadcast, text, 3, false);
+        let binary = DataType::new_binary(&[1, 2]);
+        let binary = Message::new_data(neighbor, Destination::Broadcast, binary, 3, false);
+        let mut filter = NoTextRelay::default();
+        let mut metrics = DeviceMetrics::default();
+
+        let relay = block_on(admit_relay(text, true, Some(&mut filter), &mut metrics));
+        assert_eq!(relay, None);
+        assert_eq!(metrics.relays_filtered, 1);
+        let relay = block_on(admit_relay(binary.clone(), true, Some(&mut filter), &mut metrics));
+        assert_eq!(relay, Some(binary.clone()));
+        assert_eq!(filter.polls, 4);
+
+        // Refused relays are never submitted to the filter
+        let mut local = binary.clone();
+        local.set_local_only(true);
+        assert_eq!(block_on(admit_relay(local, true, Some(&mut filter), &mut metrics)), None);
+        assert_eq!(filter.polls, 4);
+        assert_eq!(metrics.relays_filtered, 1);
+    }
+
+    #[test]
+    fn test_local_only_message_is_delivered_but_not_relayed() {
+        let uid = Uid::try_from(1).unwrap();
+        let neighbor = Uid::try_from(2).unwrap();
+        let config = MeshConfig::default();
+        let text = DataType::new_text("here");
+        let mut beacon = Message::new_data(neighbor, Destination::Broadcast, text, 3, false);
+        beacon.set_local_only(true);
+        let mut inqueue: Deque<Message, 2> = Deque::new();
+        let mut outqueue: Deque<Message, 2> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+
+        // As enqueue_message handles a broadcast heard from a neighbor
+        let handling = config.delivery_policy.handling(beacon.destination(), false);
+        let handling = arrival_handling(&beacon, uid, handling, &mut metrics);
+        assert!(handling.deliver && handling.relay);
+        if let Some(relay) = block_on(admit_relay(beacon.clone(), true, None, &mut metrics)) {
+            enqueue_relay(&mut outqueue, &mut metrics, relay);
+        }
+        let owed = hand_over_to(&mut inqueue, &mut metrics, beacon.clone(), config.ack_mode);
+        assert!(owed.is_some());
+
+        assert_eq!(inqueue.dequeue().unwrap(), beacon);
+        assert!(outqueue.is_empty());
+        assert_eq!(metrics.relays_local_only, 1);
+
+        // The same message without the flag is relayed
+        beacon.set_local_only(false);
+        let relay = block_on(admit_relay(beacon.clone(), true, None, &mut metrics));
+        assert_eq!(relay, Some(beacon));
+        assert_eq!(metrics.relays_local_only, 1);
+    }
+
+    #[test]
+    fn test_no_retries_sends_messages_without_ack_request() {
+        let uid = Uid::try_from(1).unwrap();
+        let peer = Destination::Unicast(Uid::try_from(2).unwrap());
+        let mut pending_acks = FnvIndexMap::new();
+
+        for ack_mode in [AckMode::NoRetries, AckMode::Off] {
+            let mut sent = Message::new_data(uid, peer, DataType::new_text("x"), 3, true);
+            track_ack(&mut sent, uid, ack_mode, &mut pending_acks);
+
+            assert!(pending_acks.is_empty());
+            // The receiver decodes a message without ack request, and so queues no ack
+            let mut frame = [0u8; MAX_WIRE_SIZE];
+            let size = sent.encode_into(&mut frame).unwrap();
+            assert!(!Message::try_from(&mut frame[..size]).unwrap().req_ack());
+        }
+
+        // Requests of other nodes are relayed untouched
+        let mut relayed =
+            Message::new_data(Uid::try_from(3).unwrap(), peer, DataType::new_text("x"), 3, true);
+        track_ack(&mut relayed, uid, AckMode::NoRetries, &mut pending_acks);
+        assert!(relayed.req_ack() && pending_acks.is_empty());
+    }
+
+    #[test]
+    fn test_relayed_discoveries_are_not_in_flight() {
+        let uid = Uid::try_from(1).unwrap();
+        let neighbor = Uid::try_from(2).unwrap();
+        let discovery = |source| {
+            let payload = Payload::Discovery(DiscoveryType {
+                original_ttl: 3,
+                sender_capabilities: DeviceCapabilities::Lora,
+            });
+            Message::new(source, Destination::Broadcast, payload, 3, true)
+        };
+        let mut outqueue: Deque<Message, 4> = Deque::new();
+        let mut pending_acks = FnvIndexMap::new();
+
+        let mut relay = discovery(neighbor);
+        track_ack(&mut relay, uid, AckMode::Reliable, &mut pending_acks);
+        outqueue.enqueue(relay).unwrap();
+        assert!(pending_acks.is_empty());
+        assert_eq!(queued_discoveries(&outqueue, uid), 0);
+
+        let mut own = discovery(uid);
+        track_ack(&mut own, uid, AckMode::Reliable, &mut pending_acks);
+        outqueue.enqueue(own).unwrap();
+        assert_eq!(pending_acks.len(), 1);
+        assert_eq!(queued_discoveries(&outqueue, uid), 1);
+    }
+
+    #[test]
+    fn test_relayed_unicast_to_this_device_is_a_relay() {
+        let uid = Uid::try_from(1).unwrap();
+        let neighbor = Uid::try_from(2).unwrap();
+        let mut outqueue: Deque<Message, 4> = Deque::new();
+        let to_uid = |source| {
+            Message::new_data(source, Destination::Unicast(uid), DataType::new_text("x"), 3, false)
+        };
+
+        // Sent to itself by the application, looped back rather than relayed
+        outqueue.enqueue(to_uid(uid)).unwrap();
+        assert_eq!(count_relays(&outqueue, uid), 0);
+        // Flooded on to the other nodes sharing the address
+        outqueue.enqueue(to_uid(neighbor)).unwrap();
+        assert_eq!(count_relays(&outqueue, uid), 1);
+    }
+
+    #[test]
+    fn test_success_ack_stops_retries() {
+        let uid = Uid::try_from(1).unwrap();
+        let destination = Destination::Unicast(Uid::try_from(2).unwrap());
+        let mut pending_acks = FnvIndexMap::new();
+        let mut table = RoutingTable::default();
+        let mut sent = Message::new_data(uid, destination, DataType::new_text("x"), 3, true);
+        track_ack(&mut sent, uid, AckMode::Reliable, &mut pending_acks);
+
+        let other = sent.message_id().wrapping_add(1);
+        assert!(!acknowledge(&mut pending_acks, &mut table, other));
+        assert!(!pending_acks[&sent.message_id()].is_acknowledged);
+        assert!(acknowledge(&mut pending_acks, &mut table, sent.message_id()));
+        assert!(pending_acks[&sent.message_id()].is_acknowledged);
+    }
+
+    #[test]
+    fn test_failed_deliveries_hand_the_destination_to_an_alternative() {
+        let uid = Uid::try_from(1).unwrap();
+        let destination = Uid::try_from(5).unwrap();
+        let policy = RoutePolicy::default();
+        let mut table = RoutingTable::default();
+        let mut pending_acks = FnvIndexMap::new();
+        let failing = Route {
+            next_hop: Uid::try_from(2).unwrap(),
+            hop_count: 1,
+            quality: MAX_QUALITY,
+            last_seen: Instant::from_secs(0),
+        };
+        let alternative = Route {
+            next_hop: Uid::try_from(3).unwrap(),
+            hop_count: 2,
+            ..failing
+        };
+        table.update(destination.get(), failing);
+        let text = DataType::new_text("x");
+        let mut sent = Message::new_data(uid, Destination::Unicast(destination), text, 3, true);
+        track_ack(&mut sent, uid, AckMode::Reliable, &mut pending_acks);
+
+        // A healthy route keeps the longer alternative out
+        table.update_if_better(destination.get(), alternative, &policy);
+        assert_eq!(table.lookup_route(destination.get()), Some(failing));
+
+        for _ in 0..MAX_ACK_ATTEMPTS {
+            ack_timed_out(&pending_acks[&sent.message_id()], &mut table);
+        }
+        table.update_if_better(destination.get(), alternative, &policy);
+        let route = table.lookup_route(destination.get()).unwrap();
+        assert_eq!(route.next_hop, alternative.next_hop);
+
+        // and the ack coming through the alternative credits it, once
+        let degraded = Route { quality: 50, ..route };
+        table.update(destination.get(), degraded);
+        assert!(acknowledge(&mut pending_acks, &mut table, sent.message_id()));
+        assert!(acknowledge(&mut pending_acks, &mut table, sent.message_id()));
+        assert_eq!(table.lookup_route(destination.get()).unwrap().quality, 63);
+    }
+
+    #[test]
+    fn test_reliable_discovery_response_completes_across_a_relay() {
+        // Chain origin - relay - responder, the responder being out of reach of the origin
+        let origin = Uid::try_from(1).unwrap();
+        let relay = Uid::try_from(2).unwrap();
+        let responder = Uid::try_from(3).unwrap();
+        let mut pending_acks = FnvIndexMap::new();
+        let payload = AckType::AckDiscovered { hops: 1, last_hop: responder };
+        let mut response =
+            Message::new_ack(responder, Destination::Unicast(origin), payload, 2, true);
+        track_ack(&mut response, responder, AckMode::Reliable, &mut pending_acks);
+
+        // The relay acks the response it ends, on behalf of the origin
+        let id = response.message_id();
+        let ack = success_ack(id, relay, response.ttl(), false);
+        assert_eq!(ack, AckType::Success { message_id: id });
+        let hinted = success_ack(id, relay, response.ttl(), true);
+        assert!(matches!(hinted, AckType::SuccessWithRoute { message_id, .. } if message_id == id));
+        let mut table = RoutingTable::default();
+        assert!(acknowledge(&mut pending_acks, &mut table, response.message_id()));
+
+        // and sends its own response back, which the responder does not have to ack
+        let mut hop = hop_discovery_ack(relay, responder, response.ttl());
+        assert_eq!(hop.destination(), Destination::Unicast(responder));
+        let payload = AckType::AckDiscovered { hops: 0, last_hop: relay };
+        assert_eq!(hop.payload(), &Payload::Ack(payload));
+        assert!(!hop.req_ack());
+        track_ack(&mut hop, relay, AckMode::Reliable, &mut pending_acks);
+        assert!(!pending_acks.contains_key(&hop.message_id()));
+    }
+
+    #[test]
+    fn test_no_ack_for_a_message_the_inqueue_had_no_room_for() {
+        let source = Uid::try_from(2).unwrap();
+        let destination = Destination::Unicast(Uid::try_from(1).unwrap());
+        let message = Message::new_data(source, destination, DataType::new_text("hi"), 3, true);
+        let mut inqueue: Deque<Message, 1> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+
+        assert_eq!(
+            hand_over_to(&mut inqueue, &mut metrics, message.clone(), AckMode::Reliable),
+            Some(Owed { ack: true, receipt: true, config: None })
+        );
+        assert_eq!(hand_over_to(&mut inqueue, &mut metrics, message, AckMode::Reliable), None);
+        assert_eq!(metrics.messages_dropped_inqueue_full, 1);
+    }
+
+    #[test]
+    fn test_config_the_inqueue_had_no_room_for_is_not_applied() {
+        let manager = Uid::try_from(2).unwrap();
+        let destination = Destination::Unicast(Uid::try_from(1).unwrap());
+        let requested = DeviceConfig {
+            device_class: DeviceClass::C,
+            ..DeviceConfig::default()
+        };
+        let set = CommandType::SetConfig(requested);
+        let set = Message::new_command(manager, destination, set, 3, true);
+        let mut inqueue: Deque<Message, 1> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+        let text = DataType::new_text("busy");
+        inqueue.enqueue(Message::new_data(manager, destination, text, 3, false)).unwrap();
+
+        // Nothing to apply, so the manager retries rather than being left unanswered
+        assert_eq!(hand_over_to(&mut inqueue, &mut metrics, set.clone(), AckMode::Reliable), None);
+        inqueue.dequeue().unwrap();
+        assert_eq!(
+            hand_over_to(&mut inqueue, &mut metrics, set, AckMode::Reliable),
+            Some(Owed { ack: true, receipt: true, config: Some(requested) })
+        );
+    }
+
+    #[test]
+    fn test_relays_beyond_the_cap_leave_room_for_own_traffic() {
+        let uid = Uid::try_from(1).unwrap();
+        let neighbor = Uid::try_from(2).unwrap();
+        let broadcast = |source| {
+            Message::new_data(source, Destination::Broadcast, DataType::new_text("hi"), 3, false)
+        };
+        let mut outqueue: Deque<Message, 4> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+
+        for _ in 0..4 {
+            if admits_relay(count_relays(&outqueue, uid), 2, &mut metrics) {
+                enqueue_relay(&mut outqueue, &mut metrics, broadcast(neighbor));
+            }
+        }
+
+        assert_eq!(count_relays(&outqueue, uid), 2);
+        assert_eq!(metrics.broadcasts_dropped_relay_cap, 2);
+        outqueue.enqueue(broadcast(uid)).unwrap();
+        outqueue.enqueue(broadcast(uid)).unwrap();
+        // Transmitting a relay makes room for the next one
+        outqueue.dequeue().unwrap();
+        assert!(admits_relay(count_relays(&outqueue, uid), 2, &mut metrics));
+    }
+
+    #[test]
+    fn test_relaying_switch_refuses_every_relay() {
+        let neighbor = Uid::try_from(2).unwrap();
+        let far = Destination::Unicast(Uid::try_from(3).unwrap());
+        let mut metrics = DeviceMetrics::default();
+        let relays = [
+            Message::new_data(neighbor, Destination::Broadcast, DataType::new_text("x"), 3, false),
+            Message::new_data(neighbor, Destination::Group(4), DataType::new_text("x"), 3, false),
+            Message::new_data(neighbor, far, DataType::new_text("x"), 3, true),
+        ];
+
+        for relay in &relays {
+            assert!(refuses_relay(relay, false, &mut metrics));
+        }
+        assert_eq!(metrics.relays_suppressed, 3);
+        // Turned back on, relaying takes effect for the next message
+        for relay in &relays {
+            assert!(!refuses_relay(relay, true, &mut metrics));
+        }
+        assert_eq!(metrics.relays_suppressed, 3);
+    }
+
+    /// Outgoing state of a device, lent to an `Outbox`.
+    #[derive(Default)]
+    struct Outgoing {
+        outqueue: Deque<Message, OUTQUEUE_SIZE>,
+        inqueue: Deque<Message, 4>,
+        rate_limiter: RateLimiter,
+        tx_budget: TokenBucket,
+        queued_relays: usize,
+        metrics: DeviceMetrics,
+    }
+
+    impl Outgoing {
+        fn outbox(&mut self) -> Outbox<'_, Deque<Message, 4>, Deque<Message, OUTQUEUE_SIZE>> {
+            Outbox {
+                outqueue: &mut self.outqueue,
+                inqueue: &mut self.inqueue,
+                rate_limiter: &mut self.rate_limiter,
+                tx_budget: &mut self.tx_budget,
+                queued_relays: &mut self.queued_relays,
+                metrics: &mut self.metrics,
+            }
+        }
+
+        /// Runs `flush_outqueue` at `now`, every transmission bringing `incoming` new
+        /// messages in, and returns how many were sent.
+        fn flush(&mut self, uid: Uid, config: &MeshConfig, now: Instant, incoming: usize) -> usize {
+            let deadline = now + FLUSH_TIMEOUT;
+            let mut outbox = self.outbox();
+            let mut sent = 0;
+            while flush_goes_on(sent, now, deadline) {
+                let Some(message) = outbox.next(uid, config, now) else {
+                    break;
+                };
+                sent += 1;
+                for _ in 0..incoming {
+                    outbox.outqueue.enqueue(message.clone()).unwrap();
+                }
+            }
+            sent
+        }
+    }
+
+    #[test]
+    fn test_flush_empties_the_outqueue() {
+        let uid = Uid::try_from(1).unwrap();
+        let neighbor = Uid::try_from(2).unwrap();
+        let message = |source, destination| {
+            Message::new_data(source, destination, DataType::new_text("x"), 3, false)
+        };
+        let mut outgoing = Outgoing::default();
+        let queued = [
+            message(uid, Destination::Broadcast),
+            message(neighbor, Destination::Broadcast),
+            message(uid, Destination::Unicast(uid)),
+            message(uid, Destination::Unicast(neighbor)),
+        ];
+        for message in queued {
+            outgoing.outqueue.enqueue(message).unwrap();
+        }
+        outgoing.queued_relays = 1;
+
+        let sent = outgoing.flush(uid, &MeshConfig::default(), Instant::from_secs(0), 0);
+
+        // The message to this device is looped back rather than transmitted
+        assert_eq!(sent, 3);
+        assert!(outgoing.outqueue.is_empty());
+        assert_eq!(outgoing.queued_relays, 0);
+        assert_eq!(outgoing.inqueue.len(), 1);
+        assert_eq!(outgoing.metrics.messages_looped_back, 1);
+    }
+
+    #[test]
+    fn test_flush_is_bounded() {
+        let uid = Uid::try_from(1).unwrap();
+        let message =
+            || Message::new_data(uid, Destination::Broadcast, DataType::new_text("x"), 3, false);
+        let config = MeshConfig::default();
+        let now = Instant::from_secs(0);
+
+        // Every transmission brings a new message in, such as an ack
+        let mut outgoing = Outgoing::default();
+        outgoing.outqueue.enqueue(message()).unwrap();
+        assert_eq!(outgoing.flush(uid, &config, now, 1), OUTQUEUE_SIZE);
+        assert_eq!(outgoing.outqueue.len(), 1);
+        assert!(!flush_goes_on(0, now + FLUSH_TIMEOUT, now + FLUSH_TIMEOUT));
+
+        // The airtime budget ends the flush early, the rest waits in the outqueue
+        let throttled = MeshConfig {
+            max_throughput: Some(Throughput::per_second(2)),
+            ..MeshConfig::default()
+        };
+        let mut outgoing = Outgoing::default();
+        for _ in 0..4 {
+            outgoing.outqueue.enqueue(message()).unwrap();
+        }
+        assert_eq!(outgoing.flush(uid, &throttled, now, 0), 2);
+        assert_eq!(outgoing.outqueue.len(), 2);
+    }
+
+    #[test]
+    fn test_outqueue_residence_rises_under_congestion() {
+        let uid = Uid::try_from(1).unwrap();
+        let throttled = MeshConfig {
+            max_throughput: Some(Throughput::per_second(1)),
+            ..MeshConfig::default()
+        };
+        let mut outgoing = Outgoing::default();
+        let message =
+            || Message::new_data(uid, Destination::Broadcast, DataType::new_text("x"), 3, false);
+        let mut residences: Vec<u32, 8> = Vec::new();
+
+        // Two messages come in every second while only one can go out
+        for second in 0..8 {
+            let now = Instant::from_secs(second);
+            for _ in 0..2 {
+                let mut message = message();
+                message.mark_arrival(now);
+                outgoing.outqueue.enqueue(message).unwrap();
+            }
+            while outgoing.outbox().next(uid, &throttled, now).is_some() {}
+            residences.push(outgoing.metrics.outqueue_residence_ms).unwrap();
+        }
+
+        assert!(residences.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(residences[7] > 1000);
+        assert_eq!(outgoing.outqueue.len(), 8);
+    }
+
+    #[test]
+    fn test_message_to_self_is_looped_back_to_the_inqueue() {
+        let uid = Uid::try_from(1).unwrap();
+        let to_self = |source| {
+            Message::new_data(source, Destination::Unicast(uid), DataType::new_text("x"), 3, false)
+        };
+        let mut inqueue: Deque<Message, 1> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+
+        assert!(is_loop_back(&to_self(uid), uid));
+        // A relay of a message to this address goes out to the nodes sharing it
+        assert!(!is_loop_back(&to_self(Uid::try_from(2).unwrap()), uid));
+
+        loop_back(&mut inqueue, &mut metrics, to_self(uid));
+        loop_back(&mut inqueue, &mut metrics, to_self(uid));
+        assert_eq!(inqueue.len(), 1);
+        assert_eq!(metrics.messages_looped_back, 1);
+        assert_eq!(metrics.messages_dropped_inqueue_full, 1);
+    }
+
+    #[test]
+    fn test_promiscuous_capture_keeps_every_frame() {
+        let neighbor = Uid::try_from(2).unwrap();
+        let other = Destination::Unicast(Uid::try_from(3).unwrap());
+        let frames = [
+            Message::new_data(neighbor, other, DataType::new_text("x"), 3, true),
+            Message::new_data(neighbor, Destination::Group(9), DataType::new_text("x"), 3, false),
+            Message::new_ack(neighbor, other, AckType::Success { message_id: 1 }, 3, false),
+        ];
+        let mut inqueue: Deque<Message, 3> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+
+        for (rssi, frame) in (-90..).zip(&frames) {
+            capture(&mut inqueue, &mut metrics, frame.clone(), (rssi, 5));
+        }
+        capture(&mut inqueue, &mut metrics, frames[0].clone(), (-60, 5));
+
+        assert_eq!(metrics.messages_captured, 3);
+        assert_eq!(metrics.messages_dropped_inqueue_full, 1);
+        // Captured as received, the ack request and TTL untouched, along with their signal
+        for (rssi, frame) in (-90..).zip(&frames) {
+            let mut received = frame.clone();
+            received.set_rx_signal(rssi, 5);
+            assert_eq!(inqueue.dequeue().unwrap(), received);
+        }
+    }
+
+    #[test]
+    fn test_relay_known_only_forwards_to_known_destinations() {
+        let next_hop = Uid::try_from(2).unwrap();
+        let known = Uid::try_from(5).unwrap();
+        let unknown = Uid::try_from(6).unwrap();
+        let now = Instant::from_secs(10);
+        let mut table = RoutingTable::default();
+        table.update(
+            known.get(),
+            Route {
+                next_hop,
+                hop_count: 2,
+                quality: 100,
+                last_seen: now,
+            },
+        );
+        let edge = MeshConfig {
+            relay_known_only: true,
+            ..MeshConfig::default()
+        };
+
+        assert_eq!(forwarding(&table, known, now, &edge), Forwarding::Via(next_hop));
+        assert_eq!(forwarding(&table, unknown, now, &edge), Forwarding::Drop { discover: false });
+        // A full router discovers the unknown destination for the next messages
+        let router = MeshConfig::default();
+        assert_eq!(forwarding(&table, unknown, now, &router), Forwarding::Drop { discover: true });
+    }
+
+    #[test]
+    fn test_pending_ack_to_dead_destination_is_failed_early() {
+        let uid = Uid::try_from(1).unwrap();
+        let dead = Destination::Unicast(Uid::try_from(7).unwrap());
+        let table = RoutingTable::default();
+        let mut searches = RingSearches::default();
+        let policy = RoutePolicy::default();
+        let now = Instant::from_secs(10);
+        let mut pending_acks = FnvIndexMap::new();
+        let mut sent = Message::new_data(uid, dead, DataType::new_text("x"), 3, true);
+        track_ack(&mut sent, uid, AckMode::Reliable, &mut pending_acks);
+        let ack = pending_acks.get_mut(&sent.message_id()).unwrap();
+
+        // Not tried yet, then tried once: failed as soon as `fail_after` attempts were made
+        assert!(!fails_early(ack, Some(1), &table, &searches, now, &policy));
+        ack.attempts = 1;
+        assert!(ack.attempts < MAX_ACK_ATTEMPTS);
+        assert!(fails_early(ack, Some(1), &table, &searches, now, &policy));
+        // Kept while a discovery may still find it, and by default
+        searches.start(7, now);
+        assert!(!fails_early(ack, Some(1), &table, &searches, now, &policy));
+        searches.resolve(7);
+        assert!(!fails_early(ack, None, &table, &searches, now, &policy));
+    }
+
+    #[test]
+    fn test_unicast_to_self_is_delivered_on_its_last_hop() {
+        let uid = Uid::try_from(1).unwrap();
+        let relay = Uid::try_from(2).unwrap();
+        let data = || DataType::new_text("x");
+        // Routed to this device, its TTL spent by the relay's transmission
+        let mut last_hop = Message::new_data(relay, Destination::Unicast(uid), data(), 1, false);
+        last_hop.decrement_ttl();
+        let mut flooded = Message::new_data(relay, Destination::Broadcast, data(), 1, false);
+        flooded.decrement_ttl();
+        let policy = DeliveryPolicy::default();
+        let mut metrics = DeviceMetrics::default();
+
+        let handling = policy.handling(last_hop.destination(), false);
+        assert_eq!(arrival_handling(&last_hop, uid, handling, &mut metrics), Handling::DELIVER);
+        assert_eq!(metrics.messages_dropped_expired, 0);
+        // Only the relay half of an anycast is dropped
+        let anycast = Handling::DELIVER_AND_RELAY;
+        assert_eq!(arrival_handling(&last_hop, uid, anycast, &mut metrics), Handling::DELIVER);
+        assert_eq!(metrics.messages_dropped_expired, 1);
+        let handling = policy.handling(flooded.destination(), false);
+        assert_eq!(arrival_handling(&flooded, uid, handling, &mut metrics), Handling::IGNORE);
+        assert_eq!(metrics.messages_dropped_expired, 2);
+    }
+
+    #[test]
+    fn test_discovery_cap_holds_on_every_path() {
+        let uid = Uid::try_from(1).unwrap();
+        let discovery = |ttl| {
+            let payload = Payload::Discovery(DiscoveryType {
+                original_ttl: ttl,
+                sender_capabilities: DeviceCapabilities::Lora,
+            });
+            Message::new(uid, Destination::Broadcast, payload, ttl, true)
+        };
+        let config = MeshConfig {
+            max_discoveries_in_flight: Some(2),
+            ..MeshConfig::default()
+        };
+        let mut outqueue: Deque<Message, 8> = Deque::new();
+        let mut pending_acks = FnvIndexMap::new();
+        let mut searches = RingSearches::default();
+        let mut table = RoutingTable::default();
+        let now = Instant::from_secs(0);
+        let queue = |outqueue: &mut Deque<Message, 8>, pending_acks: &_, ttl| {
+            queue_discovery(outqueue, pending_acks, uid, &config, discovery(ttl)).unwrap()
+        };
+
+        // A periodic discovery awaits its ack while a route discovery is queued
+        let mut sent = discovery(3);
+        track_ack(&mut sent, uid, AckMode::Reliable, &mut pending_acks);
+        let ttl = searches.start(5, now).unwrap();
+        assert!(queue(&mut outqueue, &pending_acks, ttl));
+        assert_eq!(discoveries_in_flight(&outqueue, &pending_acks, uid), 2);
+
+        // The ring search widening is held back by the cap
+        let later = now + RING_TIMEOUT;
+        let ttl = searches.advance(later, 3).unwrap();
+        assert!(!queue(&mut outqueue, &pending_acks, ttl));
+        // and so is the rediscovery of a destination that stopped acknowledging
+        let ttl = rediscover(&mut table, &mut searches, None, 6, later).unwrap();
+        assert!(!queue(&mut outqueue, &pending_acks, ttl));
+        assert_eq!(outqueue.len(), 1);
+
+        // Once the periodic discovery is answered, floods go out again
+        assert!(acknowledge(&mut pending_acks, &mut table, sent.message_id()));
+        pending_acks.retain(|_, ack| !ack.is_acknowledged);
+        assert!(queue(&mut outqueue, &pending_acks, ttl));
+    }
+
+    #[test]
+    fn test_routed_sequenced_messages_are_delivered_in_order() {
+        let sender = Uid::try_from(1).unwrap();
+        let relay = Uid::try_from(2).unwrap();
+        let destination = Uid::try_from(3).unwrap();
+        let now = Instant::from_secs(10);
+        let mut table = RoutingTable::default();
+        table.update(
+            destination.get(),
+            Route {
+                next_hop: destination,
+                hop_count: 0,
+                quality: 100,
+                last_seen: now,
+            },
+        );
+        let mut sequencer = Sequencer::new();
+        let mut sent: Vec<Message, 3> = Vec::new();
+        for _ in 0..3 {
+            let text = DataType::new_text("chunk");
+            let mut message =
+                Message::new_data(sender, Destination::Unicast(destination), text, 3, true);
+            sequencer.assign(&mut message);
+            sent.push(message).unwrap();
+        }
+        let Forwarding::Via(next_hop) = forwarding(&table, destination, now, &MeshConfig::default())
+        else {
+            unreachable!();
+        };
+
+        // The second message is retried and reaches the destination last
+        let mut reorderer = Reorderer::default();
+        let mut delivered: Vec<u8, 3> = Vec::new();
+        for message in [&sent[0], &sent[2], &sent[1]] {
+            let routed = forwarded(message, relay, next_hop);
+            assert_eq!(routed.sequence(), message.sequence());
+            assert_eq!(routed.ttl(), message.ttl() - 1);
+            if let Some(message) = reorderer.push(routed, now) {
+                delivered.push(message.sequence()).unwrap();
+            }
+            while let Some(message) = reorderer.pop(now, Duration::from_secs(2)) {
+                delivered.push(message.sequence()).unwrap();
+            }
+        }
+
+        assert_eq!(delivered.as_slice(), &[0, 1, 2]);
+    }
+
+    #[test]
+    fn test_filtered_payload_is_relayed_without_being_processed() {
+        let uid = Uid::try_from(1).unwrap();
+        let neighbor = Uid::try_from(2).unwrap();
+        let sensor = MeshConfig {
+            local_payloads: PayloadKinds::COMMAND,
+            ..MeshConfig::default()
+        };
+        let data = DataType::new_text("21C");
+        let reading = Message::new_data(neighbor, Destination::Broadcast, data, 3, false);
+        let set = CommandType::SetConfig(DeviceConfig::default());
+        let set = Message::new_command(neighbor, Destination::Unicast(uid), set, 3, false);
+        let mut inqueue: Deque<Message, 4> = Deque::new();
+        let mut outqueue: Deque<Message, 4> = Deque::new();
+        let mut metrics = DeviceMetrics::default();
+
+        // As enqueue_message handles what the sensor hears
+        for message in [reading.clone(), set.clone()] {
+            let handling = local_handling(&message, uid, false, &sensor, &mut metrics);
+            if handling.relay {
+                let relay = block_on(admit_relay(message.clone(), true, None, &mut metrics));
+                enqueue_relay(&mut outqueue, &mut metrics, relay.unwrap());
+            }
+            if handling.deliver {
+                hand_over_to(&mut inqueue, &mut metrics, message, sensor.ack_mode);
+            }
+        }
+
+        assert_eq!(outqueue.dequeue().unwrap(), reading);
+        assert!(outqueue.is_empty());
+        assert_eq!(inqueue.dequeue().unwrap(), set);
+        assert!(inqueue.is_empty());
+        let handling = local_handling(&reading, uid, false, &MeshConfig::default(), &mut metrics);
+        assert_eq!(handling, Handling::DELIVER_AND_RELAY);
+    }
+
+    /// Radio that refuses to transmit while a receive is in flight, and whose receive
+    /// never ends without a frame.
+    #[derive(Default)]
+    struct MockRadio {
+        frame: Option<u8>,
+        receiving: bool,
+        aborts: usize,
+    }
+
+    impl MockRadio {
+        /// Whether a frame went out.
+        fn transmit(&self) -> bool {
+            !self.receiving
+        }
+    }
+
+    impl Receiver for MockRadio {
+        type Received = u8;
+
+        async fn receive(&mut self) -> Result<u8, RadioError> {
+            self.receiving = true;
+            let Some(size) = self.frame else {
+                return pending().await;
+            };
+            self.receiving = false;
+            Ok(size)
+        }
+
+        async fn abort(&mut self) -> Result<(), RadioError> {
+            self.receiving = false;
+            self.aborts += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_host_rx_timeout_aborts_the_stuck_receive() {
+        let mut stuck = MockRadio::default();
+
+        assert!(block_on(receive_until(&mut stuck, ready(()))).is_none());
+        assert_eq!(stuck.aborts, 1);
+        // Back in standby, the next TX goes out
+        assert!(stuck.transmit());
+
+        // A frame arriving before the deadline is kept, the radio ended the receive itself
+        let mut receiving = MockRadio {
+            frame: Some(12),
+            ..MockRadio::default()
+        };
+        let received = block_on(receive_until(&mut receiving, ready(())));
+        assert!(matches!(received, Some(Ok(12))));
+        assert_eq!(receiving.aborts, 0);
+        assert!(receiving.transmit());
+    }
+}
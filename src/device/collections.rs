@@ -1,6 +1,8 @@
 use defmt::Format;
+use heapless::{Deque, Vec};
 
-use crate::message::Message;
+use crate::device::Uid;
+use crate::message::{Message, MessageId};
 
 #[derive(Debug, Format)]
 pub enum CollectionError {
@@ -21,4 +23,279 @@ pub trait MessageQueue {
     fn dequeue(&mut self) -> Result<Message, CollectionError>;
     fn len(&self) -> usize;
     fn is_empty(&self) -> bool;
+
+    /// Maximum number of messages the queue can hold.
+    ///
+    /// Queues that do not know it are taken as unbounded, `enqueue` still reporting
+    /// `CollectionError::Full` when they are not.
+    fn capacity(&self) -> usize {
+        usize::MAX
+    }
+
+    /// Message at `index` without dequeuing it, the oldest being at index 0.
+    ///
+    /// Queues that cannot be read in place return `None`, so their messages are left out of
+    /// snapshots and of the counts made by peeking, such as the relays queued.
+    fn get(&self, _index: usize) -> Option<&Message> {
+        None
+    }
+
+    /// Number of messages that can still be enqueued.
+    fn remaining(&self) -> usize {
+        self.capacity().saturating_sub(self.len())
+    }
+
+    /// Enqueues a message ahead of the queued ones, so it is dequeued next.
+    ///
+    /// Queues that cannot reorder enqueue it at the back.
+    fn enqueue_front(&mut self, message: Message) -> Result<(), CollectionError> {
+        self.enqueue(message)
+    }
+
+    /// Removes every queued message of `source` with `message_id`, keeping the others in
+    /// order.
+    ///
+    /// Returns how many messages were removed.
+    fn remove(&mut self, source: Uid, message_id: MessageId) -> usize {
+        let mut removed = 0;
+        for _ in 0..self.len() {
+            let Ok(message) = self.dequeue() else {
+                break;
+            };
+            if message.source_id() == source && message.message_id() == message_id {
+                removed += 1;
+            } else {
+                // Cannot fail, a message was just dequeued
+                let _ = self.enqueue(message);
+            }
+        }
+        removed
+    }
+}
+
+impl<const N: usize> MessageQueue for Deque<Message, N> {
+    fn enqueue(&mut self, message: Message) -> Result<(), CollectionError> {
+        self.push_back(message).map_err(|_| CollectionError::Full)
+    }
+
+    fn dequeue(&mut self) -> Result<Message, CollectionError> {
+        self.pop_front().ok_or(CollectionError::Empty)
+    }
+
+    fn enqueue_front(&mut self, message: Message) -> Result<(), CollectionError> {
+        self.push_front(message).map_err(|_| CollectionError::Full)
+    }
+
+    fn len(&self) -> usize {
+        Deque::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Deque::is_empty(self)
+    }
+
+    fn capacity(&self) -> usize {
+        Deque::capacity(self)
+    }
+
+    fn get(&self, index: usize) -> Option<&Message> {
+        self.iter().nth(index)
+    }
+}
+
+/// Queue kept in insertion order, dequeuing shifts the remaining messages.
+impl<const N: usize> MessageQueue for Vec<Message, N> {
+    fn enqueue(&mut self, message: Message) -> Result<(), CollectionError> {
+        self.push(message).map_err(|_| CollectionError::Full)
+    }
+
+    fn dequeue(&mut self) -> Result<Message, CollectionError> {
+        if self.as_slice().is_empty() {
+            return Err(CollectionError::Empty);
+        }
+        Ok(self.remove(0))
+    }
+
+    fn enqueue_front(&mut self, message: Message) -> Result<(), CollectionError> {
+        self.insert(0, message).map_err(|_| CollectionError::Full)
+    }
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.as_slice().is_empty()
+    }
+
+    fn capacity(&self) -> usize {
+        N
+    }
+
+    fn get(&self, index: usize) -> Option<&Message> {
+        self.as_slice().get(index)
+    }
+}
+
+/// Clones up to `N` messages of `queue`, oldest first, leaving the queue untouched.
+pub fn snapshot<Q, const N: usize>(queue: &Q) -> Vec<Message, N>
+where
+    Q: MessageQueue + ?Sized,
+{
+    (0..queue.len().min(N))
+        .filter_map(|index| queue.get(index).cloned())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use heapless::{Deque, Vec};
+
+    use crate::device::collections::{snapshot, CollectionError, MessageQueue};
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::payload::data::DataType;
+    use crate::message::{Message, MessageId};
+
+    #[test]
+    fn test_queue_remaining() {
+        let mut queue: Deque<Message, 2> = Deque::new();
+        let message = Message::new_data(
+            Uid::try_from(1).unwrap(),
+            Destination::Broadcast,
+            DataType::new_text("Hello World!"),
+            3,
+            false,
+        );
+
+        assert_eq!(MessageQueue::capacity(&queue), 2);
+        assert_eq!(queue.remaining(), 2);
+        queue.enqueue(message.clone()).unwrap();
+        assert_eq!(queue.remaining(), 1);
+        queue.enqueue(message.clone()).unwrap();
+        assert_eq!(queue.remaining(), 0);
+        assert!(queue.enqueue(message).is_err());
+    }
+
+    #[test]
+    fn test_snapshot_does_not_dequeue() {
+        let mut queue: Deque<Message, 4> = Deque::new();
+        for text in ["first", "second", "third"] {
+            let message = Message::new_data(
+                Uid::try_from(1).unwrap(),
+                Destination::Broadcast,
+                DataType::new_text(text),
+                3,
+                false,
+            );
+            queue.enqueue(message).unwrap();
+        }
+
+        let messages = snapshot::<_, 4>(&queue);
+
+        assert_eq!(messages.len(), 3);
+        assert_eq!(snapshot::<_, 2>(&queue).len(), 2);
+        assert_eq!(queue.len(), 3);
+        for message in &messages {
+            assert_eq!(&queue.dequeue().unwrap(), message);
+        }
+    }
+
+    #[test]
+    fn test_retry_goes_ahead_of_new_traffic() {
+        let mut queue: Deque<Message, 4> = Deque::new();
+        let source = Uid::try_from(1).unwrap();
+        let destination = Destination::Unicast(Uid::try_from(2).unwrap());
+        let retry = Message::new_data(source, destination, DataType::new_text("retry"), 3, true);
+        for _ in 0..3 {
+            let message = Message::new_data(source, destination, DataType::new_text("new"), 3, false);
+            queue.enqueue(message).unwrap();
+        }
+
+        queue.enqueue_front(retry.clone()).unwrap();
+
+        assert_eq!(queue.dequeue().unwrap(), retry);
+        assert!(queue.enqueue_front(retry.clone()).is_ok());
+        assert!(queue.enqueue_front(retry).is_err());
+    }
+
+    #[test]
+    fn test_remove_by_id_keeps_order() {
+        let mut queue: Deque<Message, 4> = Deque::new();
+        let source = Uid::try_from(1).unwrap();
+        let mut ids = [0; 4];
+        for id in ids.iter_mut() {
+            let message = Message::new_data(source, Destination::Broadcast, DataType::new_text("data"), 3, false);
+            *id = message.message_id();
+            queue.enqueue(message).unwrap();
+        }
+
+        assert_eq!(queue.remove(Uid::try_from(2).unwrap(), ids[1]), 0);
+        assert_eq!(queue.remove(source, ids[1]), 1);
+        assert_eq!(queue.remove(source, ids[1]), 0);
+
+        let remaining: heapless::Vec<MessageId, 4> = (0..queue.len())
+            .map(|_| queue.dequeue().unwrap().message_id())
+            .collect();
+        assert_eq!(remaining.as_slice(), &[ids[0], ids[2], ids[3]]);
+    }
+
+    /// Queue implementing only the required methods.
+    struct Minimal(Deque<Message, 2>);
+
+    impl MessageQueue for Minimal {
+        fn enqueue(&mut self, message: Message) -> Result<(), CollectionError> {
+            self.0.enqueue(message)
+        }
+
+        fn dequeue(&mut self) -> Result<Message, CollectionError> {
+            self.0.dequeue()
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+
+        fn is_empty(&self) -> bool {
+            self.0.is_empty()
+        }
+    }
+
+    #[test]
+    fn test_queue_without_capacity_or_get() {
+        let mut queue = Minimal(Deque::new());
+        let message = Message::new_data(
+            Uid::try_from(1).unwrap(),
+            Destination::Broadcast,
+            DataType::new_text("Hello World!"),
+            3,
+            false,
+        );
+        queue.enqueue(message.clone()).unwrap();
+
+        assert_eq!(queue.remaining(), usize::MAX - 1);
+        assert!(snapshot::<_, 2>(&queue).is_empty());
+        assert_eq!(queue.remove(Uid::try_from(1).unwrap(), message.message_id()), 1);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_vec_queue_is_first_in_first_out() {
+        let mut queue: Vec<Message, 2> = Vec::new();
+        let source = Uid::try_from(1).unwrap();
+        let message = |text| {
+            Message::new_data(source, Destination::Broadcast, DataType::new_text(text), 3, false)
+        };
+        let (first, second) = (message("first"), message("second"));
+
+        queue.enqueue(first.clone()).unwrap();
+        queue.enqueue_front(second.clone()).unwrap();
+        assert!(queue.enqueue(message("third")).is_err());
+        assert_eq!(MessageQueue::get(&queue, 1), Some(&first));
+        assert_eq!(queue.remaining(), 0);
+
+        assert_eq!(queue.dequeue().unwrap(), second);
+        assert_eq!(queue.dequeue().unwrap(), first);
+        assert!(queue.dequeue().is_err());
+    }
 }
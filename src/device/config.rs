@@ -1,2 +1,4 @@
 pub mod device_config;
+pub mod effective_config;
 pub mod lora_config;
+pub mod mesh_config;
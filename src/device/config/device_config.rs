@@ -1,7 +1,7 @@
 use defmt::Format;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Deserialize, Serialize)]
 pub struct DeviceConfig {
     pub device_class: DeviceClass,
     pub device_capabilities: DeviceCapabilities,
@@ -16,67 +16,96 @@ impl Default for DeviceConfig {
     }
 }
 
+impl DeviceConfig {
+    /// Returns the configuration resulting from applying `requested` remotely.
+    ///
+    /// The class can be changed, but capabilities describe the hardware and are kept.
+    pub fn apply(&self, requested: &DeviceConfig) -> DeviceConfig {
+        DeviceConfig {
+            device_class: requested.device_class,
+            device_capabilities: self.device_capabilities,
+        }
+    }
+}
+
+impl DeviceConfig {
+    pub fn builder() -> DeviceConfigBuilder {
+        DeviceConfigBuilder::default()
+    }
+
+    /// Decodes a capability byte, `None` if it is not one produced by `u8::from`.
+    pub fn from_byte(value: u8) -> Option<Self> {
+        let device_class = match value / 3 {
+            0 => DeviceClass::A,
+            1 => DeviceClass::B,
+            2 => DeviceClass::C,
+            _ => return None,
+        };
+        let device_capabilities = match value % 3 {
+            0 => DeviceCapabilities::Lora,
+            1 => DeviceCapabilities::LoraBle,
+            _ => DeviceCapabilities::LoraWifi,
+        };
+        Some(Self {
+            device_class,
+            device_capabilities,
+        })
+    }
+}
+
+/// Encodes the configuration as the capability byte used in discovery, from 0 to 8.
 impl From<DeviceConfig> for u8 {
     fn from(value: DeviceConfig) -> Self {
-        match (value.device_class, value.device_capabilities) {
-            (DeviceClass::A, DeviceCapabilities::Lora) => 0,
-            (DeviceClass::A, DeviceCapabilities::LoraBle) => 1,
-            (DeviceClass::A, DeviceCapabilities::LoraWifi) => 2,
-            (DeviceClass::B, DeviceCapabilities::Lora) => 3,
-            (DeviceClass::B, DeviceCapabilities::LoraBle) => 4,
-            (DeviceClass::B, DeviceCapabilities::LoraWifi) => 5,
-            (DeviceClass::C, DeviceCapabilities::Lora) => 6,
-            (DeviceClass::C, DeviceCapabilities::LoraBle) => 7,
-            (DeviceClass::C, DeviceCapabilities::LoraWifi) => 8,
-        }
+        let class = match value.device_class {
+            DeviceClass::A => 0,
+            DeviceClass::B => 1,
+            DeviceClass::C => 2,
+        };
+        let capabilities = match value.device_capabilities {
+            DeviceCapabilities::Lora => 0,
+            DeviceCapabilities::LoraBle => 1,
+            DeviceCapabilities::LoraWifi => 2,
+        };
+        class * 3 + capabilities
     }
 }
 
+/// Decodes a capability byte, panicking on bytes above 8; see `DeviceConfig::from_byte`.
 impl From<u8> for DeviceConfig {
     fn from(value: u8) -> Self {
-        match value {
-            0 => Self {
-                device_class: DeviceClass::A,
-                device_capabilities: DeviceCapabilities::Lora,
-            },
-            1 => Self {
-                device_class: DeviceClass::A,
-                device_capabilities: DeviceCapabilities::LoraBle,
-            },
-            2 => Self {
-                device_class: DeviceClass::A,
-                device_capabilities: DeviceCapabilities::LoraWifi,
-            },
-            3 => Self {
-                device_class: DeviceClass::B,
-                device_capabilities: DeviceCapabilities::Lora,
-            },
-            4 => Self {
-                device_class: DeviceClass::B,
-                device_capabilities: DeviceCapabilities::LoraBle,
-            },
-            5 => Self {
-                device_class: DeviceClass::B,
-                device_capabilities: DeviceCapabilities::LoraWifi,
-            },
-            6 => Self {
-                device_class: DeviceClass::C,
-                device_capabilities: DeviceCapabilities::Lora,
-            },
-            7 => Self {
-                device_class: DeviceClass::C,
-                device_capabilities: DeviceCapabilities::LoraBle,
-            },
-            8 => Self {
-                device_class: DeviceClass::C,
-                device_capabilities: DeviceCapabilities::LoraWifi,
-            },
-            _ => panic!("Invalid device config"),
-        }
+        Self::from_byte(value).expect("Invalid device config")
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+/// Builds a `DeviceConfig`, requiring the capabilities to be stated since they describe
+/// the hardware. The class defaults to `DeviceClass::A`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DeviceConfigBuilder {
+    device_class: Option<DeviceClass>,
+    device_capabilities: Option<DeviceCapabilities>,
+}
+
+impl DeviceConfigBuilder {
+    pub fn class(mut self, device_class: DeviceClass) -> Self {
+        self.device_class = Some(device_class);
+        self
+    }
+
+    pub fn capabilities(mut self, device_capabilities: DeviceCapabilities) -> Self {
+        self.device_capabilities = Some(device_capabilities);
+        self
+    }
+
+    /// Returns the configuration, or `None` if the capabilities were not set.
+    pub fn build(self) -> Option<DeviceConfig> {
+        Some(DeviceConfig {
+            device_class: self.device_class.unwrap_or(DeviceClass::A),
+            device_capabilities: self.device_capabilities?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Deserialize, Serialize)]
 pub enum DeviceClass {
     A,
     B,
@@ -89,3 +118,65 @@ pub enum DeviceCapabilities {
     LoraBle,
     LoraWifi,
 }
+
+#[cfg(test)]
+mod test {
+    use crate::device::config::device_config::{DeviceCapabilities, DeviceClass, DeviceConfig};
+
+    #[test]
+    fn test_apply_keeps_hardware_capabilities() {
+        let current = DeviceConfig {
+            device_class: DeviceClass::A,
+            device_capabilities: DeviceCapabilities::Lora,
+        };
+        let requested = DeviceConfig {
+            device_class: DeviceClass::C,
+            device_capabilities: DeviceCapabilities::LoraWifi,
+        };
+
+        let applied = current.apply(&requested);
+
+        assert_eq!(applied.device_class, DeviceClass::C);
+        assert_eq!(applied.device_capabilities, DeviceCapabilities::Lora);
+    }
+
+    #[test]
+    fn test_capability_byte_round_trips() {
+        let classes = [DeviceClass::A, DeviceClass::B, DeviceClass::C];
+        let capabilities = [
+            DeviceCapabilities::Lora,
+            DeviceCapabilities::LoraBle,
+            DeviceCapabilities::LoraWifi,
+        ];
+        let mut bytes = heapless::Vec::<u8, 9>::new();
+        for device_class in classes {
+            for device_capabilities in capabilities {
+                let config = DeviceConfig {
+                    device_class,
+                    device_capabilities,
+                };
+                let byte = u8::from(config);
+
+                assert_eq!(DeviceConfig::from(byte), config);
+                bytes.push(byte).unwrap();
+            }
+        }
+
+        assert_eq!(bytes.as_slice(), &[0, 1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(DeviceConfig::from_byte(9), None);
+        assert_eq!(u8::from(DeviceConfig::default()), 0);
+    }
+
+    #[test]
+    fn test_builder_requires_capabilities() {
+        assert_eq!(DeviceConfig::builder().class(DeviceClass::B).build(), None);
+
+        let config = DeviceConfig::builder()
+            .capabilities(DeviceCapabilities::LoraBle)
+            .build()
+            .unwrap();
+
+        assert_eq!(config.device_class, DeviceClass::A);
+        assert_eq!(config.device_capabilities, DeviceCapabilities::LoraBle);
+    }
+}
@@ -0,0 +1,296 @@
+use defmt::Format;
+use serde::{Deserialize, Serialize};
+
+use crate::device::config::device_config::DeviceConfig;
+use crate::device::config::lora_config::{LoraConfig, LORA_FREQUENCY_IN_HZ};
+use crate::device::config::mesh_config::{
+    AckMode, DeliveryPolicy, DiscoveryBurst, MeshConfig, Throughput,
+};
+use crate::message::payload::data::TextDecoding;
+use crate::message::payload::PayloadKinds;
+use crate::route::{RoutePolicy, ROUTE_TTL};
+
+/// Every setting in effect on a device, gathered in one place for field support.
+///
+/// Durations are in milliseconds so the whole summary can be serialized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub struct EffectiveConfig {
+    pub uid: u8,
+    pub device: DeviceConfig,
+    pub radio: RadioSettings,
+    pub mesh: MeshSettings,
+    /// Whether messages of other nodes are forwarded
+    pub relaying: bool,
+    /// Whether every decoded frame is captured instead of handled
+    pub promiscuous: bool,
+    /// Whether the crate was built with 16-bit message IDs
+    pub short_message_id: bool,
+}
+
+/// Radio settings of a `LoraConfig`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub struct RadioSettings {
+    pub frequency_hz: u32,
+    pub tx_power: i32,
+    pub boosted: bool,
+    pub rx_window_ms: u64,
+    /// Frequency of the control channel, if any
+    pub control_frequency_hz: Option<u32>,
+    pub control_window_ms: Option<u64>,
+}
+
+impl From<&LoraConfig> for RadioSettings {
+    fn from(config: &LoraConfig) -> Self {
+        Self {
+            frequency_hz: LORA_FREQUENCY_IN_HZ,
+            tx_power: config.tx_power,
+            boosted: config.boosted,
+            rx_window_ms: config.rx_window.as_millis(),
+            control_frequency_hz: config.control.as_ref().map(|control| control.frequency_in_hz),
+            control_window_ms: config.control.as_ref().map(|control| control.window.as_millis()),
+        }
+    }
+}
+
+/// Rate of a `Throughput`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub struct ThroughputSettings {
+    pub messages: u32,
+    pub period_ms: u64,
+}
+
+impl From<&Throughput> for ThroughputSettings {
+    fn from(throughput: &Throughput) -> Self {
+        Self {
+            messages: throughput.messages,
+            period_ms: throughput.period.as_millis(),
+        }
+    }
+}
+
+/// Burst of a `DiscoveryBurst`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub struct BurstSettings {
+    pub count: u8,
+    pub spacing_ms: u64,
+}
+
+impl From<&DiscoveryBurst> for BurstSettings {
+    fn from(burst: &DiscoveryBurst) -> Self {
+        Self {
+            count: burst.count,
+            spacing_ms: burst.spacing.as_millis(),
+        }
+    }
+}
+
+/// Mesh settings of a `MeshConfig`, along with the timings fixed at build time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub struct MeshSettings {
+    /// Whether route lookup misses trigger a discovery
+    pub reactive_discovery: bool,
+    /// Interval of the periodic discovery, if any
+    pub discovery_interval_ms: Option<u64>,
+    pub max_ring_ttl: u8,
+    pub max_queued_relays: u32,
+    pub max_startup_jitter_ms: u64,
+    pub route_save_interval_ms: u64,
+    pub route_policy: RoutePolicy,
+    pub route_ttl_ms: u64,
+    pub cleanup_interval_ms: u64,
+    pub max_message_age_ms: Option<u64>,
+    pub max_throughput: Option<ThroughputSettings>,
+    pub reorder_timeout_ms: Option<u64>,
+    pub route_ttl_margin: Option<u8>,
+    pub reliable_discovery_acks: bool,
+    pub route_events: bool,
+    pub decode_failure_events: bool,
+    pub rediscover_on_failure: bool,
+    pub boot_discovery_burst: Option<BurstSettings>,
+    pub relay_known_only: bool,
+    pub ack_route_hints: bool,
+    pub max_discoveries_in_flight: Option<u32>,
+    pub delivery_policy: DeliveryPolicy,
+    pub fail_unreachable_after: Option<u8>,
+    pub retry_ttl_step: Option<u8>,
+    pub echo_window_ms: u64,
+    pub ack_mode: AckMode,
+    pub local_payloads: PayloadKinds,
+    pub reserved_relay_slots: u32,
+    pub relay_budget: Option<ThroughputSettings>,
+    pub text_decoding: TextDecoding,
+}
+
+impl From<&MeshConfig> for MeshSettings {
+    fn from(config: &MeshConfig) -> Self {
+        Self {
+            reactive_discovery: config.discovery_strategy.is_reactive(),
+            discovery_interval_ms: config.discovery_strategy.interval().map(|i| i.as_millis()),
+            max_ring_ttl: config.max_ring_ttl,
+            max_queued_relays: config.max_queued_relays as u32,
+            max_startup_jitter_ms: config.max_startup_jitter.as_millis(),
+            route_save_interval_ms: config.route_save_interval.as_millis(),
+            route_policy: config.route_policy,
+            route_ttl_ms: ROUTE_TTL.as_millis(),
+            cleanup_interval_ms: config.cleanup_interval.as_millis(),
+            max_message_age_ms: config.max_message_age.map(|age| age.as_millis()),
+            max_throughput: config.max_throughput.as_ref().map(ThroughputSettings::from),
+            reorder_timeout_ms: config.reorder_timeout.map(|timeout| timeout.as_millis()),
+            route_ttl_margin: config.route_ttl_margin,
+            reliable_discovery_acks: config.reliable_discovery_acks,
+            route_events: config.route_events,
+            decode_failure_events: config.decode_failure_events,
+            rediscover_on_failure: config.rediscover_on_failure,
+            boot_discovery_burst: config.boot_discovery_burst.as_ref().map(BurstSettings::from),
+            relay_known_only: config.relay_known_only,
+            ack_route_hints: config.ack_route_hints,
+            max_discoveries_in_flight: config.max_discoveries_in_flight.map(|max| max as u32),
+            delivery_policy: config.delivery_policy,
+            fail_unreachable_after: config.fail_unreachable_after,
+            retry_ttl_step: config.retry_ttl_step,
+            echo_window_ms: config.echo_window.as_millis(),
+            ack_mode: config.ack_mode,
+            local_payloads: config.local_payloads,
+            reserved_relay_slots: config.reserved_relay_slots as u32,
+            relay_budget: config.relay_budget.as_ref().map(ThroughputSettings::from),
+            text_decoding: config.text_decoding,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Duration;
+
+    use crate::device::config::effective_config::{
+        BurstSettings, MeshSettings, ThroughputSettings,
+    };
+    use crate::device::config::mesh_config::{
+        AckMode, DeliveryPolicy, DiscoveryBurst, DiscoveryStrategy, Handling, MeshConfig,
+        Throughput,
+    };
+    use crate::message::payload::data::TextDecoding;
+    use crate::message::payload::PayloadKinds;
+    use crate::route::{RouteEviction, RoutePolicy, StaleRoutes, ROUTE_TTL};
+
+    #[test]
+    fn test_mesh_settings_mirror_config() {
+        // Every field differs from its default, so a field left unmirrored is caught
+        let config = MeshConfig {
+            discovery_strategy: DiscoveryStrategy::Proactive {
+                interval: Duration::from_secs(30),
+            },
+            max_ring_ttl: 4,
+            max_queued_relays: 12,
+            max_startup_jitter: Duration::from_secs(2),
+            route_save_interval: Duration::from_secs(60),
+            route_policy: RoutePolicy {
+                hop_weight: 5,
+                quality_weight: 2,
+                eviction: RouteEviction::LeastRecent,
+                initial_quality: 50,
+                stale_routes: StaleRoutes::Use,
+            },
+            cleanup_interval: Duration::from_secs(5),
+            max_message_age: Some(Duration::from_secs(20)),
+            max_throughput: Some(Throughput::per_second(4)),
+            reliable_discovery_acks: true,
+            route_events: true,
+            reorder_timeout: Some(Duration::from_millis(800)),
+            decode_failure_events: true,
+            route_ttl_margin: Some(1),
+            rediscover_on_failure: true,
+            boot_discovery_burst: Some(DiscoveryBurst {
+                count: 3,
+                spacing: Duration::from_secs(1),
+            }),
+            relay_known_only: true,
+            ack_route_hints: true,
+            max_discoveries_in_flight: Some(2),
+            delivery_policy: DeliveryPolicy {
+                unicast: Handling::DELIVER_AND_RELAY,
+                ..DeliveryPolicy::default()
+            },
+            fail_unreachable_after: Some(3),
+            retry_ttl_step: Some(1),
+            echo_window: Duration::from_millis(250),
+            ack_mode: AckMode::NoRetries,
+            local_payloads: PayloadKinds::DATA,
+            relay_budget: Some(Throughput::per_second(2)),
+            reserved_relay_slots: 4,
+            text_decoding: TextDecoding::Lossy,
+        };
+
+        let settings = MeshSettings::from(&config);
+
+        // No rest pattern: a field added to `MeshConfig` fails to compile here until it is
+        // mirrored and checked
+        let MeshConfig {
+            discovery_strategy,
+            max_ring_ttl,
+            max_queued_relays,
+            max_startup_jitter,
+            route_save_interval,
+            route_policy,
+            cleanup_interval,
+            max_message_age,
+            max_throughput,
+            reliable_discovery_acks,
+            route_events,
+            reorder_timeout,
+            decode_failure_events,
+            route_ttl_margin,
+            rediscover_on_failure,
+            boot_discovery_burst,
+            relay_known_only,
+            ack_route_hints,
+            max_discoveries_in_flight,
+            delivery_policy,
+            fail_unreachable_after,
+            retry_ttl_step,
+            echo_window,
+            ack_mode,
+            local_payloads,
+            relay_budget,
+            reserved_relay_slots,
+            text_decoding,
+        } = config;
+        let expected = MeshSettings {
+            reactive_discovery: discovery_strategy.is_reactive(),
+            discovery_interval_ms: Some(30_000),
+            max_ring_ttl,
+            max_queued_relays: max_queued_relays as u32,
+            max_startup_jitter_ms: max_startup_jitter.as_millis(),
+            route_save_interval_ms: route_save_interval.as_millis(),
+            route_policy,
+            route_ttl_ms: ROUTE_TTL.as_millis(),
+            cleanup_interval_ms: cleanup_interval.as_millis(),
+            max_message_age_ms: max_message_age.map(|age| age.as_millis()),
+            max_throughput: Some(ThroughputSettings { messages: 4, period_ms: 1_000 }),
+            reorder_timeout_ms: reorder_timeout.map(|timeout| timeout.as_millis()),
+            route_ttl_margin,
+            reliable_discovery_acks,
+            route_events,
+            decode_failure_events,
+            rediscover_on_failure,
+            boot_discovery_burst: Some(BurstSettings { count: 3, spacing_ms: 1_000 }),
+            relay_known_only,
+            ack_route_hints,
+            max_discoveries_in_flight: max_discoveries_in_flight.map(|max| max as u32),
+            delivery_policy,
+            fail_unreachable_after,
+            retry_ttl_step,
+            echo_window_ms: echo_window.as_millis(),
+            ack_mode,
+            local_payloads,
+            reserved_relay_slots: reserved_relay_slots as u32,
+            relay_budget: Some(ThroughputSettings { messages: 2, period_ms: 1_000 }),
+            text_decoding,
+        };
+        assert_eq!(max_throughput, Some(Throughput::per_second(4)));
+        assert_eq!(relay_budget, Some(Throughput::per_second(2)));
+        assert!(boot_discovery_burst.is_some());
+        assert_eq!(settings, expected);
+        assert_ne!(settings, MeshSettings::from(&MeshConfig::default()));
+    }
+}
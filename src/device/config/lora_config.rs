@@ -1,3 +1,4 @@
+use embassy_time::{Duration, Instant};
 use embedded_hal_async::delay::DelayNs;
 use lora_phy::LoRa;
 use lora_phy::mod_params::{
@@ -5,8 +6,21 @@ use lora_phy::mod_params::{
 };
 use lora_phy::mod_traits::RadioKind;
 
+use crate::device::Uid;
+use crate::message::payload::ack::AckType;
+use crate::message::payload::command::CommandType;
+use crate::message::payload::Payload;
+use crate::message::Message;
+
 pub const LORA_FREQUENCY_IN_HZ: u32 = 433_220_000;
 const TX_POWER: i32 = 20;
+const RX_WINDOW_MS: u64 = 10_000;
+/// Listen window on the control channel following a transmission awaiting an answer.
+const CONTROL_WINDOW_MS: u64 = 2_000;
+/// Air time of a LoRa symbol, 2^10 chips at 125 kHz, in microseconds.
+const SYMBOL_TIME_US: u64 = 8_192;
+/// Extra time the host waits for the radio to report its own RX timeout before aborting.
+const RX_HOST_GUARD: Duration = Duration::from_millis(500);
 
 pub struct LoraConfig {
     pub tx_power: i32,
@@ -14,6 +28,105 @@ pub struct LoraConfig {
     pub rx_pkt_params: PacketParams,
     pub tx_pkt_params: PacketParams,
     pub boosted: bool,
+    /// Listen window of a single receive.
+    ///
+    /// The radio enforces it with `RxMode::Single`, whose timeout is a number of symbols,
+    /// up to `u16::MAX` of them. Radios with a shorter symbol timeout clamp it and end the
+    /// receive early, the next cycle listening again. The host only aborts the receive
+    /// `RX_HOST_GUARD` later, should the radio never report back, so the radio window is
+    /// always used in full.
+    pub rx_window: Duration,
+    /// Control channel on a second frequency, see `Channel`.
+    ///
+    /// `None` keeps every message on the data channel.
+    pub control: Option<ControlChannel>,
+}
+
+/// Second frequency, carrying the immediate answers to a frame, see `Channel`.
+pub struct ControlChannel {
+    pub frequency_in_hz: u32,
+    pub modulation: ModulationParams,
+    /// How long the device listens on the control channel right after a transmission
+    /// awaiting an answer
+    pub window: Duration,
+}
+
+/// Channel a message is sent on when a control channel is configured.
+///
+/// Nodes share no clock to agree on when to listen to which channel, so the control channel
+/// only carries what a node sends right away to a direct neighbor it just heard: transport
+/// acks, pongs and configuration replies. The neighbor listens there right after sending a
+/// frame that awaits such an answer, and goes back to the data channel once it is heard.
+///
+/// Everything else goes on the data channel, where nodes listen the rest of the time.
+/// This includes discoveries and commands, which nobody is waiting for on the control
+/// channel. It also includes forwarded acks, application receipts and discovery responses,
+/// which come back long after their receiver last transmitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    /// Every message but the immediate answers
+    Data,
+    /// Immediate answers, kept off the congested data channel
+    Control,
+}
+
+impl Channel {
+    /// Channel a message is sent on at `now`, given the transmitter and time of the last
+    /// unicast frame heard.
+    ///
+    /// Only an answer to that transmitter within `control_window` goes on the control
+    /// channel, while it still listens there.
+    pub fn for_message(
+        message: &Message,
+        last_heard: Option<(Uid, Instant)>,
+        now: Instant,
+        control_window: Duration,
+    ) -> Self {
+        let answer = matches!(
+            message.payload(),
+            Payload::Ack(
+                AckType::Success { .. }
+                    | AckType::SuccessWithRoute { .. }
+                    | AckType::Pong { .. }
+                    | AckType::ConfigApplied { .. }
+            )
+        );
+        match last_heard {
+            Some((neighbor, heard_at))
+                if answer
+                    && message.destination_id() == Some(neighbor)
+                    && now.saturating_duration_since(heard_at) < control_window =>
+            {
+                Channel::Control
+            }
+            _ => Channel::Data,
+        }
+    }
+
+    /// Whether the sender of `message` listens on the control channel once it is sent, for
+    /// the answer of its receiver: an ack or a pong.
+    pub fn awaits_answer(message: &Message) -> bool {
+        message.destination_id().is_some()
+            && (message.req_ack()
+                || matches!(message.payload(), Payload::Command(CommandType::Ping)))
+    }
+
+    /// Channel to listen on at `now` and for how long, given when the last transmission
+    /// awaiting an answer ended: the control channel for what is left of `control_window`,
+    /// the data channel for `rx_window` otherwise.
+    pub fn to_listen(
+        now: Instant,
+        awaiting_since: Option<Instant>,
+        control_window: Duration,
+        rx_window: Duration,
+    ) -> (Self, Duration) {
+        match awaiting_since.map(|sent_at| now.saturating_duration_since(sent_at)) {
+            Some(elapsed) if elapsed < control_window => {
+                (Channel::Control, control_window - elapsed)
+            }
+            _ => (Channel::Data, rx_window),
+        }
+    }
 }
 
 impl LoraConfig {
@@ -22,7 +135,8 @@ impl LoraConfig {
         RK: RadioKind,
         DLY: DelayNs,
     {
-        let modulation = modulation_params(lora).expect("Failed to create modulation params");
+        let modulation = modulation_params(lora, LORA_FREQUENCY_IN_HZ)
+            .expect("Failed to create modulation params");
 
         let tx_pkt_params =
             create_tx_packet(lora, &modulation).expect("Failed to create TX packet params");
@@ -36,20 +150,100 @@ impl LoraConfig {
             rx_pkt_params,
             tx_pkt_params,
             boosted: false,
+            rx_window: Duration::from_millis(RX_WINDOW_MS),
+            control: None,
+        }
+    }
+
+    /// Moves the immediate answers to a frame to `frequency_in_hz`, keeping the data channel
+    /// modulation otherwise. The device then listens on that frequency right after each
+    /// transmission awaiting an answer.
+    pub fn with_control_channel<RK, DLY>(
+        mut self,
+        lora: &mut LoRa<RK, DLY>,
+        frequency_in_hz: u32,
+    ) -> Result<Self, RadioError>
+    where
+        RK: RadioKind,
+        DLY: DelayNs,
+    {
+        self.control = Some(ControlChannel {
+            frequency_in_hz,
+            modulation: modulation_params(lora, frequency_in_hz)?,
+            window: Duration::from_millis(CONTROL_WINDOW_MS),
+        });
+        Ok(self)
+    }
+
+    /// Modulation of `channel`, the data channel one when no control channel is set.
+    pub fn channel_modulation(&self, channel: Channel) -> &ModulationParams {
+        match (channel, &self.control) {
+            (Channel::Control, Some(control)) => &control.modulation,
+            _ => &self.modulation,
+        }
+    }
+
+    /// Channel of the next listen window at `now` and its length, given when the last
+    /// transmission awaiting an answer ended.
+    pub fn listen_window(
+        &self,
+        now: Instant,
+        awaiting_since: Option<Instant>,
+    ) -> (Channel, Duration) {
+        match &self.control {
+            Some(control) => {
+                Channel::to_listen(now, awaiting_since, control.window, self.rx_window)
+            }
+            None => (Channel::Data, self.rx_window),
+        }
+    }
+
+    /// Channel `message` is sent on at `now`, the data channel when no control channel is
+    /// set, see `Channel::for_message`.
+    pub fn send_channel(
+        &self,
+        message: &Message,
+        last_heard: Option<(Uid, Instant)>,
+        now: Instant,
+    ) -> Channel {
+        match &self.control {
+            Some(control) => Channel::for_message(message, last_heard, now, control.window),
+            None => Channel::Data,
         }
     }
+
+    /// Timeout handed to the radio, in symbols.
+    pub fn radio_rx_timeout(&self) -> u16 {
+        rx_timeouts(self.rx_window).0
+    }
+
+    /// Timeout after which the host aborts a receive the radio did not end.
+    pub fn host_rx_timeout(&self) -> Duration {
+        rx_timeouts(self.rx_window).1
+    }
+}
+
+/// Splits a listen window into the radio timeout, in symbols, and the strictly longer host
+/// timeout.
+pub fn rx_timeouts(window: Duration) -> (u16, Duration) {
+    let radio = (window.as_micros() / SYMBOL_TIME_US).min(u16::MAX as u64) as u16;
+    (radio, Duration::from_micros(radio as u64 * SYMBOL_TIME_US) + RX_HOST_GUARD)
 }
 
-fn modulation_params<RK, DLY>(lora: &mut LoRa<RK, DLY>) -> Result<ModulationParams, RadioError>
+fn modulation_params<RK, DLY>(
+    lora: &mut LoRa<RK, DLY>,
+    frequency_in_hz: u32,
+) -> Result<ModulationParams, RadioError>
 where
     RK: RadioKind,
     DLY: DelayNs,
 {
+    // Keep `SYMBOL_TIME_US` in line with these
     lora.create_modulation_params(
         SpreadingFactor::_10,
         Bandwidth::_125KHz,
         CodingRate::_4_8,
-        LORA_FREQUENCY_IN_HZ,
+        frequency_in_hz,
     )
 }
 
@@ -74,3 +268,92 @@ where
 {
     lora.create_tx_packet_params(8, false, true, false, mdltn_params)
 }
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use crate::device::config::lora_config::{rx_timeouts, Channel, SYMBOL_TIME_US};
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::payload::ack::AckType;
+    use crate::message::payload::command::CommandType;
+    use crate::message::payload::data::DataType;
+    use crate::message::payload::route::RouteType;
+    use crate::message::Message;
+
+    #[test]
+    fn test_host_timeout_outlasts_radio_window() {
+        let symbols = |count: u16| Duration::from_micros(count as u64 * SYMBOL_TIME_US);
+
+        // 8.192 ms per symbol at SF10 and 125 kHz
+        let (radio, host) = rx_timeouts(Duration::from_millis(1000));
+
+        assert_eq!(radio, 122);
+        assert!(symbols(radio) <= Duration::from_millis(1000));
+        assert!(host > symbols(radio));
+
+        let (radio, host) = rx_timeouts(Duration::from_secs(600));
+
+        assert_eq!(radio, u16::MAX);
+        assert!(host > symbols(u16::MAX));
+    }
+
+    #[test]
+    fn test_only_immediate_answers_use_the_control_channel() {
+        let control_window = Duration::from_secs(2);
+        let rx_window = Duration::from_secs(10);
+        let at = Instant::from_millis;
+        let uid = |uid: u8| Uid::try_from(uid).unwrap();
+        let (sender, relay, receiver) = (uid(1), uid(2), uid(3));
+        // Two-channel medium: a frame is only heard by the nodes listening on its channel, the
+        // one a node listens on depending on when it last sent a frame awaiting an answer
+        let listening = |now: u64, awaiting_since: Option<u64>| {
+            Channel::to_listen(at(now), awaiting_since.map(at), control_window, rx_window).0
+        };
+        // while the channel of a frame depends on the last frame its transmitter heard
+        let sent_on = |message: &Message, last_heard: Option<(Uid, u64)>, now: u64| {
+            let last_heard = last_heard.map(|(uid, heard_at)| (uid, at(heard_at)));
+            Channel::for_message(message, last_heard, at(now), control_window)
+        };
+
+        // The relay sends a reading at 0 ms, then listens for its ack on the control channel
+        let text = DataType::new_text("reading");
+        let reading = Message::new_data(relay, Destination::Unicast(receiver), text, 3, true);
+        assert!(Channel::awaits_answer(&reading));
+        assert_eq!(sent_on(&reading, None, 0), listening(0, None));
+        let success = AckType::Success { message_id: reading.message_id() };
+        let ack = Message::new_ack(receiver, Destination::Unicast(relay), success, 3, false);
+        assert_eq!(sent_on(&ack, Some((relay, 0)), 500), listening(500, Some(0)));
+        assert_eq!(listening(500, Some(0)), Channel::Control);
+        // Sending the ack awaits nothing, the receiver stays on the data channel
+        assert!(!Channel::awaits_answer(&ack));
+        // An answer sent once the relay is back on the data channel goes there too
+        assert_eq!(sent_on(&ack, Some((relay, 0)), 2_500), listening(2_500, Some(0)));
+
+        // The relay forwards the ack of the receiver to the sender, which sent long ago
+        let forwarded = Message::new_ack(relay, Destination::Unicast(sender), success, 2, false);
+        assert_eq!(sent_on(&forwarded, Some((receiver, 500)), 600), Channel::Data);
+        assert_eq!(listening(600, None), Channel::Data);
+        // The receiver confirms its application processed the reading, which takes a while
+        let receipt = AckType::AppReceipt { message_id: reading.message_id() };
+        let receipt = Message::new_ack(receiver, Destination::Unicast(relay), receipt, 3, false);
+        assert_eq!(sent_on(&receipt, Some((relay, 0)), 1_000), Channel::Data);
+        assert_eq!(listening(6_000, Some(0)), Channel::Data);
+
+        // A ping is answered on the control channel, a discovery awaits nobody there
+        let ping = CommandType::Ping;
+        let ping = Message::new_command(sender, Destination::Unicast(relay), ping, 1, false);
+        assert!(Channel::awaits_answer(&ping));
+        let pong = AckType::Pong { message_id: ping.message_id() };
+        let pong = Message::new_ack(relay, Destination::Unicast(sender), pong, 1, false);
+        assert_eq!(sent_on(&pong, Some((sender, 7_000)), 7_200), Channel::Control);
+        let request = RouteType::Request;
+        let discovery = Message::new_route(sender, Destination::Broadcast, request, 3, true);
+        assert!(!Channel::awaits_answer(&discovery));
+        assert_eq!(sent_on(&discovery, Some((relay, 7_000)), 7_200), Channel::Data);
+
+        let (channel, window) = Channel::to_listen(at(500), Some(at(0)), control_window, rx_window);
+        assert_eq!((channel, window), (Channel::Control, Duration::from_millis(1_500)));
+    }
+}
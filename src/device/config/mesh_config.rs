@@ -0,0 +1,454 @@
+use defmt::Format;
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+use crate::message::destination::Destination;
+use crate::message::payload::data::TextDecoding;
+use crate::message::payload::{Payload, PayloadKinds};
+use crate::route::RoutePolicy;
+
+/// Interval between two full discoveries when discovery runs periodically.
+pub const DISCOVERY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Runtime tunables of the mesh layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct MeshConfig {
+    pub discovery_strategy: DiscoveryStrategy,
+    /// Widest ring tried by an expanding-ring route discovery
+    pub max_ring_ttl: u8,
+    /// Broadcast relays allowed in the outqueue at once, so relaying cannot starve
+    /// the device's own traffic
+    pub max_queued_relays: usize,
+    /// Upper bound of the random delay before the first discovery, spreading the
+    /// traffic of nodes powered up together
+    pub max_startup_jitter: Duration,
+    /// Minimum time between two saves of the routing table to the route store
+    pub route_save_interval: Duration,
+    /// Weights used to compare routes
+    pub route_policy: RoutePolicy,
+    /// Minimum time between two cleanups of stale routes, zero to clean up on every loop
+    pub cleanup_interval: Duration,
+    /// Time after which a message waiting on this device is dropped instead of sent,
+    /// whatever its TTL, or `None` to keep messages until sent
+    pub max_message_age: Option<Duration>,
+    /// Cap on the messages transmitted by the device, or `None` for no cap
+    pub max_throughput: Option<Throughput>,
+    /// Whether discovery responses request an ack and are retried until acknowledged,
+    /// converging faster on lossy links at the cost of more control traffic
+    pub reliable_discovery_acks: bool,
+    /// Whether every route removal raises a `DeviceEvent::RouteRemoved`
+    pub route_events: bool,
+    /// How long sequenced messages arriving ahead of their predecessors from the same
+    /// source are held to be delivered in order, or `None` to deliver them as they come
+    pub reorder_timeout: Option<Duration>,
+    /// Whether every received frame that fails to decode raises a `DeviceEvent::DecodeFailed`
+    pub decode_failure_events: bool,
+    /// Spare hops added to the route hop count when setting the TTL of unicast messages
+    /// queued by the application, or `None` to keep the TTL they were created with.
+    /// Destinations without a known route get the maximum TTL.
+    pub route_ttl_margin: Option<u8>,
+    /// Whether a destination that stopped acknowledging loses its route and is
+    /// rediscovered, instead of waiting for the application to notice
+    pub rediscover_on_failure: bool,
+    /// Discoveries sent right after startup to populate the routing table quickly, before
+    /// the periodic cadence of `discovery_strategy` takes over, or `None` for a single one
+    pub boot_discovery_burst: Option<DiscoveryBurst>,
+    /// Whether unicast messages of other nodes are only relayed to destinations with a
+    /// known route, dropping the others without discovering a route on their behalf
+    pub relay_known_only: bool,
+    /// Whether acks carry a route hint seeding the sender's route back to this device,
+    /// at the cost of two extra bytes per ack
+    pub ack_route_hints: bool,
+    /// Cap on route discoveries queued or awaiting their ack at once, keeping room in the
+    /// pending ack table for data, or `None` for no cap
+    pub max_discoveries_in_flight: Option<usize>,
+    /// Whether broadcast, group and unicast messages to this device are delivered,
+    /// relayed, or both
+    pub delivery_policy: DeliveryPolicy,
+    /// Attempts after which a pending ack is failed when its destination has no route
+    /// and no discovery in flight, freeing its slot before all attempts are spent, or
+    /// `None` to always retry up to the maximum
+    pub fail_unreachable_after: Option<u8>,
+    /// Hops added to the TTL on every retry of a message awaiting its ack, up to the
+    /// maximum TTL, for destinations farther than first assumed, or `None` to retry with
+    /// the original TTL
+    pub retry_ttl_step: Option<u8>,
+    /// How long after a transmission a received copy of the transmitted frame is taken for
+    /// an echo of the shared medium and ignored, zero to process every frame
+    pub echo_window: Duration,
+    /// Whether messages requesting an ack are tracked and retried, and whether received
+    /// ones are acknowledged
+    pub ack_mode: AckMode,
+    /// Payload kinds handled by this device, by its mesh layer, such as clearing pending
+    /// acks or answering discoveries, and by the application. Messages of the other kinds
+    /// are only relayed, sparing specialized nodes the work of handling them
+    pub local_payloads: PayloadKinds,
+    /// Cap on the messages of other nodes relayed by this device, whatever their TTL,
+    /// protecting a well placed relay from overwork, or `None` for no cap
+    pub relay_budget: Option<Throughput>,
+    /// Outqueue slots that messages queued by the application cannot take, keeping room
+    /// for broadcast relays: a message the device fails to relay is lost to the nodes
+    /// beyond it, while the application can queue again later
+    pub reserved_relay_slots: usize,
+    /// How received text payloads containing invalid UTF-8 are handled
+    pub text_decoding: TextDecoding,
+}
+
+impl Default for MeshConfig {
+    fn default() -> Self {
+        Self {
+            discovery_strategy: DiscoveryStrategy::Hybrid,
+            max_ring_ttl: 3,
+            max_queued_relays: 24,
+            max_startup_jitter: Duration::from_secs(5),
+            route_save_interval: Duration::from_secs(600),
+            route_policy: RoutePolicy::default(),
+            cleanup_interval: Duration::from_secs(0),
+            max_message_age: None,
+            max_throughput: None,
+            reliable_discovery_acks: false,
+            route_events: false,
+            reorder_timeout: None,
+            decode_failure_events: false,
+            route_ttl_margin: None,
+            rediscover_on_failure: false,
+            boot_discovery_burst: None,
+            relay_known_only: false,
+            ack_route_hints: false,
+            max_discoveries_in_flight: None,
+            delivery_policy: DeliveryPolicy::default(),
+            fail_unreachable_after: None,
+            retry_ttl_step: None,
+            echo_window: Duration::from_millis(500),
+            ack_mode: AckMode::Reliable,
+            local_payloads: PayloadKinds::ALL,
+            relay_budget: None,
+            reserved_relay_slots: 0,
+            text_decoding: TextDecoding::Strict,
+        }
+    }
+}
+
+impl MeshConfig {
+    /// Whether this device handles `payload`, instead of only relaying it.
+    pub fn processes(&self, payload: &Payload) -> bool {
+        self.local_payloads.contains(payload)
+    }
+
+    /// Whether a relayed message without a route starts a discovery of its destination.
+    pub fn discovers_for_relays(&self) -> bool {
+        self.discovery_strategy.is_reactive() && !self.relay_known_only
+    }
+
+    /// Whether a message of the application may be queued with `remaining` free slots in
+    /// the outqueue.
+    pub fn admits_own_message(&self, remaining: usize) -> bool {
+        remaining > self.reserved_relay_slots
+    }
+
+    /// Whether a route discovery may start with `in_flight` discoveries outstanding.
+    pub fn allows_discovery(&self, in_flight: usize) -> bool {
+        match self.max_discoveries_in_flight {
+            Some(max) => in_flight < max,
+            None => true,
+        }
+    }
+}
+
+/// What the device does with a received message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub struct Handling {
+    /// Hand the message over to the application
+    pub deliver: bool,
+    /// Send the message on to the other nodes
+    pub relay: bool,
+}
+
+impl Handling {
+    pub const DELIVER_AND_RELAY: Self = Self { deliver: true, relay: true };
+    pub const DELIVER: Self = Self { deliver: true, relay: false };
+    pub const RELAY: Self = Self { deliver: false, relay: true };
+    pub const IGNORE: Self = Self { deliver: false, relay: false };
+}
+
+/// Handling of every kind of destination the device receives for.
+///
+/// Unicast messages to other nodes are not covered, they are routed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub struct DeliveryPolicy {
+    /// Unicast messages addressed to the device, `DELIVER_AND_RELAY` flooding them on to
+    /// the other nodes sharing its address, as with anycast
+    pub unicast: Handling,
+    pub broadcast: Handling,
+    /// Groups the device is subscribed to
+    pub subscribed_group: Handling,
+    /// Groups the device is not subscribed to, `RELAY` making it a relay for them
+    pub other_group: Handling,
+}
+
+impl Default for DeliveryPolicy {
+    fn default() -> Self {
+        Self {
+            unicast: Handling::DELIVER,
+            broadcast: Handling::DELIVER_AND_RELAY,
+            subscribed_group: Handling::DELIVER_AND_RELAY,
+            other_group: Handling::IGNORE,
+        }
+    }
+}
+
+impl DeliveryPolicy {
+    /// Handling of a message to `destination`, unicast ones being addressed to the device.
+    pub fn handling(&self, destination: Destination, subscribed: bool) -> Handling {
+        match destination {
+            Destination::Unicast(_) => self.unicast,
+            Destination::Broadcast => self.broadcast,
+            Destination::Group(_) if subscribed => self.subscribed_group,
+            Destination::Group(_) => self.other_group,
+        }
+    }
+}
+
+/// Transport acknowledgement behavior of the device, for deployments where retransmits
+/// waste more airtime than lost messages are worth.
+///
+/// Pongs and discovery responses are still sent, as routing and diagnostics depend on
+/// them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub enum AckMode {
+    /// Messages requesting an ack are retried until acknowledged, and acknowledged on receipt
+    Reliable,
+    /// Sent messages are never retried and go out without requesting an ack, but received
+    /// ones are still acknowledged for the nodes relying on acks
+    NoRetries,
+    /// Sent messages are never retried and received ones never acknowledged
+    Off,
+}
+
+impl AckMode {
+    /// Whether a sent message requesting an ack waits for it and is retried.
+    pub fn retries(&self) -> bool {
+        *self == AckMode::Reliable
+    }
+
+    /// Whether a received message requesting an ack is acknowledged.
+    pub fn acknowledges(&self) -> bool {
+        *self != AckMode::Off
+    }
+}
+
+/// Outbound rate of `messages` per `period`, also allowing bursts of `messages`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct Throughput {
+    pub messages: u32,
+    pub period: Duration,
+}
+
+impl Throughput {
+    pub const fn per_second(messages: u32) -> Self {
+        Self {
+            messages,
+            period: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Discoveries sent in a row after startup, `spacing` apart.
+///
+/// They go through the outqueue like any discovery, so throughput limits still apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub struct DiscoveryBurst {
+    pub count: u8,
+    pub spacing: Duration,
+}
+
+impl DiscoveryBurst {
+    /// Whether the next burst discovery is due, `sent` of them having been sent in the
+    /// `since_start` elapsed since startup.
+    pub fn is_due(&self, sent: u8, since_start: Duration) -> bool {
+        sent < self.count && since_start >= self.spacing * sent as u32
+    }
+}
+
+/// Controls when the device floods discovery messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Format)]
+pub enum DiscoveryStrategy {
+    /// Discover only when a route lookup misses
+    Reactive,
+    /// Discover at a fixed interval, never on demand
+    Proactive { interval: Duration },
+    /// Discover on route misses and every `DISCOVERY_INTERVAL`
+    Hybrid,
+}
+
+impl DiscoveryStrategy {
+    /// Whether a route lookup miss should trigger a discovery.
+    pub fn is_reactive(&self) -> bool {
+        matches!(self, DiscoveryStrategy::Reactive | DiscoveryStrategy::Hybrid)
+    }
+
+    /// Interval of the periodic discovery, if any.
+    pub fn interval(&self) -> Option<Duration> {
+        match self {
+            DiscoveryStrategy::Reactive => None,
+            DiscoveryStrategy::Proactive { interval } => Some(*interval),
+            DiscoveryStrategy::Hybrid => Some(DISCOVERY_INTERVAL),
+        }
+    }
+
+    /// Whether a periodic discovery is due after `elapsed` since the last one.
+    pub fn is_due(&self, elapsed: Duration) -> bool {
+        self.interval().is_some_and(|interval| elapsed >= interval)
+    }
+
+    /// Time left before the next periodic discovery, `elapsed` after the last one, or
+    /// `None` without periodic discovery.
+    pub fn remaining(&self, elapsed: Duration) -> Option<Duration> {
+        self.interval()
+            .map(|interval| interval.checked_sub(elapsed).unwrap_or(Duration::from_ticks(0)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Duration;
+
+    use crate::device::config::mesh_config::{
+        AckMode, DeliveryPolicy, DiscoveryBurst, DiscoveryStrategy, Handling, MeshConfig,
+        DISCOVERY_INTERVAL,
+    };
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+
+    #[test]
+    fn test_reactive_never_discovers_periodically() {
+        let strategy = DiscoveryStrategy::Reactive;
+
+        assert!(strategy.is_reactive());
+        assert!(!strategy.is_due(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn test_proactive_discovers_on_interval_only() {
+        let strategy = DiscoveryStrategy::Proactive {
+            interval: Duration::from_secs(10),
+        };
+
+        assert!(!strategy.is_reactive());
+        assert!(!strategy.is_due(Duration::from_secs(9)));
+        assert!(strategy.is_due(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn test_hybrid_discovers_on_miss_and_interval() {
+        let strategy = DiscoveryStrategy::Hybrid;
+
+        assert!(strategy.is_reactive());
+        assert!(!strategy.is_due(DISCOVERY_INTERVAL - Duration::from_secs(1)));
+        assert!(strategy.is_due(DISCOVERY_INTERVAL));
+    }
+
+    #[test]
+    fn test_boot_burst_sends_count_discoveries() {
+        let burst = DiscoveryBurst {
+            count: 4,
+            spacing: Duration::from_secs(5),
+        };
+        let mut sent = 0;
+        let mut sent_at = [0; 8];
+
+        for second in 0..120 {
+            if burst.is_due(sent, Duration::from_secs(second)) {
+                sent_at[sent as usize] = second;
+                sent += 1;
+            }
+        }
+
+        assert_eq!(sent, 4);
+        assert_eq!(&sent_at[..4], &[0, 5, 10, 15]);
+    }
+
+    #[test]
+    fn test_relay_known_only_never_discovers_for_others() {
+        let config = MeshConfig::default();
+        let edge = MeshConfig {
+            relay_known_only: true,
+            ..MeshConfig::default()
+        };
+
+        assert!(config.discovers_for_relays());
+        assert!(!edge.discovers_for_relays());
+    }
+
+    #[test]
+    fn test_discoveries_throttled_at_cap() {
+        let config = MeshConfig {
+            max_discoveries_in_flight: Some(2),
+            ..MeshConfig::default()
+        };
+
+        let mut in_flight = 0;
+        for _ in 0..5 {
+            if config.allows_discovery(in_flight) {
+                in_flight += 1;
+            }
+        }
+
+        assert_eq!(in_flight, 2);
+        assert!(MeshConfig::default().allows_discovery(1000));
+    }
+
+    #[test]
+    fn test_default_delivery_policy_matches_flooding() {
+        let policy = DeliveryPolicy::default();
+
+        let unicast = Destination::Unicast(Uid::try_from(2).unwrap());
+
+        assert_eq!(policy.handling(Destination::Broadcast, false), Handling::DELIVER_AND_RELAY);
+        assert_eq!(policy.handling(Destination::Group(3), true), Handling::DELIVER_AND_RELAY);
+        assert_eq!(policy.handling(Destination::Group(3), false), Handling::IGNORE);
+        assert_eq!(policy.handling(unicast, false), Handling::DELIVER);
+    }
+
+    #[test]
+    fn test_every_handling_is_honored_per_destination_kind() {
+        let handlings = [
+            Handling::DELIVER_AND_RELAY,
+            Handling::DELIVER,
+            Handling::RELAY,
+            Handling::IGNORE,
+        ];
+        for handling in handlings {
+            let policy = DeliveryPolicy {
+                unicast: handling,
+                broadcast: handling,
+                subscribed_group: handling,
+                other_group: handling,
+            };
+            let unicast = Destination::Unicast(Uid::try_from(2).unwrap());
+
+            assert_eq!(policy.handling(unicast, false), handling);
+            assert_eq!(policy.handling(Destination::Broadcast, false), handling);
+            assert_eq!(policy.handling(Destination::Group(3), true), handling);
+            assert_eq!(policy.handling(Destination::Group(3), false), handling);
+        }
+    }
+
+    #[test]
+    fn test_remaining_counts_down_to_next_discovery() {
+        let strategy = DiscoveryStrategy::Proactive {
+            interval: Duration::from_secs(10),
+        };
+
+        assert_eq!(strategy.remaining(Duration::from_secs(4)), Some(Duration::from_secs(6)));
+        assert_eq!(strategy.remaining(Duration::from_secs(12)), Some(Duration::from_secs(0)));
+        assert_eq!(DiscoveryStrategy::Reactive.remaining(Duration::from_secs(4)), None);
+    }
+
+    #[test]
+    fn test_acks_off_neither_retries_nor_acknowledges() {
+        assert!(AckMode::Reliable.retries() && AckMode::Reliable.acknowledges());
+        assert!(!AckMode::NoRetries.retries() && AckMode::NoRetries.acknowledges());
+        assert!(!AckMode::Off.retries() && !AckMode::Off.acknowledges());
+    }
+}
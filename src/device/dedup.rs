@@ -0,0 +1,100 @@
+use heapless::Deque;
+
+use crate::message::{Message, MessageId};
+
+pub const DEDUP_WINDOW: usize = 16;
+
+/// Remembers the last `DEDUP_WINDOW` broadcasts heard, to drop the copies of them that
+/// come back through repetitions or other relays.
+pub struct DuplicateFilter {
+    recent: Deque<(u8, MessageId), DEDUP_WINDOW>,
+}
+
+impl Default for DuplicateFilter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DuplicateFilter {
+    pub const fn new() -> Self {
+        Self {
+            recent: Deque::new(),
+        }
+    }
+
+    /// Whether `message` was already heard, remembering it otherwise.
+    pub fn is_duplicate(&mut self, message: &Message) -> bool {
+        if self.contains(message) {
+            return true;
+        }
+        self.remember(message);
+        false
+    }
+
+    /// Whether `message` is one of the last `DEDUP_WINDOW` remembered.
+    pub fn contains(&self, message: &Message) -> bool {
+        let key = (message.source_id().get(), message.message_id());
+        self.recent.iter().any(|&recent| recent == key)
+    }
+
+    /// Remembers `message`, forgetting the oldest one if the window is full.
+    pub fn remember(&mut self, message: &Message) {
+        if self.recent.is_full() {
+            self.recent.pop_front();
+        }
+        // Cannot fail, room was just made
+        let _ = self.recent.push_back((message.source_id().get(), message.message_id()));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::dedup::{DuplicateFilter, DEDUP_WINDOW};
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::payload::data::DataType;
+    use crate::message::Message;
+
+    fn broadcast(source: u8) -> Message {
+        Message::new_data(
+            Uid::try_from(source).unwrap(),
+            Destination::Broadcast,
+            DataType::new_text("alert"),
+            3,
+            false,
+        )
+    }
+
+    #[test]
+    fn test_copies_are_dropped_within_window() {
+        let mut filter = DuplicateFilter::new();
+        let message = broadcast(1);
+        let mut same_id_other_source = broadcast(2);
+        same_id_other_source.set_message_id(message.message_id());
+
+        assert!(!filter.is_duplicate(&message));
+        assert!(filter.is_duplicate(&message.clone()));
+        assert!(!filter.is_duplicate(&same_id_other_source));
+
+        for _ in 0..DEDUP_WINDOW {
+            filter.is_duplicate(&broadcast(3));
+        }
+        assert!(!filter.is_duplicate(&message));
+    }
+
+    #[test]
+    fn test_echo_of_our_own_relay_is_recognized() {
+        let mut relayed = DuplicateFilter::new();
+        let heard = broadcast(1);
+        let mut relay = heard.clone();
+        relay.decrement_ttl();
+        relayed.remember(&relay);
+
+        // The neighbor we relayed to sends our copy back, one hop shorter again
+        let mut echo = relay.clone();
+        echo.decrement_ttl();
+        assert!(relayed.contains(&echo));
+        assert!(!relayed.contains(&broadcast(1)));
+    }
+}
@@ -2,6 +2,7 @@ use defmt::Format;
 use lora_phy::mod_params::RadioError;
 use snafu::Snafu;
 
+use crate::device::collections::CollectionError;
 use crate::message::error::MessageError;
 
 #[derive(Debug, Snafu, Format)]
@@ -10,10 +11,28 @@ pub enum DeviceError {
     RouteNotFound,
     #[snafu(display("Route error"))]
     RouteError,
+    #[snafu(display("Next hop is not a known neighbor"))]
+    UnknownNextHop,
+    #[snafu(display("Group subscription limit reached"))]
+    GroupLimitReached,
+    #[snafu(display("Rate limit table full"))]
+    RateLimitTableFull,
+    #[snafu(display("Too many broadcasts repeating"))]
+    RepeatLimitReached,
+    #[snafu(display("Message is not a broadcast"))]
+    NotABroadcast,
     #[snafu(display("Message error: {}", source))]
     MessageError { source: MessageError },
     #[snafu(display("Radio error: {:?}", error))]
     RadioError { error: RadioError },
+    #[snafu(display("Queue error: {:?}", error))]
+    QueueError { error: CollectionError },
+    #[snafu(display("Unknown message"))]
+    UnknownMessage,
+    #[snafu(display("UID is reserved"))]
+    ReservedUid,
+    #[snafu(display("Timed out"))]
+    Timeout,
 }
 
 impl From<RadioError> for DeviceError {
@@ -27,3 +46,9 @@ impl From<MessageError> for DeviceError {
         Self::MessageError { source: error }
     }
 }
+
+impl From<CollectionError> for DeviceError {
+    fn from(error: CollectionError) -> Self {
+        Self::QueueError { error }
+    }
+}
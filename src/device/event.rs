@@ -0,0 +1,68 @@
+use defmt::Format;
+use heapless::Deque;
+
+use crate::device::Uid;
+use crate::message::error::MessageError;
+
+const MAX_EVENTS: usize = 16;
+
+/// Notifications raised by the device for the application.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum DeviceEvent {
+    /// A direct neighbor has not been heard from and its route was removed
+    NeighborDown { uid: Uid },
+    /// A route was removed from the routing table, when `MeshConfig::route_events` is set
+    RouteRemoved { destination: u8, reason: RouteRemoval },
+    /// A received frame could not be decoded, when `MeshConfig::decode_failure_events` is set
+    DecodeFailed { error: MessageError, rssi: i16, snr: i16 },
+}
+
+/// Why a route left the routing table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum RouteRemoval {
+    /// Not refreshed within `ROUTE_TTL`
+    Expired,
+    /// Replaced by a new destination in a full table
+    Evicted,
+    /// Dropped after its destination stopped acknowledging, when
+    /// `MeshConfig::rediscover_on_failure` is set
+    Failed,
+}
+
+/// Bounded queue of pending events, dropping the oldest one when full.
+pub struct EventQueue {
+    events: Deque<DeviceEvent, MAX_EVENTS>,
+}
+
+impl Default for EventQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventQueue {
+    pub const fn new() -> Self {
+        Self {
+            events: Deque::new(),
+        }
+    }
+
+    pub fn push(&mut self, event: DeviceEvent) {
+        if self.events.is_full() {
+            self.events.pop_front();
+        }
+        let _ = self.events.push_back(event);
+    }
+
+    pub fn pop(&mut self) -> Option<DeviceEvent> {
+        self.events.pop_front()
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+}
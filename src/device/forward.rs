@@ -0,0 +1,124 @@
+use core::future::poll_fn;
+use core::task::{Context, Poll};
+
+use defmt::Format;
+
+use crate::message::Message;
+
+/// Outcome of a `ForwardFilter` for a message about to be relayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum ForwardDecision {
+    Forward,
+    /// Forward with the TTL replaced by the given value
+    ForwardWithTtl(u8),
+    Drop,
+}
+
+impl ForwardDecision {
+    /// Returns the message to forward, if any.
+    pub fn apply(self, mut message: Message) -> Option<Message> {
+        match self {
+            ForwardDecision::Forward => Some(message),
+            ForwardDecision::ForwardWithTtl(ttl) => {
+                message.set_ttl(ttl);
+                Some(message)
+            }
+            ForwardDecision::Drop => None,
+        }
+    }
+}
+
+/// Application hook deciding, message by message, whether other nodes' traffic is relayed.
+///
+/// The decision is awaited in the forwarding path, so the hook may keep state and wait on
+/// async work, such as fetching a list of deprecated ports. A filter that has to wait
+/// returns `Poll::Pending`, wakes the waker of `cx` once it can decide, and is then polled
+/// again with the same message. The device neither sends nor receives in the meantime.
+///
+/// The hook is polled rather than being an `async fn` so that the device can hold it as a
+/// `dyn ForwardFilter` without boxing its future, which would need an allocator.
+pub trait ForwardFilter {
+    fn poll_filter(&mut self, message: &Message, cx: &mut Context<'_>) -> Poll<ForwardDecision>;
+}
+
+impl dyn ForwardFilter + '_ {
+    /// Decision of the filter for `message`, once it is made.
+    pub async fn filter(&mut self, message: &Message) -> ForwardDecision {
+        poll_fn(|cx| self.poll_filter(message, cx)).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use core::task::{Context, Poll, Waker};
+
+    use crate::device::config::device_config::DeviceConfig;
+    use crate::device::forward::{ForwardDecision, ForwardFilter};
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::payload::command::CommandType;
+    use crate::message::payload::data::DataType;
+    use crate::message::payload::Payload;
+    use crate::message::Message;
+
+    /// Drops data relays and shortens command relays, deciding each message on its second
+    /// poll as if it had to look something up first.
+    #[derive(Default)]
+    struct NoDataRelay {
+        looked_up: bool,
+    }
+
+    impl ForwardFilter for NoDataRelay {
+        fn poll_filter(
+            &mut self,
+            message: &Message,
+            cx: &mut Context<'_>,
+        ) -> Poll<ForwardDecision> {
+            if !self.looked_up {
+                self.looked_up = true;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            self.looked_up = false;
+            Poll::Ready(match message.payload() {
+                Payload::Data(_) => ForwardDecision::Drop,
+                Payload::Command(_) => ForwardDecision::ForwardWithTtl(1),
+                _ => ForwardDecision::Forward,
+            })
+        }
+    }
+
+    /// Polls `filter` until it decides on `message`.
+    fn decide(filter: &mut NoDataRelay, message: &Message) -> ForwardDecision {
+        let mut cx = Context::from_waker(Waker::noop());
+        assert_eq!(filter.poll_filter(message, &mut cx), Poll::Pending);
+        match filter.poll_filter(message, &mut cx) {
+            Poll::Ready(decision) => decision,
+            Poll::Pending => panic!("no decision after the lookup"),
+        }
+    }
+
+    #[test]
+    fn test_filter_by_payload_kind() {
+        let source = Uid::try_from(1).unwrap();
+        let data = Message::new_data(
+            source,
+            Destination::Broadcast,
+            DataType::new_text("hi"),
+            5,
+            false,
+        );
+        let command = Message::new_command(
+            source,
+            Destination::Broadcast,
+            CommandType::SetConfig(DeviceConfig::default()),
+            5,
+            false,
+        );
+        let mut filter = NoDataRelay::default();
+
+        assert_eq!(decide(&mut filter, &data).apply(data), None);
+        let forwarded = decide(&mut filter, &command).apply(command).unwrap();
+        assert_eq!(forwarded.ttl(), 1);
+    }
+}
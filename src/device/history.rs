@@ -0,0 +1,133 @@
+use defmt::Format;
+use embassy_time::Instant;
+use heapless::Deque;
+
+use crate::message::destination::Destination;
+use crate::message::{Message, MessageId};
+
+pub const TX_HISTORY_SIZE: usize = 8;
+
+/// What became of a transmitted message, as far as the device knows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum TxOutcome {
+    /// No acknowledgement was requested
+    Unacknowledged,
+    /// Waiting for the acknowledgement
+    AwaitingAck,
+    /// The destination acknowledged the message
+    Acknowledged,
+    /// No acknowledgement came back after the last attempt
+    Failed,
+}
+
+/// Summary of a transmitted frame.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct TxRecord {
+    pub message_id: MessageId,
+    /// Next hop the frame was addressed to
+    pub destination: Destination,
+    /// Encoded size of the frame in bytes
+    pub size: usize,
+    pub sent_at: Instant,
+    pub outcome: TxOutcome,
+}
+
+/// The last `TX_HISTORY_SIZE` transmissions, kept for diagnosing lost messages.
+///
+/// Purely observational: nothing in the protocol reads it.
+pub struct TxHistory {
+    records: Deque<TxRecord, TX_HISTORY_SIZE>,
+}
+
+impl Default for TxHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TxHistory {
+    pub const fn new() -> Self {
+        Self {
+            records: Deque::new(),
+        }
+    }
+
+    /// Records the transmission of `message`, dropping the oldest record when full.
+    pub fn record(&mut self, message: &Message, size: usize, sent_at: Instant) {
+        if self.records.is_full() {
+            self.records.pop_front();
+        }
+        let outcome = if message.req_ack() {
+            TxOutcome::AwaitingAck
+        } else {
+            TxOutcome::Unacknowledged
+        };
+        let _ = self.records.push_back(TxRecord {
+            message_id: message.message_id(),
+            destination: message.destination(),
+            size,
+            sent_at,
+            outcome,
+        });
+    }
+
+    /// Updates the outcome of every recorded transmission of `message_id`.
+    pub fn set_outcome(&mut self, message_id: MessageId, outcome: TxOutcome) {
+        self.records
+            .iter_mut()
+            .filter(|record| record.message_id == message_id)
+            .for_each(|record| record.outcome = outcome);
+    }
+
+    /// Recorded transmissions, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &TxRecord> {
+        self.records.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Instant;
+    use heapless::Vec;
+
+    use crate::device::history::{TxHistory, TxOutcome, TX_HISTORY_SIZE};
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::payload::data::DataType;
+    use crate::message::{Message, MessageId};
+
+    #[test]
+    fn test_history_keeps_recent_transmits() {
+        let mut history = TxHistory::new();
+        let mut ids = Vec::<MessageId, 16>::new();
+        for second in 0..TX_HISTORY_SIZE as u64 + 2 {
+            let message = Message::new_data(
+                Uid::try_from(1).unwrap(),
+                Destination::Unicast(Uid::try_from(2).unwrap()),
+                DataType::new_text("reading"),
+                3,
+                true,
+            );
+            ids.push(message.message_id()).unwrap();
+            history.record(&message, 20, Instant::from_secs(second));
+        }
+        let last = *ids.last().unwrap();
+        history.set_outcome(last, TxOutcome::Acknowledged);
+
+        assert_eq!(history.len(), TX_HISTORY_SIZE);
+        let recorded: Vec<MessageId, 16> = history.iter().map(|record| record.message_id).collect();
+        assert_eq!(recorded.as_slice(), &ids[2..]);
+        let newest = history.iter().last().unwrap();
+        assert_eq!(newest.sent_at, Instant::from_secs(TX_HISTORY_SIZE as u64 + 1));
+        assert_eq!(newest.outcome, TxOutcome::Acknowledged);
+        assert_eq!(history.iter().next().unwrap().outcome, TxOutcome::AwaitingAck);
+    }
+}
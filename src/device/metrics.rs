@@ -0,0 +1,146 @@
+use defmt::Format;
+use embassy_time::Duration;
+use serde::{Deserialize, Serialize};
+
+/// Weight of the history in the rolling residence average, out of `RESIDENCE_WEIGHT + 1`.
+const RESIDENCE_WEIGHT: u64 = 7;
+
+/// Cumulative counters describing the traffic handled by a device.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, Format)]
+pub struct DeviceMetrics {
+    /// Unicast messages relayed towards their next hop
+    pub messages_forwarded: u32,
+    /// Messages dropped because their TTL reached zero
+    pub messages_dropped_expired: u32,
+    /// Messages dropped because they waited longer than the maximum message age
+    pub messages_dropped_stale: u32,
+    /// Messages dropped because no route to their destination was known
+    pub messages_dropped_no_route: u32,
+    /// Broadcast messages re-queued for relaying
+    pub broadcasts_relayed: u32,
+    /// Broadcast relays dropped because too many were already queued
+    pub broadcasts_dropped_relay_cap: u32,
+    /// Broadcast relays dropped because the outqueue was full
+    pub broadcasts_dropped_outqueue_full: u32,
+    /// Broadcast and group messages dropped because they were already heard
+    pub broadcasts_duplicate: u32,
+    /// Received copies of messages this device relayed itself, dropped on arrival
+    pub relay_echoes_dropped: u32,
+    /// Received copies of the last transmitted frame, heard within the echo window
+    pub tx_echoes_dropped: u32,
+    /// Messages not forwarded because relaying is disabled
+    pub relays_suppressed: u32,
+    /// Messages not forwarded because the forward filter dropped them
+    pub relays_filtered: u32,
+    /// Messages not forwarded because they are meant for direct neighbors only
+    pub relays_local_only: u32,
+    /// Messages not forwarded because the relay budget was spent
+    pub relays_over_budget: u32,
+    /// Messages addressed to this device dropped because the inqueue was full
+    pub messages_dropped_inqueue_full: u32,
+    /// Received frames dropped because the radio reported an impossible size
+    pub oversized_frames_dropped: u32,
+    /// Received frames dropped because they did not decode to a message
+    pub frames_undecodable: u32,
+    /// Outgoing messages dropped because they did not encode into a frame
+    pub messages_unencodable: u32,
+    /// Receptions that failed the radio CRC check, a sign of interference or collisions
+    pub rx_crc_errors: u32,
+    /// Receptions that failed with any other radio error, a sign of misconfiguration
+    pub rx_errors: u32,
+    /// Processed messages dropped because the application channel was full
+    pub app_channel_dropped: u32,
+    /// Messages to a rate-limited destination dropped because too many were deferred
+    pub messages_dropped_rate_limit: u32,
+    /// Outgoing messages addressed to this device, delivered to the inqueue without TX
+    pub messages_looped_back: u32,
+    /// Frames delivered as is to the inqueue in promiscuous mode
+    pub messages_captured: u32,
+    /// Rolling average of the time messages wait in the outqueue before being sent, in
+    /// milliseconds; a rising value signals congestion or airtime starvation
+    pub outqueue_residence_ms: u32,
+}
+
+impl DeviceMetrics {
+    /// Folds the time a message spent in the outqueue into the rolling average.
+    ///
+    /// The average is rounded to the nearest millisecond, flooring it would keep it below
+    /// a steady wait by up to `RESIDENCE_WEIGHT` milliseconds.
+    pub fn record_outqueue_residence(&mut self, residence: Duration) {
+        let sample = residence.as_millis().min(u32::MAX as u64);
+        let average = self.outqueue_residence_ms as u64;
+        let total = average * RESIDENCE_WEIGHT + sample + (RESIDENCE_WEIGHT + 1) / 2;
+        self.outqueue_residence_ms = (total / (RESIDENCE_WEIGHT + 1)) as u32;
+    }
+
+    /// Returns the counters accumulated so far and zeroes them, so that consecutive calls
+    /// report disjoint intervals. The residence average is a gauge, not a counter, and is
+    /// kept.
+    pub fn take(&mut self) -> Self {
+        let taken = *self;
+        *self = Self {
+            outqueue_residence_ms: self.outqueue_residence_ms,
+            ..Self::default()
+        };
+        taken
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Duration;
+
+    use crate::device::metrics::DeviceMetrics;
+
+    #[test]
+    fn test_residence_rises_under_congestion() {
+        let mut metrics = DeviceMetrics::default();
+        for _ in 0..32 {
+            metrics.record_outqueue_residence(Duration::from_millis(20));
+        }
+        let idle = metrics.outqueue_residence_ms;
+
+        // A backlog builds up, every message waiting longer than the previous one
+        for wait in 1..=16 {
+            metrics.record_outqueue_residence(Duration::from_millis(wait * 500));
+        }
+
+        assert!(idle <= 20);
+        assert!(metrics.outqueue_residence_ms > 10 * idle);
+    }
+
+    #[test]
+    fn test_residence_settles_near_a_steady_wait() {
+        let mut rising = DeviceMetrics::default();
+        let mut falling = DeviceMetrics {
+            outqueue_residence_ms: 8000,
+            ..DeviceMetrics::default()
+        };
+
+        for _ in 0..64 {
+            rising.record_outqueue_residence(Duration::from_millis(20));
+            falling.record_outqueue_residence(Duration::from_millis(20));
+        }
+
+        // Within half the weight of the wait from both sides
+        assert!(rising.outqueue_residence_ms.abs_diff(20) <= 4);
+        assert!(falling.outqueue_residence_ms.abs_diff(20) <= 4);
+    }
+
+    #[test]
+    fn test_take_returns_accumulated_counters_then_zeroes_them() {
+        let mut metrics = DeviceMetrics::default();
+        metrics.messages_forwarded = 3;
+        metrics.rx_crc_errors = 2;
+        metrics.record_outqueue_residence(Duration::from_millis(800));
+        let accumulated = metrics;
+
+        assert_eq!(metrics.take(), accumulated);
+        assert_eq!(metrics.messages_forwarded, 0);
+        assert_eq!(metrics.rx_crc_errors, 0);
+        assert_eq!(metrics.outqueue_residence_ms, accumulated.outqueue_residence_ms);
+
+        metrics.messages_forwarded += 1;
+        assert_eq!(metrics.take().messages_forwarded, 1);
+    }
+}
@@ -1,6 +1,7 @@
 use embassy_time::Instant;
-use crate::device::Uid;
+use crate::message::destination::Destination;
 use crate::message::payload::Payload;
+use crate::message::MAX_TTL;
 
 
 pub const MAX_PENDING_ACKS: usize = 32;
@@ -13,18 +14,18 @@ pub struct PendingAck {
     pub attempts: u8,
     pub is_acknowledged: bool,
     payload: Payload,  // Minimal information needed to recreate the message
-    destination_uid: Option<Uid>,
+    destination: Destination,
     ttl: u8,
 }
 
 impl PendingAck {
-    pub fn new(payload: Payload, destination_uid: Option<Uid>, ttl: u8) -> Self {
+    pub fn new(payload: Payload, destination: Destination, ttl: u8) -> Self {
         Self {
             timestamp: Instant::now(),
             attempts: 0,
             is_acknowledged: false,
             payload,
-            destination_uid,
+            destination,
             ttl,
         }
     }
@@ -33,8 +34,8 @@ impl PendingAck {
         &self.payload
     }
 
-    pub fn destination_uid(&self) -> Option<Uid> {
-        self.destination_uid
+    pub fn destination(&self) -> Destination {
+        self.destination
     }
 
     pub fn ttl(&self) -> u8 {
@@ -54,3 +55,27 @@ impl PendingAck {
     }
 }
 
+/// TTL of a retry after `attempts` retries, `step` hops wider than the last one, up to
+/// `MAX_TTL`.
+pub fn retry_ttl(ttl: u8, attempts: u8, step: u8) -> u8 {
+    let bump = step.saturating_mul(attempts.saturating_add(1));
+    ttl.saturating_add(bump).min(MAX_TTL)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::pending_ack::{retry_ttl, MAX_ACK_ATTEMPTS};
+    use crate::message::MAX_TTL;
+
+    #[test]
+    fn test_retry_reaches_farther_destination_once_ttl_is_bumped() {
+        let (ttl, distance, step) = (3, 6, 2);
+
+        let reached_at =
+            (0..MAX_ACK_ATTEMPTS).find(|&attempts| retry_ttl(ttl, attempts, step) >= distance);
+
+        assert_eq!(reached_at, Some(1));
+        assert_eq!(retry_ttl(ttl, 0, 0), ttl);
+        assert_eq!(retry_ttl(ttl, MAX_ACK_ATTEMPTS, u8::MAX), MAX_TTL);
+    }
+}
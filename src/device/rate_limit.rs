@@ -0,0 +1,187 @@
+use embassy_time::{Duration, Instant};
+use heapless::{FnvIndexMap, Vec};
+
+use crate::device::device_error::DeviceError;
+use crate::device::schedule::Periodic;
+use crate::device::Uid;
+use crate::message::{Message, MessageId};
+
+pub const MAX_RATE_LIMITS: usize = 8;
+pub const MAX_DEFERRED: usize = 8;
+
+/// Outcome of pacing an outgoing message.
+#[derive(Debug)]
+pub enum Pacing {
+    /// The message may be transmitted now
+    Send(Message),
+    /// The message was kept until its destination is due
+    Deferred,
+    /// The message was dropped because no room was left to defer it
+    Dropped,
+}
+
+/// Spaces out transmissions to destinations with a minimum send interval.
+///
+/// Messages to a destination that is not due yet are kept aside, in order, instead of
+/// being dropped, up to `MAX_DEFERRED` messages.
+#[derive(Default)]
+pub struct RateLimiter {
+    limits: FnvIndexMap<u8, (Duration, Periodic), MAX_RATE_LIMITS>,
+    deferred: Vec<Message, MAX_DEFERRED>,
+}
+
+impl RateLimiter {
+    pub fn set_limit(&mut self, destination: Uid, min_interval: Duration) -> Result<(), DeviceError> {
+        let last_sent = self
+            .limits
+            .get(&destination.get())
+            .map(|&(_, last_sent)| last_sent)
+            .unwrap_or_default();
+        self.limits
+            .insert(destination.get(), (min_interval, last_sent))
+            .map_err(|_| DeviceError::RateLimitTableFull)?;
+        Ok(())
+    }
+
+    pub fn clear_limit(&mut self, destination: Uid) {
+        self.limits.remove(&destination.get());
+    }
+
+    pub fn has_deferred(&self) -> bool {
+        !self.deferred.is_empty()
+    }
+
+    /// Decides whether `message` may be sent at `now`, deferring it otherwise.
+    ///
+    /// A message is also deferred behind older deferred messages to the same destination,
+    /// so they are sent in order.
+    pub fn pace(&mut self, message: Message, now: Instant) -> Pacing {
+        let Some(destination) = message.destination_id() else {
+            return Pacing::Send(message);
+        };
+        let waiting = self
+            .deferred
+            .iter()
+            .any(|deferred| deferred.destination_id() == Some(destination));
+        if !waiting && self.is_due(destination.get(), now) {
+            return Pacing::Send(message);
+        }
+        match self.deferred.push(message) {
+            Ok(()) => Pacing::Deferred,
+            Err(_) => Pacing::Dropped,
+        }
+    }
+
+    /// Drops the deferred messages of `source` with `message_id`, returning how many were
+    /// dropped.
+    pub fn remove(&mut self, source: Uid, message_id: MessageId) -> usize {
+        let before = self.deferred.len();
+        self.deferred
+            .retain(|message| message.source_id() != source || message.message_id() != message_id);
+        before - self.deferred.len()
+    }
+
+    /// Takes the oldest deferred message whose destination is due at `now`.
+    pub fn next_ready(&mut self, now: Instant) -> Option<Message> {
+        let position = self.deferred.iter().position(|message| {
+            message
+                .destination_id()
+                .is_some_and(|destination| self.is_due(destination.get(), now))
+        })?;
+        Some(self.deferred.remove(position))
+    }
+
+    /// Records that `message` was handed over for transmission at `now`.
+    pub fn record(&mut self, message: &Message, now: Instant) {
+        let Some(destination) = message.destination_id() else {
+            return;
+        };
+        if let Some((_, last_sent)) = self.limits.get_mut(&destination.get()) {
+            last_sent.reset(now);
+        }
+    }
+
+    fn is_due(&self, destination: u8, now: Instant) -> bool {
+        match self.limits.get(&destination) {
+            Some((min_interval, last_sent)) => {
+                last_sent.remaining(now, *min_interval) == Duration::from_ticks(0)
+            }
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+    use heapless::Vec;
+
+    use crate::device::rate_limit::{Pacing, RateLimiter, MAX_DEFERRED};
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::payload::data::DataType;
+    use crate::message::Message;
+
+    fn message_to(destination: u8) -> Message {
+        Message::new_data(
+            Uid::try_from(1).unwrap(),
+            Destination::Unicast(Uid::try_from(destination).unwrap()),
+            DataType::new_text("command"),
+            3,
+            false,
+        )
+    }
+
+    /// Drains the limiter the way the outqueue does, recording when each message was sent.
+    fn send(limiter: &mut RateLimiter, message: Message, now: Instant, sent: &mut Vec<(u8, u64), 8>) {
+        if let Pacing::Send(message) = limiter.pace(message, now) {
+            limiter.record(&message, now);
+            sent.push((message.destination_id().unwrap().get(), now.as_secs())).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_messages_to_limited_peer_are_spaced_out() {
+        let mut limiter = RateLimiter::default();
+        limiter
+            .set_limit(Uid::try_from(2).unwrap(), Duration::from_secs(10))
+            .unwrap();
+        let mut sent = Vec::new();
+
+        let start = Instant::from_secs(0);
+        send(&mut limiter, message_to(2), start, &mut sent);
+        send(&mut limiter, message_to(2), start, &mut sent);
+        send(&mut limiter, message_to(2), start, &mut sent);
+        send(&mut limiter, message_to(3), start, &mut sent);
+
+        for second in 1..=30 {
+            let now = Instant::from_secs(second);
+            while let Some(message) = limiter.next_ready(now) {
+                limiter.record(&message, now);
+                sent.push((message.destination_id().unwrap().get(), second)).unwrap();
+            }
+        }
+
+        assert_eq!(sent.as_slice(), &[(2, 0), (3, 0), (2, 10), (2, 20)]);
+        assert!(!limiter.has_deferred());
+    }
+
+    #[test]
+    fn test_drops_beyond_deferred_limit() {
+        let mut limiter = RateLimiter::default();
+        limiter
+            .set_limit(Uid::try_from(2).unwrap(), Duration::from_secs(10))
+            .unwrap();
+        let now = Instant::from_secs(0);
+        let first = limiter.pace(message_to(2), now);
+        let Pacing::Send(first) = first else {
+            panic!("first message should be sent");
+        };
+        limiter.record(&first, now);
+
+        for _ in 0..MAX_DEFERRED {
+            assert!(matches!(limiter.pace(message_to(2), now), Pacing::Deferred));
+        }
+        assert!(matches!(limiter.pace(message_to(2), now), Pacing::Dropped));
+    }
+}
@@ -0,0 +1,106 @@
+use embassy_time::{Duration, Instant};
+use heapless::FnvIndexMap;
+
+use crate::device::pending_ack::MAX_PENDING_ACKS;
+use crate::device::Uid;
+use crate::message::MessageId;
+
+/// How long a delivered message waits for the application to confirm it.
+pub const RECEIPT_TIMEOUT: Duration = Duration::from_secs(600);
+
+struct Awaiting {
+    source: Uid,
+    ttl: u8,
+    delivered_at: Instant,
+}
+
+/// Delivered messages awaiting an application receipt, with the source and TTL to answer
+/// them with.
+///
+/// Messages the application never confirms expire after `RECEIPT_TIMEOUT`, and the oldest
+/// one makes room for a new delivery when full, so they cannot block receipts for good.
+#[derive(Default)]
+pub struct AwaitingReceipts {
+    awaiting: FnvIndexMap<MessageId, Awaiting, MAX_PENDING_ACKS>,
+}
+
+impl AwaitingReceipts {
+    /// Tracks the message `id` delivered at `now`.
+    pub fn insert(&mut self, id: MessageId, source: Uid, ttl: u8, now: Instant) {
+        if self.awaiting.len() == self.awaiting.capacity() && !self.awaiting.contains_key(&id) {
+            let oldest = self
+                .awaiting
+                .iter()
+                .min_by_key(|(_, awaiting)| awaiting.delivered_at)
+                .map(|(&oldest, _)| oldest);
+            if let Some(oldest) = oldest {
+                self.awaiting.remove(&oldest);
+            }
+        }
+        // Cannot fail, room was just made
+        let _ = self.awaiting.insert(id, Awaiting { source, ttl, delivered_at: now });
+    }
+
+    /// Stops tracking the message `id`, returning its source and TTL unless it expired.
+    pub fn remove(&mut self, id: MessageId, now: Instant) -> Option<(Uid, u8)> {
+        let awaiting = self.awaiting.remove(&id)?;
+        let expired = now.saturating_duration_since(awaiting.delivered_at) > RECEIPT_TIMEOUT;
+        (!expired).then_some((awaiting.source, awaiting.ttl))
+    }
+
+    /// Forgets the messages awaiting their receipt for longer than `RECEIPT_TIMEOUT`.
+    pub fn remove_expired(&mut self, now: Instant) {
+        self.awaiting.retain(|_, awaiting| {
+            now.saturating_duration_since(awaiting.delivered_at) <= RECEIPT_TIMEOUT
+        });
+    }
+
+    pub fn len(&self) -> usize {
+        self.awaiting.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.awaiting.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use crate::device::pending_ack::MAX_PENDING_ACKS;
+    use crate::device::receipt::{AwaitingReceipts, RECEIPT_TIMEOUT};
+    use crate::device::Uid;
+    use crate::message::MessageId;
+
+    #[test]
+    fn test_unconfirmed_receipts_expire() {
+        let mut receipts = AwaitingReceipts::default();
+        let source = Uid::try_from(2).unwrap();
+        let start = Instant::from_secs(0);
+        receipts.insert(1, source, 3, start);
+        receipts.insert(2, source, 3, start);
+
+        let late = start + RECEIPT_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(receipts.remove(1, late), None);
+        receipts.remove_expired(late);
+        assert!(receipts.is_empty());
+    }
+
+    #[test]
+    fn test_oldest_receipt_makes_room() {
+        let mut receipts = AwaitingReceipts::default();
+        let source = Uid::try_from(2).unwrap();
+        for id in 0..MAX_PENDING_ACKS as MessageId {
+            receipts.insert(id, source, 3, Instant::from_secs(id as u64));
+        }
+
+        let now = Instant::from_secs(100);
+        receipts.insert(100, source, 3, now);
+
+        assert_eq!(receipts.len(), MAX_PENDING_ACKS);
+        assert_eq!(receipts.remove(0, now), None);
+        assert_eq!(receipts.remove(1, now), Some((source, 3)));
+        assert_eq!(receipts.remove(100, now), Some((source, 3)));
+    }
+}
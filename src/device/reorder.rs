@@ -0,0 +1,268 @@
+use embassy_time::{Duration, Instant};
+use heapless::{FnvIndexMap, Vec};
+
+use crate::message::Message;
+
+pub const MAX_REORDER_SOURCES: usize = 4;
+pub const REORDER_WINDOW: usize = 4;
+
+/// Numbers the sequenced messages this device sends, one sequence per destination.
+pub struct Sequencer {
+    next: [u8; 256],
+}
+
+impl Default for Sequencer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sequencer {
+    pub const fn new() -> Self {
+        Self { next: [0; 256] }
+    }
+
+    /// Gives `message` the next sequence number towards its destination, if it is sequenced.
+    pub fn assign(&mut self, message: &mut Message) {
+        if let (true, Some(destination)) = (message.is_sequenced(), message.destination_id()) {
+            let next = &mut self.next[destination.get() as usize];
+            message.set_sequence(*next);
+            *next = next.wrapping_add(1);
+        }
+    }
+}
+
+/// Messages held back for one source and destination until the gap before them is filled.
+struct SourceWindow {
+    next: u8,
+    /// Held messages, sorted by sequence number. One over the window until popped
+    held: Vec<Message, { REORDER_WINDOW + 1 }>,
+    /// When the device started waiting for the missing message
+    gap_since: Option<Instant>,
+}
+
+/// Delivers the sequenced messages of every source to every destination in sequence order.
+///
+/// A message arriving ahead of its predecessors is held until they arrive, the window of
+/// its source is full or the gap timed out. Messages that are not sequenced, the ones of
+/// sources beyond `MAX_REORDER_SOURCES` and messages older than the ones already delivered
+/// are passed through as they come.
+#[derive(Default)]
+pub struct Reorderer {
+    sources: FnvIndexMap<(u8, u8), SourceWindow, MAX_REORDER_SOURCES>,
+}
+
+impl Reorderer {
+    /// Accepts a message received at `now`, giving it back when it can be delivered now.
+    ///
+    /// The held messages it puts back in order are then taken with `pop`, which must be
+    /// called until it returns `None` before the next push.
+    pub fn push(&mut self, message: Message, now: Instant) -> Option<Message> {
+        let Some(destination) = message.destination_id().filter(|_| message.is_sequenced()) else {
+            return Some(message);
+        };
+        let key = (message.source_id().get(), destination.get());
+        let sequence = message.sequence();
+        let Some(window) = self.sources.get_mut(&key) else {
+            let window = SourceWindow {
+                next: sequence.wrapping_add(1),
+                held: Vec::new(),
+                gap_since: None,
+            };
+            // Without room to track the source, its messages are not reordered
+            let _ = self.sources.insert(key, window);
+            return Some(message);
+        };
+
+        let ahead = sequence.wrapping_sub(window.next) as i8;
+        if ahead < 0 {
+            // Late or duplicated, its successors are already out
+            return Some(message);
+        }
+        if ahead == 0 {
+            window.next = sequence.wrapping_add(1);
+            // With messages still held, the wait for the next gap starts over
+            window.gap_since = (!window.held.is_empty()).then_some(now);
+            return Some(message);
+        }
+
+        if window.held.iter().any(|held| held.sequence() == sequence) {
+            // A retry of a held message, which is only delivered once
+            return None;
+        }
+        let position = window
+            .held
+            .iter()
+            .position(|held| (held.sequence().wrapping_sub(sequence) as i8) > 0)
+            .unwrap_or(window.held.len());
+        let _ = window.held.insert(position, message);
+        window.gap_since.get_or_insert(now);
+        None
+    }
+
+    /// Takes the next held message that is now in order, giving up on gaps older than
+    /// `timeout` and on the gaps of full windows.
+    pub fn pop(&mut self, now: Instant, timeout: Duration) -> Option<Message> {
+        self.sources
+            .values_mut()
+            .find_map(|window| window.pop(now, timeout))
+    }
+}
+
+impl SourceWindow {
+    fn pop(&mut self, now: Instant, timeout: Duration) -> Option<Message> {
+        let first = self.held.first()?;
+        let expired = self
+            .gap_since
+            .is_some_and(|since| now.saturating_duration_since(since) >= timeout);
+        let overflowing = self.held.len() > REORDER_WINDOW;
+        if first.sequence() != self.next && !expired && !overflowing {
+            return None;
+        }
+        let message = self.held.remove(0);
+        self.next = message.sequence().wrapping_add(1);
+        // With messages still held, the wait for the next gap starts over
+        self.gap_since = (!self.held.is_empty()).then_some(now);
+        Some(message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+    use heapless::Vec;
+
+    use crate::device::reorder::{Reorderer, Sequencer, REORDER_WINDOW};
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::payload::data::DataType;
+    use crate::message::Message;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn message(source: u8, sequence: u8) -> Message {
+        let mut message = Message::new_data(
+            Uid::try_from(source).unwrap(),
+            Destination::Unicast(Uid::try_from(9).unwrap()),
+            DataType::new_text("chunk"),
+            3,
+            false,
+        );
+        message.set_sequence(sequence);
+        message
+    }
+
+    /// Pushes `message` and pops what it released, as the device does.
+    fn deliver(
+        reorderer: &mut Reorderer,
+        message: Message,
+        now: Instant,
+        delivered: &mut Vec<(u8, u8), 16>,
+    ) {
+        let mut record = |m: Message| delivered.push((m.source_id().get(), m.sequence())).unwrap();
+        if let Some(message) = reorderer.push(message, now) {
+            record(message);
+        }
+        while let Some(message) = reorderer.pop(now, TIMEOUT) {
+            record(message);
+        }
+    }
+
+    #[test]
+    fn test_out_of_order_messages_are_delivered_in_order() {
+        let mut reorderer = Reorderer::default();
+        let mut delivered = Vec::new();
+        let now = Instant::from_secs(0);
+
+        for (source, sequence) in [(1, 10), (1, 13), (2, 50), (1, 12), (2, 51), (1, 11)] {
+            deliver(&mut reorderer, message(source, sequence), now, &mut delivered);
+        }
+
+        assert_eq!(
+            delivered.as_slice(),
+            &[(1, 10), (2, 50), (2, 51), (1, 11), (1, 12), (1, 13)]
+        );
+    }
+
+    #[test]
+    fn test_unsequenced_messages_do_not_open_gaps() {
+        let mut reorderer = Reorderer::default();
+        let mut delivered = Vec::new();
+        let now = Instant::from_secs(0);
+        let source = Uid::try_from(1).unwrap();
+        // A broadcast from the same source in between, as well as other message IDs spent
+        // on acks or discoveries, must not hold the next message back
+        let broadcast =
+            Message::new_data(source, Destination::Broadcast, DataType::new_text("all"), 3, false);
+
+        deliver(&mut reorderer, message(1, 0), now, &mut delivered);
+        deliver(&mut reorderer, broadcast, now, &mut delivered);
+        deliver(&mut reorderer, message(1, 1), now, &mut delivered);
+
+        assert_eq!(delivered.as_slice(), &[(1, 0), (1, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn test_gap_timeout_releases_held_messages() {
+        let mut reorderer = Reorderer::default();
+        let mut delivered = Vec::new();
+
+        deliver(&mut reorderer, message(1, 1), Instant::from_secs(0), &mut delivered);
+        deliver(&mut reorderer, message(1, 3), Instant::from_secs(0), &mut delivered);
+        assert!(reorderer.pop(Instant::from_secs(1), TIMEOUT).is_none());
+        let released = reorderer.pop(Instant::from_secs(2), TIMEOUT).unwrap();
+        delivered.push((1, released.sequence())).unwrap();
+        deliver(&mut reorderer, message(1, 2), Instant::from_secs(3), &mut delivered);
+
+        // The gap was given up on at 2 s, the late message still reaches the application
+        assert_eq!(delivered.as_slice(), &[(1, 1), (1, 3), (1, 2)]);
+    }
+
+    #[test]
+    fn test_full_window_gives_up_on_the_gap() {
+        let mut reorderer = Reorderer::default();
+        let mut delivered = Vec::new();
+        let now = Instant::from_secs(0);
+
+        deliver(&mut reorderer, message(1, 0), now, &mut delivered);
+        for sequence in 2..(REORDER_WINDOW as u8 + 3) {
+            deliver(&mut reorderer, message(1, sequence), now, &mut delivered);
+        }
+
+        let sequences: Vec<u8, 16> = delivered.iter().map(|&(_, sequence)| sequence).collect();
+        assert_eq!(sequences.as_slice(), &[0, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_sequences_wrapping_around_stay_in_order() {
+        let mut reorderer = Reorderer::default();
+        let mut delivered = Vec::new();
+        let now = Instant::from_secs(0);
+
+        for sequence in [254, 0, 255, 1] {
+            deliver(&mut reorderer, message(1, sequence), now, &mut delivered);
+        }
+        // Behind the window once wrapped, so passed through rather than held forever
+        deliver(&mut reorderer, message(1, 254), now, &mut delivered);
+
+        let sequences: Vec<u8, 16> = delivered.iter().map(|&(_, sequence)| sequence).collect();
+        assert_eq!(sequences.as_slice(), &[254, 255, 0, 1, 254]);
+    }
+
+    #[test]
+    fn test_sequences_are_kept_per_destination() {
+        let mut sequencer = Sequencer::new();
+        let source = Uid::try_from(1).unwrap();
+        let to = |destination: u8| Destination::Unicast(Uid::try_from(destination).unwrap());
+        let mut numbered = |destination| {
+            let mut message =
+                Message::new_data(source, to(destination), DataType::new_text("x"), 3, false);
+            sequencer.assign(&mut message);
+            message.sequence()
+        };
+
+        assert_eq!(numbered(2), 0);
+        assert_eq!(numbered(3), 0);
+        assert_eq!(numbered(2), 1);
+    }
+}
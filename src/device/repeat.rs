@@ -0,0 +1,149 @@
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use heapless::Vec;
+
+use crate::device::schedule::Periodic;
+use crate::message::{Message, MessageId};
+
+pub const MAX_REPEATING: usize = 4;
+
+/// Extra transmissions of a broadcast, raising the odds that every neighbor hears it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct Repeat {
+    /// Transmissions on top of the first one
+    pub count: u8,
+    /// Time between two transmissions
+    pub interval: Duration,
+}
+
+struct Repeating {
+    message: Message,
+    remaining: u8,
+    interval: Duration,
+    last_sent: Periodic,
+}
+
+/// Broadcasts waiting for their next repetition.
+///
+/// Repetitions keep the message ID of the original, so receivers drop the copies they
+/// already heard.
+#[derive(Default)]
+pub struct BroadcastRepeater {
+    repeating: Vec<Repeating, MAX_REPEATING>,
+}
+
+impl BroadcastRepeater {
+    /// Schedules the repetitions of `message`, first sent at `now`.
+    ///
+    /// Gives the message back when too many broadcasts are already repeating.
+    pub fn push(&mut self, message: Message, repeat: Repeat, now: Instant) -> Result<(), Message> {
+        if repeat.count == 0 {
+            return Ok(());
+        }
+        let mut last_sent = Periodic::new();
+        last_sent.reset(now);
+        self.repeating
+            .push(Repeating {
+                message,
+                remaining: repeat.count,
+                interval: repeat.interval,
+                last_sent,
+            })
+            .map_err(|repeating| repeating.message)
+    }
+
+    /// Stops repeating the broadcast with `message_id`, returning whether it was repeating.
+    pub fn cancel(&mut self, message_id: MessageId) -> bool {
+        let before = self.repeating.len();
+        self.repeating
+            .retain(|repeating| repeating.message.message_id() != message_id);
+        self.repeating.len() < before
+    }
+
+    /// Takes the next repetition due at `now`.
+    pub fn next_due(&mut self, now: Instant) -> Option<Message> {
+        let position = self
+            .repeating
+            .iter_mut()
+            .position(|repeating| repeating.last_sent.poll(now, repeating.interval))?;
+        let repeating = &mut self.repeating[position];
+        repeating.remaining -= 1;
+        if repeating.remaining == 0 {
+            Some(self.repeating.remove(position).message)
+        } else {
+            Some(repeating.message.clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use crate::device::repeat::{BroadcastRepeater, Repeat, MAX_REPEATING};
+    use crate::device::Uid;
+    use crate::message::destination::Destination;
+    use crate::message::payload::data::DataType;
+    use crate::message::Message;
+
+    fn broadcast() -> Message {
+        Message::new_data(
+            Uid::try_from(1).unwrap(),
+            Destination::Broadcast,
+            DataType::new_text("alert"),
+            3,
+            false,
+        )
+    }
+
+    #[test]
+    fn test_broadcast_is_repeated_the_configured_number_of_times() {
+        let mut repeater = BroadcastRepeater::default();
+        let message = broadcast();
+        let repeat = Repeat {
+            count: 2,
+            interval: Duration::from_secs(3),
+        };
+        repeater.push(message.clone(), repeat, Instant::from_secs(0)).unwrap();
+
+        let mut sent_at = heapless::Vec::<u64, 8>::new();
+        for second in 0..=20 {
+            while let Some(repeated) = repeater.next_due(Instant::from_secs(second)) {
+                assert_eq!(repeated.message_id(), message.message_id());
+                sent_at.push(second).unwrap();
+            }
+        }
+
+        assert_eq!(sent_at.as_slice(), &[3, 6]);
+    }
+
+    #[test]
+    fn test_too_many_repeating_broadcasts_are_refused() {
+        let mut repeater = BroadcastRepeater::default();
+        let repeat = Repeat {
+            count: 1,
+            interval: Duration::from_secs(1),
+        };
+        let now = Instant::from_secs(0);
+        for _ in 0..MAX_REPEATING {
+            repeater.push(broadcast(), repeat, now).unwrap();
+        }
+
+        assert!(repeater.push(broadcast(), repeat, now).is_err());
+    }
+
+    #[test]
+    fn test_cancelled_broadcast_is_not_repeated() {
+        let mut repeater = BroadcastRepeater::default();
+        let message = broadcast();
+        let repeat = Repeat {
+            count: 2,
+            interval: Duration::from_secs(1),
+        };
+        repeater.push(message.clone(), repeat, Instant::from_secs(0)).unwrap();
+
+        assert!(repeater.cancel(message.message_id()));
+        assert!(!repeater.cancel(message.message_id()));
+        assert!(repeater.next_due(Instant::from_secs(5)).is_none());
+    }
+}
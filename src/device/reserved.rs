@@ -0,0 +1,71 @@
+use crate::device::device_error::DeviceError;
+use crate::device::Uid;
+
+/// UIDs set aside for infrastructure roles by `RESERVED_UIDS`.
+pub const GATEWAY_UID: Uid = uid(0xFF);
+pub const TIME_MASTER_UID: Uid = uid(0xFE);
+
+/// Default block of UIDs reserved for infrastructure roles.
+pub const RESERVED_UIDS: ReservedUids = ReservedUids::new(0xF0, 0xFF);
+
+const fn uid(value: u8) -> Uid {
+    match Uid::new(value) {
+        Some(uid) => uid,
+        None => panic!("UID must be non-zero"),
+    }
+}
+
+/// Inclusive range of UIDs regular devices may not use.
+///
+/// Ranges are checked at compile time when built as constants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReservedUids {
+    first: Uid,
+    last: Uid,
+}
+
+impl ReservedUids {
+    pub const fn new(first: u8, last: u8) -> Self {
+        assert!(first <= last, "Reserved UID range is empty");
+        Self {
+            first: uid(first),
+            last: uid(last),
+        }
+    }
+
+    pub const fn contains(&self, uid: Uid) -> bool {
+        uid.get() >= self.first.get() && uid.get() <= self.last.get()
+    }
+
+    /// Rejects `uid` if it falls within the reserved range.
+    pub fn check(&self, uid: Uid) -> Result<(), DeviceError> {
+        if self.contains(uid) {
+            Err(DeviceError::ReservedUid)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::reserved::{ReservedUids, GATEWAY_UID, RESERVED_UIDS, TIME_MASTER_UID};
+    use crate::device::Uid;
+
+    #[test]
+    fn test_reserved_uids() {
+        const RANGE: ReservedUids = ReservedUids::new(0x10, 0x1F);
+
+        assert!(RANGE.check(Uid::try_from(0x10).unwrap()).is_err());
+        assert!(RANGE.check(Uid::try_from(0x1F).unwrap()).is_err());
+        assert!(RANGE.check(Uid::try_from(0x0F).unwrap()).is_ok());
+        assert!(RANGE.check(Uid::try_from(0x20).unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_well_known_roles_are_reserved() {
+        assert!(RESERVED_UIDS.contains(GATEWAY_UID));
+        assert!(RESERVED_UIDS.contains(TIME_MASTER_UID));
+        assert!(!RESERVED_UIDS.contains(Uid::try_from(0x01).unwrap()));
+    }
+}
@@ -0,0 +1,117 @@
+use embassy_time::Duration;
+
+use crate::device::Uid;
+
+/// Source of randomness for every randomized behavior of the device.
+pub trait RngSource {
+    fn next_u32(&mut self) -> u32;
+
+    /// Returns a value in `0..bound`, or 0 when `bound` is 0.
+    fn next_below(&mut self, bound: u32) -> u32 {
+        if bound == 0 {
+            0
+        } else {
+            self.next_u32() % bound
+        }
+    }
+
+    /// Returns a random duration in `0..=max`, at millisecond resolution.
+    fn jitter(&mut self, max: Duration) -> Duration {
+        let max_ms = max.as_millis().min(u32::MAX as u64 - 1) as u32;
+        Duration::from_millis(self.next_below(max_ms + 1) as u64)
+    }
+}
+
+/// Xorshift32 generator, the default source of randomness.
+#[derive(Clone, Debug, PartialEq)]
+pub struct XorShiftRng {
+    state: u32,
+}
+
+impl XorShiftRng {
+    pub const fn new(seed: u32) -> Self {
+        // Zero is a fixed point of xorshift
+        Self {
+            state: if seed == 0 { 0x9E37_79B9 } else { seed },
+        }
+    }
+
+    pub const fn from_uid(uid: Uid) -> Self {
+        Self::new((uid.get() as u32).wrapping_mul(0x9E37_79B9))
+    }
+}
+
+impl RngSource for XorShiftRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Duration;
+
+    use crate::device::rng::{RngSource, XorShiftRng};
+    use crate::device::Uid;
+
+    struct SequenceRng<'a> {
+        values: &'a [u32],
+        index: usize,
+    }
+
+    impl RngSource for SequenceRng<'_> {
+        fn next_u32(&mut self) -> u32 {
+            let value = self.values[self.index % self.values.len()];
+            self.index += 1;
+            value
+        }
+    }
+
+    #[test]
+    fn test_fixed_rng_gives_deterministic_backoff() {
+        let mut rng = SequenceRng {
+            values: &[0, 150, 1999, 2001],
+            index: 0,
+        };
+        let max = Duration::from_millis(2000);
+
+        let schedule = [(); 4].map(|_| rng.jitter(max).as_millis());
+
+        assert_eq!(schedule, [0, 150, 1999, 0]);
+    }
+
+    #[test]
+    fn test_xorshift_is_reproducible_per_uid() {
+        let uid = Uid::try_from(7).unwrap();
+        let mut a = XorShiftRng::from_uid(uid);
+        let mut b = XorShiftRng::from_uid(uid);
+        let mut other = XorShiftRng::from_uid(Uid::try_from(8).unwrap());
+
+        let first = a.next_u32();
+        assert_eq!(first, b.next_u32());
+        assert_ne!(first, other.next_u32());
+        assert_eq!(XorShiftRng::new(0).next_u32(), XorShiftRng::new(0).next_u32());
+    }
+
+    #[test]
+    fn test_nodes_powered_up_together_start_apart() {
+        let max = Duration::from_secs(5);
+        let mut delays = [Duration::from_ticks(0); 20];
+        for (uid, delay) in (1..=20).zip(delays.iter_mut()) {
+            // What `LoraDevice::startup_delay` draws with the default generator
+            *delay = XorShiftRng::from_uid(Uid::try_from(uid).unwrap()).jitter(max);
+        }
+
+        assert!(delays.iter().all(|delay| *delay <= max));
+        for (index, delay) in delays.iter().enumerate() {
+            assert!(!delays[index + 1..].contains(delay));
+        }
+        let spread = *delays.iter().max().unwrap() - *delays.iter().min().unwrap();
+        assert!(spread > max / 2);
+    }
+}
@@ -0,0 +1,81 @@
+use embassy_time::{Duration, Instant};
+
+/// Tracks when a periodic task last ran.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Periodic {
+    last: Option<Instant>,
+}
+
+impl Periodic {
+    pub const fn new() -> Self {
+        Self { last: None }
+    }
+
+    /// Returns whether the task is due at `now`, recording the run if it is.
+    ///
+    /// A task that never ran is always due.
+    pub fn poll(&mut self, now: Instant, interval: Duration) -> bool {
+        let due = self.remaining(now, interval) == Duration::from_ticks(0);
+        if due {
+            self.last = Some(now);
+        }
+        due
+    }
+
+    /// Time left at `now` before the task is due.
+    pub fn remaining(&self, now: Instant, interval: Duration) -> Duration {
+        match self.last {
+            None => Duration::from_ticks(0),
+            Some(last) => (last + interval).saturating_duration_since(now),
+        }
+    }
+
+    /// Records a run at `now` without polling.
+    pub fn reset(&mut self, now: Instant) {
+        self.last = Some(now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use crate::device::schedule::Periodic;
+
+    #[test]
+    fn test_runs_at_interval_not_every_tick() {
+        let mut cleanup = Periodic::new();
+        let interval = Duration::from_secs(10);
+
+        let runs = (0..=30)
+            .map(|second| Instant::from_secs(second * 2))
+            .filter(|&now| cleanup.poll(now, interval))
+            .count();
+
+        // At 0, 10, 20, 30, 40, 50 and 60 seconds
+        assert_eq!(runs, 7);
+    }
+
+    #[test]
+    fn test_zero_interval_runs_every_tick() {
+        let mut cleanup = Periodic::new();
+
+        assert!(cleanup.poll(Instant::from_secs(1), Duration::from_secs(0)));
+        assert!(cleanup.poll(Instant::from_secs(1), Duration::from_secs(0)));
+    }
+
+    #[test]
+    fn test_remaining() {
+        let mut task = Periodic::new();
+        task.reset(Instant::from_secs(5));
+
+        assert_eq!(
+            task.remaining(Instant::from_secs(7), Duration::from_secs(10)),
+            Duration::from_secs(8)
+        );
+        assert_eq!(
+            task.remaining(Instant::from_secs(20), Duration::from_secs(10)),
+            Duration::from_secs(0)
+        );
+    }
+}
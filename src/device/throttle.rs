@@ -0,0 +1,112 @@
+use embassy_time::{Duration, Instant};
+
+use crate::device::config::mesh_config::Throughput;
+
+/// Token bucket limiting the outbound throughput of the whole device, or the relays it
+/// performs.
+///
+/// Implemented as a virtual scheduling algorithm: instead of counting tokens, it tracks
+/// when the bucket will be full again, which needs no fractional tokens.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TokenBucket {
+    full_at: Option<Instant>,
+}
+
+impl TokenBucket {
+    pub const fn new() -> Self {
+        Self { full_at: None }
+    }
+
+    /// Whether a message may be transmitted at `now`.
+    pub fn is_available(&self, now: Instant, throughput: &Throughput) -> bool {
+        let Some(full_at) = self.full_at else {
+            return true;
+        };
+        let (_, burst) = Self::shape(throughput);
+        full_at.saturating_duration_since(now) <= burst
+    }
+
+    /// Takes the token of a message transmitted at `now`.
+    pub fn take(&mut self, now: Instant, throughput: &Throughput) {
+        let (interval, _) = Self::shape(throughput);
+        let start = self.full_at.map_or(now, |full_at| full_at.max(now));
+        self.full_at = Some(start + interval);
+    }
+
+    /// Time between two tokens and the burst allowed on top of it.
+    fn shape(throughput: &Throughput) -> (Duration, Duration) {
+        let interval = throughput.period / throughput.messages.max(1);
+        (interval, throughput.period - interval)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+
+    use crate::device::config::mesh_config::Throughput;
+    use crate::device::throttle::TokenBucket;
+
+    #[test]
+    fn test_sustained_rate_stays_under_cap() {
+        let throughput = Throughput::per_second(2);
+        let mut bucket = TokenBucket::new();
+        let mut sent = 0;
+
+        // A device with always something to send, polling every 100 ms for 60 s
+        for tick in 0..600 {
+            let now = Instant::from_millis(tick * 100);
+            while bucket.is_available(now, &throughput) {
+                bucket.take(now, &throughput);
+                sent += 1;
+            }
+        }
+
+        // The initial burst, then two messages per second
+        assert!(sent <= 2 + 2 * 60);
+        assert!(sent >= 2 * 60);
+    }
+
+    #[test]
+    fn test_burst_then_wait() {
+        let throughput = Throughput {
+            messages: 3,
+            period: Duration::from_secs(3),
+        };
+        let mut bucket = TokenBucket::new();
+        let start = Instant::from_secs(0);
+
+        for _ in 0..3 {
+            assert!(bucket.is_available(start, &throughput));
+            bucket.take(start, &throughput);
+        }
+        assert!(!bucket.is_available(start, &throughput));
+        assert!(!bucket.is_available(Instant::from_millis(999), &throughput));
+        assert!(bucket.is_available(Instant::from_secs(1), &throughput));
+    }
+
+    #[test]
+    fn test_relays_stop_when_budget_spent_and_resume_next_window() {
+        let budget = Throughput {
+            messages: 4,
+            period: Duration::from_secs(60),
+        };
+        let mut bucket = TokenBucket::new();
+        let relay = |bucket: &mut TokenBucket, now| {
+            let admitted = bucket.is_available(now, &budget);
+            if admitted {
+                bucket.take(now, &budget);
+            }
+            admitted
+        };
+
+        // A burst of traffic to relay, far more than the budget
+        let relayed = (0..10)
+            .filter(|&second| relay(&mut bucket, Instant::from_secs(second)))
+            .count();
+        assert_eq!(relayed, 4);
+
+        let window_later = Instant::from_secs(60);
+        assert!(relay(&mut bucket, window_later));
+    }
+}
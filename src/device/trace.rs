@@ -0,0 +1,89 @@
+use defmt::Format;
+
+/// Parts of the device whose logs can be tuned independently.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub enum Subsystem {
+    /// Route discovery and forwarding
+    Routing,
+    /// Acknowledgements and retries
+    Acks,
+    /// Frames sent and received
+    Radio,
+}
+
+/// How much a subsystem logs, on top of the global defmt level.
+///
+/// Errors and warnings are always logged.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Format)]
+pub enum Verbosity {
+    Quiet,
+    Info,
+    Debug,
+}
+
+/// Runtime verbosity of every subsystem, all at `Verbosity::Debug` by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct TraceLevels {
+    routing: Verbosity,
+    acks: Verbosity,
+    radio: Verbosity,
+}
+
+impl Default for TraceLevels {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TraceLevels {
+    pub const fn new() -> Self {
+        Self {
+            routing: Verbosity::Debug,
+            acks: Verbosity::Debug,
+            radio: Verbosity::Debug,
+        }
+    }
+
+    pub fn set(&mut self, subsystem: Subsystem, verbosity: Verbosity) {
+        *self.level_mut(subsystem) = verbosity;
+    }
+
+    pub fn level(&self, subsystem: Subsystem) -> Verbosity {
+        match subsystem {
+            Subsystem::Routing => self.routing,
+            Subsystem::Acks => self.acks,
+            Subsystem::Radio => self.radio,
+        }
+    }
+
+    /// Whether logs of `subsystem` at `verbosity` should be emitted.
+    pub fn enabled(&self, subsystem: Subsystem, verbosity: Verbosity) -> bool {
+        self.level(subsystem) >= verbosity
+    }
+
+    fn level_mut(&mut self, subsystem: Subsystem) -> &mut Verbosity {
+        match subsystem {
+            Subsystem::Routing => &mut self.routing,
+            Subsystem::Acks => &mut self.acks,
+            Subsystem::Radio => &mut self.radio,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::device::trace::{Subsystem, TraceLevels, Verbosity};
+
+    #[test]
+    fn test_levels_are_per_subsystem() {
+        let mut levels = TraceLevels::new();
+        levels.set(Subsystem::Radio, Verbosity::Quiet);
+        levels.set(Subsystem::Acks, Verbosity::Info);
+
+        assert_eq!(levels.level(Subsystem::Radio), Verbosity::Quiet);
+        assert!(!levels.enabled(Subsystem::Radio, Verbosity::Info));
+        assert!(levels.enabled(Subsystem::Acks, Verbosity::Info));
+        assert!(!levels.enabled(Subsystem::Acks, Verbosity::Debug));
+        assert!(levels.enabled(Subsystem::Routing, Verbosity::Debug));
+    }
+}
@@ -2,4 +2,5 @@
 
 pub mod device;
 pub mod message;
+pub mod node;
 pub mod route;
@@ -1,85 +1,179 @@
 use core::convert::TryFrom;
+use core::mem::size_of;
+use core::ptr::{addr_of, addr_of_mut};
 
 use defmt::Format;
+use embassy_time::{Duration, Instant};
 use serde::{Deserialize, Serialize};
 
 use payload::Payload;
 use crate::device::{Uid, DEVICE_CONFIG};
+use crate::message::destination::Destination;
 use crate::message::error::MessageError;
 use crate::message::payload::ack::AckType;
 use crate::message::payload::command::CommandType;
-use crate::message::payload::data::DataType;
+use crate::message::payload::data::{DataType, TextDecoding};
 use crate::message::payload::discovery::DiscoveryType;
 use crate::message::payload::route::RouteType;
 
+pub mod destination;
 pub mod error;
 pub mod payload;
 
 #[cfg(test)]
 mod test;
 
-const MAX_TTL: u8 = 10;
-const MAX_MESSAGE_SIZE: usize = 70;
-static mut MESSAGE_ID_COUNTER: u32 = 0;
+pub const MAX_TTL: u8 = 10;
+pub const MAX_MESSAGE_SIZE: usize = 70;
+/// Worst-case bytes postcard adds to `MAX_MESSAGE_SIZE`: the message ID varint, the
+/// local-only flag, the sequence number, the destination and payload variant tags, and the
+/// payload length varint.
+const MAX_ENCODING_OVERHEAD: usize = MAX_ID_VARINT_SIZE + 1 + 1 + 1 + 2 + 1;
+/// A varint carries 7 bits per byte.
+const MAX_ID_VARINT_SIZE: usize = (size_of::<MessageId>() * 8).div_ceil(7);
+/// Largest frame a message takes on the air once COBS encoded, delimiter included.
+///
+/// Buffers receiving frames must be at least this large.
+pub const MAX_WIRE_SIZE: usize = cobs_max_size(MAX_MESSAGE_SIZE + MAX_ENCODING_OVERHEAD);
+static mut MESSAGE_ID_COUNTER: MessageIdCounter = MessageIdCounter::new(0);
 
-fn generate_message_id() -> u32 {
-    unsafe {
-        let id = MESSAGE_ID_COUNTER;
-        MESSAGE_ID_COUNTER = MESSAGE_ID_COUNTER.wrapping_add(1);
+/// Identifier of a message, unique per source until the ID counter wraps around.
+///
+/// IDs are 32 bits by default. The `short-message-id` feature makes them 16 bits, saving up
+/// to two bytes on every frame, but IDs then wrap around after 65536 messages: a source
+/// sending that many while one of its older IDs is still tracked (pending ack, receipt)
+/// can have a new message mistaken for the old one. IDs are compared with
+/// `message_id_offset`, which stays correct across the wrap as long as the IDs compared
+/// are less than half the ID space apart.
+#[cfg(not(feature = "short-message-id"))]
+pub type MessageId = u32;
+#[cfg(feature = "short-message-id")]
+pub type MessageId = u16;
+
+/// How far `id` is ahead of `base`, negative when it is behind, accounting for wraparound.
+#[cfg(not(feature = "short-message-id"))]
+pub fn message_id_offset(id: MessageId, base: MessageId) -> i32 {
+    id.wrapping_sub(base) as i32
+}
+
+/// How far `id` is ahead of `base`, negative when it is behind, accounting for wraparound.
+#[cfg(feature = "short-message-id")]
+pub fn message_id_offset(id: MessageId, base: MessageId) -> i32 {
+    id.wrapping_sub(base) as i16 as i32
+}
+
+/// COBS adds one byte for every 254 bytes of data, at least one, plus the zero delimiter.
+const fn cobs_max_size(len: usize) -> usize {
+    len + len / 254 + 1 + 1
+}
+
+/// Source of message IDs, counting up and wrapping around.
+pub struct MessageIdCounter {
+    next: MessageId,
+}
+
+impl MessageIdCounter {
+    pub const fn new(next: MessageId) -> Self {
+        Self { next }
+    }
+
+    /// Takes the next ID.
+    pub fn take(&mut self) -> MessageId {
+        let id = self.next;
+        self.next = self.next.wrapping_add(1);
         id
     }
+
+    /// The ID `take` returns next.
+    pub fn peek(&self) -> MessageId {
+        self.next
+    }
+}
+
+/// Sets the ID of the next message created by this node.
+///
+/// IDs restart from 0 on every boot by default, reusing IDs that peers may still remember.
+/// Restoring a persisted `next_message_id`, or seeding from a boot counter, avoids it.
+pub fn set_next_message_id(message_id: MessageId) {
+    unsafe {
+        MESSAGE_ID_COUNTER = MessageIdCounter::new(message_id);
+    }
+}
+
+/// ID the next message created by this node will get, to be persisted across reboots.
+pub fn next_message_id() -> MessageId {
+    unsafe { (*addr_of!(MESSAGE_ID_COUNTER)).peek() }
+}
+
+fn generate_message_id() -> MessageId {
+    unsafe { (*addr_of_mut!(MESSAGE_ID_COUNTER)).take() }
 }
 
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Format)]
 pub struct Message {
-    message_id: u32,
+    message_id: MessageId,
     /// Source ID is the UID of the node that sent the message
     source_id: Uid,
-    /// Destination ID is the UID of the node the message is intended for
-    destination_id: Option<Uid>,
+    /// Destination is the node or group the message is intended for
+    destination: Destination,
     /// Time to live is the number of hops a message can take before it is considered expired
     ttl: u8,
     /// Req ack is a flag that indicates if the message requires an acknowledgement
     req_ack: bool,
+    /// Local only messages are meant for direct neighbors and are never relayed
+    local_only: bool,
+    /// Sequence is the number of the message among the ones its source sent to its
+    /// destination, for in-order delivery. Only sequenced messages are numbered
+    sequence: u8,
     /// Payload is the data being sent
     payload: Payload,
+    /// Arrived at is when the message entered this device, it is never transmitted
+    #[serde(skip)]
+    arrived_at: Option<Instant>,
+    /// RSSI and SNR the message was captured with in promiscuous mode, never transmitted
+    #[serde(skip)]
+    rx_signal: Option<(i16, i16)>,
 }
 
 impl Message {
-    pub fn new(source_id: Uid, destination_id: Option<Uid>, payload: Payload, ttl: u8, require_ack: bool) -> Self {
+    pub fn new(source_id: Uid, destination: Destination, payload: Payload, ttl: u8, require_ack: bool) -> Self {
         Self {
             message_id: generate_message_id(),
             source_id,
-            destination_id,
+            destination,
             payload,
             req_ack: require_ack,
+            local_only: false,
+            sequence: 0,
             ttl: ttl.min(MAX_TTL),
+            arrived_at: None,
+            rx_signal: None,
         }
     }
 
-    pub fn new_data(source_id: Uid, destination_id: Option<Uid>, payload: DataType, ttl: u8, require_ack: bool) -> Self {
-        Self::new(source_id, destination_id, Payload::Data(payload), ttl, require_ack)
+    pub fn new_data(source_id: Uid, destination: Destination, payload: DataType, ttl: u8, require_ack: bool) -> Self {
+        Self::new(source_id, destination, Payload::Data(payload), ttl, require_ack)
     }
 
-    pub fn new_ack(source_id: Uid, destination_id: Option<Uid>, payload: AckType, ttl: u8, require_ack: bool) -> Self {
-        Self::new(source_id, destination_id, Payload::Ack(payload), ttl, require_ack)
+    pub fn new_ack(source_id: Uid, destination: Destination, payload: AckType, ttl: u8, require_ack: bool) -> Self {
+        Self::new(source_id, destination, Payload::Ack(payload), ttl, require_ack)
     }
 
-    pub fn new_command(source_id: Uid, destination_id: Option<Uid>, payload: CommandType, ttl: u8, require_ack: bool) -> Self {
-        Self::new(source_id, destination_id, Payload::Command(payload), ttl, require_ack)
+    pub fn new_command(source_id: Uid, destination: Destination, payload: CommandType, ttl: u8, require_ack: bool) -> Self {
+        Self::new(source_id, destination, Payload::Command(payload), ttl, require_ack)
     }
 
-    pub fn new_route(source_id: Uid, destination_id: Option<Uid>, payload: RouteType, ttl: u8, require_ack: bool) -> Self {
-        Self::new(source_id, destination_id, Payload::Route(payload), ttl, require_ack)
+    pub fn new_route(source_id: Uid, destination: Destination, payload: RouteType, ttl: u8, require_ack: bool) -> Self {
+        Self::new(source_id, destination, Payload::Route(payload), ttl, require_ack)
     }
 
-    pub fn new_discovery(source_id: Uid, destination_id: Option<Uid>, ttl: u8, require_ack: bool) -> Self {
+    pub fn new_discovery(source_id: Uid, destination: Destination, ttl: u8, require_ack: bool) -> Self {
         let discovery_payload = DiscoveryType {
             original_ttl: ttl,
             sender_capabilities: unsafe { DEVICE_CONFIG.get().unwrap().unwrap().device_capabilities },
         };
-        Self::new(source_id, destination_id, Payload::Discovery(discovery_payload), ttl, require_ack)
+        Self::new(source_id, destination, Payload::Discovery(discovery_payload), ttl, require_ack)
     }
 
 
@@ -87,11 +181,11 @@ impl Message {
         self.source_id
     }
 
-    pub fn message_id(&self) -> u32 {
+    pub fn message_id(&self) -> MessageId {
         self.message_id
     }
 
-    pub fn set_message_id(&mut self, message_id: u32) {
+    pub fn set_message_id(&mut self, message_id: MessageId) {
         self.message_id = message_id;
     }
 
@@ -99,8 +193,43 @@ impl Message {
         self.req_ack
     }
 
+    pub fn set_req_ack(&mut self, req_ack: bool) {
+        self.req_ack = req_ack;
+    }
+
+    pub fn is_local_only(&self) -> bool {
+        self.local_only
+    }
+
+    /// Restricts the message to direct neighbors: they deliver it but never relay it.
+    pub fn set_local_only(&mut self, local_only: bool) {
+        self.local_only = local_only;
+    }
+
+    pub fn sequence(&self) -> u8 {
+        self.sequence
+    }
+
+    pub fn set_sequence(&mut self, sequence: u8) {
+        self.sequence = sequence;
+    }
+
+    /// Whether the message is numbered for in-order delivery: unicast messages that reach
+    /// the application of their destination.
+    pub fn is_sequenced(&self) -> bool {
+        self.destination_id().is_some()
+            && matches!(
+                self.payload,
+                Payload::Data(_) | Payload::App { .. } | Payload::Command(CommandType::SetConfig(_))
+            )
+    }
+
+    pub fn destination(&self) -> Destination {
+        self.destination
+    }
+
     pub fn destination_id(&self) -> Option<Uid> {
-        self.destination_id
+        self.destination.uid()
     }
 
     pub fn payload(&self) -> &Payload {
@@ -111,6 +240,10 @@ impl Message {
         self.ttl
     }
 
+    pub fn set_ttl(&mut self, ttl: u8) {
+        self.ttl = ttl.min(MAX_TTL);
+    }
+
     pub fn decrement_ttl(&mut self) {
         self.ttl = self.ttl.saturating_sub(1);
     }
@@ -119,8 +252,70 @@ impl Message {
         self.ttl == 0
     }
 
+    pub fn arrived_at(&self) -> Option<Instant> {
+        self.arrived_at
+    }
+
+    /// Records when the message entered this device, unless already known.
+    pub fn mark_arrival(&mut self, now: Instant) {
+        self.arrived_at.get_or_insert(now);
+    }
+
+    /// RSSI and SNR of the frame, known for the messages captured in promiscuous mode.
+    pub fn rx_signal(&self) -> Option<(i16, i16)> {
+        self.rx_signal
+    }
+
+    pub fn set_rx_signal(&mut self, rssi: i16, snr: i16) {
+        self.rx_signal = Some((rssi, snr));
+    }
+
+    /// Whether the message has been on this device for longer than `max_age`.
+    pub fn is_stale(&self, now: Instant, max_age: Duration) -> bool {
+        self.arrived_at
+            .is_some_and(|arrived_at| now.saturating_duration_since(arrived_at) > max_age)
+    }
+
     pub fn is_for_me(&self, uid: Uid) -> bool {
-        self.destination_id == Some(uid) || self.destination_id.is_none()
+        match self.destination {
+            Destination::Unicast(destination) => destination == uid,
+            Destination::Broadcast => true,
+            Destination::Group(_) => false,
+        }
+    }
+}
+
+/// Returns the first `size` bytes of `buf` holding a received frame, or `None` when the
+/// radio reported more bytes than the buffer or a message can hold.
+pub fn received_frame(buf: &mut [u8], size: usize) -> Option<&mut [u8]> {
+    if size > MAX_WIRE_SIZE || size > buf.len() {
+        None
+    } else {
+        Some(&mut buf[..size])
+    }
+}
+
+impl Message {
+    /// Number of bytes the message occupies on the air once encoded, at most `MAX_WIRE_SIZE`.
+    pub fn wire_size(&self) -> Result<usize, MessageError> {
+        self.encode_into(&mut [0; MAX_WIRE_SIZE])
+    }
+
+    /// Decodes a COBS frame, in place, handling text payloads containing invalid UTF-8 as
+    /// `text_decoding` says.
+    pub fn decode(frame: &mut [u8], text_decoding: TextDecoding) -> Result<Self, MessageError> {
+        let mut message: Message = postcard::from_bytes_cobs(frame).map_err(MessageError::from)?;
+        if let Payload::Data(data) = &mut message.payload {
+            data.check_text(text_decoding)?;
+        }
+        Ok(message)
+    }
+
+    /// Encodes the message as a COBS frame into `buf`, returning the frame length.
+    pub fn encode_into(&self, buf: &mut [u8]) -> Result<usize, MessageError> {
+        postcard::to_slice_cobs(self, buf)
+            .map(|frame| frame.len())
+            .map_err(|_| MessageError::SerializationError)
     }
 }
 
@@ -128,14 +323,14 @@ impl TryFrom<&mut [u8]> for Message {
     type Error = MessageError;
 
     fn try_from(data: &mut [u8]) -> Result<Self, Self::Error> {
-        postcard::from_bytes_cobs(data).map_err(|_| MessageError::DeserializationError)
+        Message::decode(data, TextDecoding::Strict)
     }
 }
 
-impl From<Message> for [u8; MAX_MESSAGE_SIZE] {
+impl From<Message> for [u8; MAX_WIRE_SIZE] {
     fn from(message: Message) -> Self {
-        let mut data = [0; MAX_MESSAGE_SIZE];
-        let _ = postcard::to_slice_cobs(&message, &mut data);
+        let mut data = [0; MAX_WIRE_SIZE];
+        let _ = message.encode_into(&mut data);
         data
     }
 }
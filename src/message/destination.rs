@@ -0,0 +1,51 @@
+use defmt::Format;
+use serde::{Deserialize, Serialize};
+
+use crate::device::Uid;
+
+/// Addressing mode of a message.
+///
+/// The variant order is part of the wire format: `Broadcast` and `Unicast` encode
+/// exactly like the `Option<Uid>` they replace (`None` and `Some`), and `Group`
+/// takes the next free discriminant.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Format)]
+pub enum Destination {
+    /// Delivered to and relayed by every node
+    Broadcast,
+    /// Delivered to a single node
+    Unicast(Uid),
+    /// Delivered to and relayed by the nodes subscribed to the group
+    Group(u8),
+}
+
+impl Destination {
+    pub fn uid(&self) -> Option<Uid> {
+        match self {
+            Destination::Unicast(uid) => Some(*uid),
+            _ => None,
+        }
+    }
+
+    pub fn is_broadcast(&self) -> bool {
+        matches!(self, Destination::Broadcast)
+    }
+
+    pub fn is_group(&self) -> bool {
+        matches!(self, Destination::Group(_))
+    }
+}
+
+impl From<Uid> for Destination {
+    fn from(uid: Uid) -> Self {
+        Destination::Unicast(uid)
+    }
+}
+
+impl From<Option<Uid>> for Destination {
+    fn from(uid: Option<Uid>) -> Self {
+        match uid {
+            Some(uid) => Destination::Unicast(uid),
+            None => Destination::Broadcast,
+        }
+    }
+}
@@ -1,10 +1,32 @@
 use defmt::Format;
 use snafu::Snafu;
 
-#[derive(Debug, Snafu, Format)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Snafu, Format)]
 pub enum MessageError {
     #[snafu(display("Failed to deserialize message"))]
     DeserializationError,
     #[snafu(display("Failed to serialize message"))]
     SerializationError,
+    #[snafu(display("Invalid COBS framing"))]
+    CobsDecodeError,
+    #[snafu(display("Message is truncated"))]
+    Truncated,
+    #[snafu(display("Unknown variant or out of range value"))]
+    UnknownVariant,
+    #[snafu(display("Payload exceeds its size limit"))]
+    PayloadTooLarge,
+}
+
+impl From<postcard::Error> for MessageError {
+    fn from(error: postcard::Error) -> Self {
+        match error {
+            postcard::Error::DeserializeBadEncoding => Self::CobsDecodeError,
+            postcard::Error::DeserializeUnexpectedEnd => Self::Truncated,
+            // Serde reports unknown enum discriminants and invalid values as custom errors
+            postcard::Error::DeserializeBadEnum | postcard::Error::SerdeDeCustom => {
+                Self::UnknownVariant
+            }
+            _ => Self::DeserializationError,
+        }
+    }
 }
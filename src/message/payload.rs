@@ -1,13 +1,15 @@
 use core::mem::size_of;
 
 use defmt::Format;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use ack::AckType;
 use command::CommandType;
-use data::DataType;
+use data::{Binary, DataType};
 use route::RouteType;
 
+use crate::message::error::MessageError;
 use crate::message::MAX_MESSAGE_SIZE;
 use crate::message::payload::discovery::DiscoveryType;
 
@@ -28,5 +30,106 @@ pub enum Payload {
     Ack(AckType),
     Route(RouteType),
     Discovery(DiscoveryType),
+    /// Application-defined payload, routed and acknowledged like `Data`.
+    ///
+    /// `type_id` lets applications layer their own protocols, the mesh never interprets it.
+    App { type_id: u8, bytes: Binary },
     // Other payload types...
 }
+
+/// Scheduling precedence of a payload, ordered from lowest to highest.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Format)]
+pub enum PriorityClass {
+    /// Application data
+    Normal,
+    /// Commands addressed to a device
+    High,
+    /// Acknowledgements, discovery and routing traffic
+    Control,
+}
+
+/// Set of payload kinds, matched against payloads regardless of their content.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Format)]
+pub struct PayloadKinds(u8);
+
+impl PayloadKinds {
+    pub const NONE: Self = Self(0);
+    pub const DATA: Self = Self(1 << 0);
+    pub const COMMAND: Self = Self(1 << 1);
+    pub const ACK: Self = Self(1 << 2);
+    pub const ROUTE: Self = Self(1 << 3);
+    pub const DISCOVERY: Self = Self(1 << 4);
+    pub const APP: Self = Self(1 << 5);
+    pub const ALL: Self = Self(0b11_1111);
+
+    /// Kinds in either set.
+    pub const fn with(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// Whether the kind of `payload` is in the set.
+    pub fn contains(&self, payload: &Payload) -> bool {
+        self.0 & payload.kind().0 != 0
+    }
+}
+
+impl Payload {
+    /// Returns the kind of this payload, as a single element set.
+    pub fn kind(&self) -> PayloadKinds {
+        match self {
+            Payload::Data(_) => PayloadKinds::DATA,
+            Payload::Command(_) => PayloadKinds::COMMAND,
+            Payload::Ack(_) => PayloadKinds::ACK,
+            Payload::Route(_) => PayloadKinds::ROUTE,
+            Payload::Discovery(_) => PayloadKinds::DISCOVERY,
+            Payload::App { .. } => PayloadKinds::APP,
+        }
+    }
+
+    /// Returns the priority class every scheduler should use for this payload.
+    pub fn priority_class(&self) -> PriorityClass {
+        match self {
+            Payload::Data(_) | Payload::App { .. } => PriorityClass::Normal,
+            Payload::Command(_) => PriorityClass::High,
+            Payload::Ack(_) | Payload::Route(_) | Payload::Discovery(_) => PriorityClass::Control,
+        }
+    }
+
+    /// Serializes the payload alone into `buf`, in the encoding it has inside a `Message`
+    /// but without its framing, returning the number of bytes written.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, MessageError> {
+        postcard::to_slice(self, buf)
+            .map(|bytes| bytes.len())
+            .map_err(|_| MessageError::SerializationError)
+    }
+
+    /// Deserializes a payload written by `to_bytes`.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, MessageError> {
+        postcard::from_bytes(bytes).map_err(MessageError::from)
+    }
+
+    /// Wraps an application value as an `App` payload tagged with `type_id`.
+    ///
+    /// Fails when the encoded value does not fit in `MAX_PAYLOAD_SIZE` bytes.
+    pub fn app<T: Serialize>(type_id: u8, value: &T) -> Result<Self, MessageError> {
+        let mut buffer = [0; MAX_PAYLOAD_SIZE];
+        let bytes = postcard::to_slice(value, &mut buffer)
+            .map_err(|_| MessageError::SerializationError)?;
+        Ok(Payload::App {
+            type_id,
+            bytes: Binary::new(bytes),
+        })
+    }
+
+    /// Unwraps the application value of an `App` payload tagged with `type_id`.
+    ///
+    /// Returns `None` for other payloads and other type IDs.
+    pub fn app_value<T: DeserializeOwned>(&self, type_id: u8) -> Option<Result<T, MessageError>> {
+        match self {
+            Payload::App { type_id: tag, bytes } if *tag == type_id => {
+                Some(postcard::from_bytes(bytes.as_bytes()).map_err(MessageError::from))
+            }
+            _ => None,
+        }
+    }
+}
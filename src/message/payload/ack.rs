@@ -1,17 +1,47 @@
 use defmt::Format;
 use serde::{Deserialize, Serialize};
+use crate::device::config::device_config::DeviceConfig;
 use crate::device::Uid;
+use crate::message::MessageId;
 
+/// Acknowledgements exchanged between devices.
+///
+/// Delivery is confirmed at two levels: `Success` is a transport ack sent as soon as the
+/// destination receives the frame, while `AppReceipt` is only sent once the application
+/// has consumed the message and called `LoraDevice::confirm_processed`.
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Format)]
 pub enum AckType {
     Success {
-        message_id: u32,
+        message_id: MessageId,
     },
     AckDiscovered {
         hops: u8,
         last_hop: Uid,
     },
     Failure {
-        message_id: u32,
+        message_id: MessageId,
+    },
+    AppReceipt {
+        message_id: MessageId,
+    },
+    /// Answer to a `CommandType::SetConfig`, carrying the configuration actually applied
+    ConfigApplied {
+        message_id: MessageId,
+        config: DeviceConfig,
+    },
+    /// Answer to a `CommandType::Ping`
+    Pong {
+        message_id: MessageId,
+    },
+    /// `Success` carrying a route hint, so the sender learns a route to the `acker`.
+    ///
+    /// Relays rewrite the source of the messages they forward and `last_hop` to themselves,
+    /// so the acker travels in the payload, and its distance is the TTL consumed since the
+    /// ack left it with `original_ttl`.
+    SuccessWithRoute {
+        message_id: MessageId,
+        acker: Uid,
+        original_ttl: u8,
+        last_hop: Uid,
     },
 }
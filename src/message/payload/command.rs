@@ -1,7 +1,12 @@
 use defmt::Format;
 use serde::{Deserialize, Serialize};
 
+use crate::device::config::device_config::DeviceConfig;
+
 #[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize, Format)]
 pub enum CommandType {
-    SetConfig,
+    /// Asks the destination to apply a configuration, answered by an `AckType::ConfigApplied`
+    SetConfig(DeviceConfig),
+    /// Asks the destination to answer with an `AckType::Pong`
+    Ping,
 }
@@ -2,8 +2,71 @@ use core::fmt::{Display, Formatter};
 use defmt::Format;
 use serde::{Deserialize, Deserializer, Serialize};
 
+use crate::message::error::MessageError;
 use crate::message::payload::MAX_PAYLOAD_SIZE;
 
+/// How received text payloads containing invalid UTF-8 are handled, see `Message::decode`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub enum TextDecoding {
+    /// Reject the whole message
+    Strict,
+    /// Replace every invalid sequence with U+FFFD
+    Lossy,
+}
+
+/// Largest payload, in bytes, of every kind of `DataType`, leaving room for instance for
+/// a header of the application. Limits above `MAX_PAYLOAD_SIZE` act as `MAX_PAYLOAD_SIZE`.
+///
+/// The application keeps its own limits and builds its payloads through them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format)]
+pub struct PayloadLimits {
+    pub text: usize,
+    pub binary: usize,
+}
+
+impl PayloadLimits {
+    pub const MAX: Self = Self {
+        text: MAX_PAYLOAD_SIZE,
+        binary: MAX_PAYLOAD_SIZE,
+    };
+
+    /// Text payload of `text`, failing when it is longer than the text limit.
+    pub fn text(&self, text: &str) -> Result<DataType, MessageError> {
+        let (data, truncated) = self.truncated_text(text);
+        if truncated {
+            return Err(MessageError::PayloadTooLarge);
+        }
+        Ok(data)
+    }
+
+    /// Binary payload of `bytes`, failing when they are longer than the binary limit.
+    pub fn binary(&self, bytes: &[u8]) -> Result<DataType, MessageError> {
+        let (data, truncated) = self.truncated_binary(bytes);
+        if truncated {
+            return Err(MessageError::PayloadTooLarge);
+        }
+        Ok(data)
+    }
+
+    /// Text payload of `text` cut to the text limit, on a character boundary, and whether
+    /// it was cut.
+    pub fn truncated_text(&self, text: &str) -> (DataType, bool) {
+        let mut len = text.len().min(self.text).min(MAX_PAYLOAD_SIZE);
+        while !text.is_char_boundary(len) {
+            len -= 1;
+        }
+        let mut data = [0u8; MAX_PAYLOAD_SIZE];
+        data[..len].copy_from_slice(&text.as_bytes()[..len]);
+        (DataType::Text(Text { data, len }), len < text.len())
+    }
+
+    /// Binary payload of `bytes` cut to the binary limit, and whether they were cut.
+    pub fn truncated_binary(&self, bytes: &[u8]) -> (DataType, bool) {
+        let len = bytes.len().min(self.binary).min(MAX_PAYLOAD_SIZE);
+        (DataType::Binary(Binary::new(&bytes[..len])), len < bytes.len())
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, Format)]
 pub enum DataType {
     Text(Text),
@@ -11,19 +74,55 @@ pub enum DataType {
 }
 
 impl DataType {
+    /// Text payload of `text`, silently cut to `MAX_PAYLOAD_SIZE`. See `try_new_text` to
+    /// detect the cut, and `PayloadLimits` for lower limits.
     pub fn new_text(text: &str) -> Self {
-        let bytes = text.as_bytes();
-        let len = bytes.len().min(MAX_PAYLOAD_SIZE);
-        let mut data = [0u8; MAX_PAYLOAD_SIZE];
-        data[..len].copy_from_slice(&bytes[..len]);
-        DataType::Text(Text { data, len })
+        PayloadLimits::MAX.truncated_text(text).0
     }
 
+    /// Binary payload of `bytes`, silently cut to `MAX_PAYLOAD_SIZE`. See `try_new_binary`
+    /// to detect the cut, and `PayloadLimits` for lower limits.
     pub fn new_binary(bytes: &[u8]) -> Self {
-        let mut data = [0; MAX_PAYLOAD_SIZE];
-        let len = bytes.len().min(MAX_PAYLOAD_SIZE);
-        data[..len].copy_from_slice(&bytes[..len]);
-        DataType::Binary(Binary(data))
+        PayloadLimits::MAX.truncated_binary(bytes).0
+    }
+
+    /// Text payload of `text`, failing when it is longer than `MAX_PAYLOAD_SIZE`.
+    pub fn try_new_text(text: &str) -> Result<Self, MessageError> {
+        PayloadLimits::MAX.text(text)
+    }
+
+    /// Binary payload of `bytes`, failing when they are longer than `MAX_PAYLOAD_SIZE`.
+    pub fn try_new_binary(bytes: &[u8]) -> Result<Self, MessageError> {
+        PayloadLimits::MAX.binary(bytes)
+    }
+
+    /// Applies `mode` to a received text payload containing invalid UTF-8.
+    pub(crate) fn check_text(&mut self, mode: TextDecoding) -> Result<(), MessageError> {
+        let DataType::Text(text) = self else {
+            return Ok(());
+        };
+        let bytes = &text.data[..text.len];
+        if core::str::from_utf8(bytes).is_ok() {
+            return Ok(());
+        }
+        match mode {
+            // Same error as any other value out of range
+            TextDecoding::Strict => Err(MessageError::UnknownVariant),
+            TextDecoding::Lossy => {
+                *text = Text::from_utf8_lossy(bytes);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the payload carries no bytes at all.
+    ///
+    /// Empty payloads are valid and round-trip to an equal empty payload.
+    pub fn is_empty(&self) -> bool {
+        match self {
+            DataType::Text(text) => text.len == 0,
+            DataType::Binary(binary) => binary.len == 0,
+        }
     }
 }
 
@@ -33,6 +132,35 @@ pub struct Text {
     len: usize,
 }
 
+impl Text {
+    fn from_utf8_lossy(bytes: &[u8]) -> Self {
+        const REPLACEMENT: &[u8] = "\u{FFFD}".as_bytes();
+
+        let mut data = [0u8; MAX_PAYLOAD_SIZE];
+        let mut len = 0;
+        let mut append = |part: &[u8]| {
+            let end = len + part.len();
+            if end > MAX_PAYLOAD_SIZE {
+                return false;
+            }
+            data[len..end].copy_from_slice(part);
+            len = end;
+            true
+        };
+        'chunks: for chunk in bytes.utf8_chunks() {
+            for c in chunk.valid().chars() {
+                if !append(c.encode_utf8(&mut [0; 4]).as_bytes()) {
+                    break 'chunks;
+                }
+            }
+            if !chunk.invalid().is_empty() && !append(REPLACEMENT) {
+                break;
+            }
+        }
+        Text { data, len }
+    }
+}
+
 impl Display for Text {
     fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
         match core::str::from_utf8(&self.data[..self.len]) {
@@ -71,9 +199,7 @@ impl<'de> Deserialize<'de> for Text {
             where
                 E: serde::de::Error,
             {
-                // Validate UTF-8
-                core::str::from_utf8(v).map_err(E::custom)?;
-
+                // UTF-8 is checked once the message is decoded, see `DataType::check_text`
                 let len = v.len().min(MAX_PAYLOAD_SIZE);
                 let mut data = [0u8; MAX_PAYLOAD_SIZE];
                 data[..len].copy_from_slice(&v[..len]);
@@ -85,15 +211,36 @@ impl<'de> Deserialize<'de> for Text {
     }
 }
 
+/// Bytes of a binary payload, up to `MAX_PAYLOAD_SIZE`.
+///
+/// Only the bytes in use go on the wire, prefixed with their count. Frames carrying them
+/// cannot be decoded by nodes that still send the whole zero-padded buffer.
 #[derive(Clone, Debug, PartialEq, Format)]
-pub struct Binary([u8; MAX_PAYLOAD_SIZE]);
+pub struct Binary {
+    data: [u8; MAX_PAYLOAD_SIZE],
+    len: usize,
+}
+
+impl Binary {
+    /// Copies `bytes`, truncated to `MAX_PAYLOAD_SIZE`.
+    pub fn new(bytes: &[u8]) -> Self {
+        let mut data = [0; MAX_PAYLOAD_SIZE];
+        let len = bytes.len().min(MAX_PAYLOAD_SIZE);
+        data[..len].copy_from_slice(&bytes[..len]);
+        Binary { data, len }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+}
 
 impl Serialize for Binary {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        serializer.serialize_bytes(&self.0)
+        serializer.serialize_bytes(self.as_bytes())
     }
 }
 
@@ -110,7 +257,10 @@ impl<'de> Deserialize<'de> for Binary {
         }
         let mut data = [0; MAX_PAYLOAD_SIZE];
         data[..bytes.len()].copy_from_slice(bytes);
-        Ok(Binary(data))
+        Ok(Binary {
+            data,
+            len: bytes.len(),
+        })
     }
 }
 
@@ -118,9 +268,37 @@ impl<'de> Deserialize<'de> for Binary {
 mod test {
     use postcard::{from_bytes, to_allocvec};
     use crate::device::Uid;
-    use crate::message::Message;
+    use crate::message::{Message, MAX_WIRE_SIZE};
+    use crate::message::destination::Destination;
     use crate::message::payload::{Payload, MAX_PAYLOAD_SIZE};
-    use crate::message::payload::data::DataType;
+    use crate::message::error::MessageError;
+    use crate::message::payload::data::{Binary, DataType, PayloadLimits, TextDecoding};
+
+    #[test]
+    fn test_text_invalid_utf8_decoding() {
+        let mut message = Message::new_data(
+            Uid::try_from(1).unwrap(),
+            Destination::Broadcast,
+            DataType::new_text("a~b"),
+            3,
+            false,
+        );
+        message.set_message_id(1);
+        let mut frame = [0u8; MAX_WIRE_SIZE];
+        let len = message.encode_into(&mut frame).unwrap();
+        let tilde = frame.iter().position(|&byte| byte == b'~').unwrap();
+        frame[tilde] = 0xFF;
+
+        assert_eq!(
+            Message::decode(&mut frame.clone()[..len], TextDecoding::Strict),
+            Err(MessageError::UnknownVariant)
+        );
+        let lossy = Message::decode(&mut frame[..len], TextDecoding::Lossy).unwrap();
+        match lossy.payload() {
+            Payload::Data(DataType::Text(text)) => assert_eq!(text.to_string(), "a\u{FFFD}b"),
+            _ => panic!("Expected a text payload"),
+        }
+    }
 
     #[test]
     fn test_message_serialization_deserialization_thorough() {
@@ -139,7 +317,7 @@ mod test {
             let original_payload = Payload::Data(DataType::new_text(case));
             let original_message = Message::new(
                 Uid::try_from(1).unwrap(),
-                Some(Uid::try_from(2).unwrap()),
+                Destination::Unicast(Uid::try_from(2).unwrap()),
                 original_payload.clone(),
                 10,
                 false,
@@ -164,4 +342,61 @@ mod test {
             }
         }
     }
+
+    #[test]
+    fn test_payload_limits_at_limit() {
+        let limits = PayloadLimits { text: 8, binary: 4 };
+
+        assert_eq!(limits.text("12345678"), Ok(DataType::new_text("12345678")));
+        assert_eq!(limits.binary(&[1, 2, 3, 4]), Ok(DataType::new_binary(&[1, 2, 3, 4])));
+        assert!(!limits.truncated_text("12345678").1);
+        assert!(PayloadLimits::MAX.text(&"a".repeat(MAX_PAYLOAD_SIZE)).is_ok());
+    }
+
+    #[test]
+    fn test_payload_limits_over_limit() {
+        let limits = PayloadLimits { text: 8, binary: 4 };
+
+        assert_eq!(limits.text("123456789"), Err(MessageError::PayloadTooLarge));
+        assert_eq!(limits.binary(&[1, 2, 3, 4, 5]), Err(MessageError::PayloadTooLarge));
+        assert_eq!(
+            limits.truncated_binary(&[1, 2, 3, 4, 5]),
+            (DataType::new_binary(&[1, 2, 3, 4]), true)
+        );
+        // Never cut inside a character, "🦀" takes four bytes
+        assert_eq!(
+            limits.truncated_text("12345🦀"),
+            (DataType::new_text("12345"), true)
+        );
+        assert!(PayloadLimits::MAX.text(&"a".repeat(MAX_PAYLOAD_SIZE + 1)).is_err());
+    }
+
+    #[test]
+    fn test_constructors_are_capped_at_max_payload_size() {
+        let over_size = "a".repeat(MAX_PAYLOAD_SIZE + 1);
+        let limits = PayloadLimits { text: 8, binary: 4 };
+
+        // Limits held by the application do not change the plain constructors
+        assert_eq!(limits.text("123456789"), Err(MessageError::PayloadTooLarge));
+        assert!(DataType::try_new_text("123456789").is_ok());
+        assert_eq!(DataType::try_new_text(&over_size), Err(MessageError::PayloadTooLarge));
+        assert_eq!(
+            DataType::new_text(&over_size),
+            DataType::new_text(&over_size[..MAX_PAYLOAD_SIZE])
+        );
+        assert_eq!(
+            DataType::try_new_binary(&[0; MAX_PAYLOAD_SIZE + 1]),
+            Err(MessageError::PayloadTooLarge)
+        );
+    }
+
+    #[test]
+    fn test_binary_sends_only_the_bytes_in_use() {
+        let binary = Binary::new(&[0, 1, 255]);
+
+        let encoded = to_allocvec(&binary).unwrap();
+
+        assert_eq!(encoded.as_slice(), &[3, 0, 1, 255]);
+        assert_eq!(from_bytes::<Binary>(&encoded).unwrap().as_bytes(), &[0, 1, 255]);
+    }
 }
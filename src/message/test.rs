@@ -1,11 +1,34 @@
 use core::convert::TryFrom;
 
-use postcard::{from_bytes, to_allocvec};
+use embassy_time::{Duration, Instant};
+use postcard::{from_bytes, to_allocvec, to_allocvec_cobs};
+use serde::{Deserialize, Serialize};
 
 use crate::device::Uid;
+use crate::message::destination::Destination;
+use crate::message::error::MessageError;
 use crate::message::payload::data::DataType;
-use crate::message::payload::Payload;
-use crate::message::Message;
+use crate::message::payload::ack::AckType;
+use crate::message::payload::command::CommandType;
+use crate::message::payload::{Payload, PayloadKinds, PriorityClass};
+use crate::device::config::device_config::{DeviceCapabilities, DeviceConfig};
+use crate::message::payload::discovery::DiscoveryType;
+use crate::message::payload::route::RouteType;
+use crate::message::payload::data::Binary;
+use crate::message::payload::MAX_PAYLOAD_SIZE;
+use crate::message::{
+    message_id_offset, received_frame, Message, MessageId, MessageIdCounter, MAX_WIRE_SIZE,
+};
+
+/// Encodes `message` like the TX path and decodes it like the RX path.
+pub fn assert_roundtrip(message: &Message) {
+    let mut buffer = [0u8; MAX_WIRE_SIZE];
+    message.encode_into(&mut buffer).unwrap();
+
+    let decoded = Message::try_from(&mut buffer[..]).unwrap();
+
+    assert_eq!(&decoded, message);
+}
 
 #[test]
 fn test_message() {
@@ -14,7 +37,7 @@ fn test_message() {
     let payload = Payload::Data(DataType::new_text("Hello World!"));
     let ttl = 10;
 
-    let message = Message::new(source_id, Some(destination_id), payload.clone(), ttl, false);
+    let message = Message::new(source_id, Destination::Unicast(destination_id), payload.clone(), ttl, false);
 
     assert_eq!(message.source_id(), source_id);
     assert_eq!(message.destination_id(), Some(destination_id));
@@ -32,7 +55,7 @@ fn test_message_decrement_ttl() {
     let payload = Payload::Data(DataType::new_text("Hello World!"));
     let ttl = 10;
 
-    let mut message = Message::new(source_id, Some(destination_id), payload, ttl, false);
+    let mut message = Message::new(source_id, Destination::Unicast(destination_id), payload, ttl, false);
 
     assert_eq!(message.ttl(), ttl);
     message.decrement_ttl();
@@ -46,7 +69,7 @@ fn test_message_is_expired() {
     let payload = Payload::Data(DataType::new_text("Hello World!"));
     let ttl = 10;
 
-    let mut message = Message::new(source_id, Some(destination_id), payload, ttl, false);
+    let mut message = Message::new(source_id, Destination::Unicast(destination_id), payload, ttl, false);
 
     assert_eq!(message.is_expired(), false);
     for _ in 0..ttl {
@@ -62,7 +85,7 @@ fn test_message_serialization_deserialization() {
     let payload = Payload::Data(DataType::new_text("Hello World!"));
     let ttl = 10;
 
-    let message = Message::new(source_id, Some(destination_id), payload.clone(), ttl, false);
+    let message = Message::new(source_id, Destination::Unicast(destination_id), payload.clone(), ttl, false);
     let serialized = to_allocvec(&message).unwrap();
     let deserialized: Message = from_bytes(&serialized).unwrap();
 
@@ -86,7 +109,7 @@ fn test_broadcast_message_creation() {
     let payload = Payload::Data(DataType::new_text("Hello World!"));
     let ttl = 10;
 
-    let message = Message::new(source_id, None, payload, ttl, false);
+    let message = Message::new(source_id, Destination::Broadcast, payload, ttl, false);
 
     assert_eq!(message.destination_id(), None);
     assert_eq!(message.is_for_me(Uid::try_from(0x02).unwrap()), true); // Assuming 'is_for_me' checks if the message is a broadcast
@@ -99,9 +122,424 @@ fn test_message_for_me() {
     let payload = Payload::Data(DataType::new_text("Hello World!"));
     let ttl = 10;
 
-    let message = Message::new(source_id, Some(destination_id), payload, ttl, false);
+    let message = Message::new(source_id, Destination::Unicast(destination_id), payload, ttl, false);
 
     assert_eq!(message.is_for_me(destination_id), true);
     assert_eq!(message.is_for_me(source_id), false);
 }
 
+#[test]
+fn test_group_message_is_not_for_me() {
+    let source_id = Uid::try_from(0x01).unwrap();
+    let payload = Payload::Data(DataType::new_text("Hello World!"));
+
+    let message = Message::new(source_id, Destination::Group(7), payload, 10, false);
+
+    assert_eq!(message.destination(), Destination::Group(7));
+    assert_eq!(message.destination_id(), None);
+    assert_eq!(message.is_for_me(Uid::try_from(0x02).unwrap()), false);
+}
+
+#[test]
+fn test_destination_wire_compatibility() {
+    let uid = Uid::try_from(0x02).unwrap();
+
+    assert_eq!(
+        to_allocvec(&Destination::Unicast(uid)).unwrap(),
+        to_allocvec(&Some(uid)).unwrap()
+    );
+    assert_eq!(
+        to_allocvec(&Destination::Broadcast).unwrap(),
+        to_allocvec(&Option::<Uid>::None).unwrap()
+    );
+
+    let group = to_allocvec(&Destination::Group(7)).unwrap();
+    assert_eq!(from_bytes::<Destination>(&group).unwrap(), Destination::Group(7));
+}
+
+#[test]
+fn test_payload_priority_ordering() {
+    let mut payloads = [
+        Payload::Data(DataType::new_text("Hello World!")),
+        Payload::Ack(AckType::Success { message_id: 1 }),
+        Payload::Command(CommandType::SetConfig(DeviceConfig::default())),
+        Payload::Data(DataType::new_binary(&[1, 2, 3])),
+    ];
+
+    payloads.sort_by_key(|payload| core::cmp::Reverse(payload.priority_class()));
+
+    let classes = payloads.map(|payload| payload.priority_class());
+    assert_eq!(
+        classes,
+        [
+            PriorityClass::Control,
+            PriorityClass::High,
+            PriorityClass::Normal,
+            PriorityClass::Normal,
+        ]
+    );
+}
+
+#[test]
+fn test_deserialization_error_categories() {
+    let mut bad_framing = [0x05, 0x01, 0x00];
+    assert!(matches!(
+        Message::try_from(&mut bad_framing[..]),
+        Err(MessageError::CobsDecodeError)
+    ));
+
+    // Message ID and source only
+    let mut truncated = to_allocvec_cobs(&(5u32, 1u8)).unwrap();
+    assert!(matches!(
+        Message::try_from(truncated.as_mut_slice()),
+        Err(MessageError::Truncated)
+    ));
+
+    // Destination discriminant 9 does not exist
+    let mut unknown_variant = to_allocvec_cobs(&(5u32, 1u8, 9u8)).unwrap();
+    assert!(matches!(
+        Message::try_from(unknown_variant.as_mut_slice()),
+        Err(MessageError::UnknownVariant)
+    ));
+
+    // Message ID varint overflowing a u32
+    let mut bad_varint = to_allocvec_cobs(&[0xFFu8; 6]).unwrap();
+    assert!(matches!(
+        Message::try_from(bad_varint.as_mut_slice()),
+        Err(MessageError::DeserializationError)
+    ));
+}
+
+/// One payload of every variant, `source_id` standing in for the UIDs they carry.
+fn every_payload(source_id: Uid) -> [Payload; 16] {
+    [
+        Payload::Data(DataType::new_text("Hello World!")),
+        Payload::Data(DataType::new_binary(&[0, 1, 0, 255])),
+        Payload::Command(CommandType::SetConfig(DeviceConfig::default())),
+        Payload::Command(CommandType::Ping),
+        Payload::Ack(AckType::Success { message_id: 42 }),
+        Payload::Ack(AckType::AckDiscovered {
+            hops: 2,
+            last_hop: source_id,
+        }),
+        Payload::Ack(AckType::Failure { message_id: 42 }),
+        Payload::Ack(AckType::AppReceipt { message_id: 42 }),
+        Payload::Ack(AckType::ConfigApplied {
+            message_id: 42,
+            config: DeviceConfig::default(),
+        }),
+        Payload::Ack(AckType::Pong { message_id: 42 }),
+        Payload::Ack(AckType::SuccessWithRoute {
+            message_id: 42,
+            acker: source_id,
+            original_ttl: 5,
+            last_hop: source_id,
+        }),
+        Payload::Route(RouteType::Request),
+        Payload::Route(RouteType::Response),
+        Payload::Route(RouteType::Error),
+        Payload::Discovery(DiscoveryType {
+            original_ttl: 3,
+            sender_capabilities: DeviceCapabilities::LoraWifi,
+        }),
+        Payload::app(7, &[1u8, 2, 3]).unwrap(),
+    ]
+}
+
+#[test]
+fn test_roundtrip_every_payload() {
+    let source_id = Uid::try_from(0x01).unwrap();
+    let destination = Destination::Unicast(Uid::try_from(0x02).unwrap());
+
+    for payload in every_payload(source_id) {
+        assert_roundtrip(&Message::new(source_id, destination, payload, 10, true));
+    }
+}
+
+#[test]
+fn test_payload_bytes_roundtrip_and_match_message_encoding() {
+    let source_id = Uid::try_from(0x01).unwrap();
+    let destination = Destination::Unicast(Uid::try_from(0x02).unwrap());
+
+    for payload in every_payload(source_id) {
+        let mut buffer = [0u8; MAX_WIRE_SIZE];
+        let len = payload.to_bytes(&mut buffer).unwrap();
+        let bytes = &buffer[..len];
+
+        assert_eq!(Payload::from_bytes(bytes), Ok(payload.clone()));
+
+        let message = Message::new(source_id, destination, payload, 10, true);
+        let encoded = to_allocvec(&message).unwrap();
+        assert!(encoded.windows(len).any(|window| window == bytes));
+    }
+}
+
+#[test]
+fn test_received_frame_rejects_oversized_size() {
+    let mut buffer = [0u8; MAX_WIRE_SIZE];
+
+    assert_eq!(received_frame(&mut buffer, 12).map(|frame| frame.len()), Some(12));
+    assert_eq!(
+        received_frame(&mut buffer, MAX_WIRE_SIZE).map(|frame| frame.len()),
+        Some(MAX_WIRE_SIZE)
+    );
+    assert!(received_frame(&mut buffer, 200).is_none());
+    assert!(received_frame(&mut buffer[..10], 20).is_none());
+}
+
+#[test]
+fn test_message_staleness() {
+    let source_id = Uid::try_from(0x01).unwrap();
+    let payload = Payload::Data(DataType::new_text("Hello World!"));
+    let mut message = Message::new(source_id, Destination::Broadcast, payload, 10, false);
+    let max_age = Duration::from_secs(30);
+
+    assert!(!message.is_stale(Instant::from_secs(1000), max_age));
+
+    message.mark_arrival(Instant::from_secs(100));
+    message.mark_arrival(Instant::from_secs(120));
+    assert_eq!(message.arrived_at(), Some(Instant::from_secs(100)));
+    assert!(!message.is_stale(Instant::from_secs(130), max_age));
+    assert!(message.is_stale(Instant::from_secs(131), max_age));
+
+    // The arrival time is local to the device
+    let serialized = to_allocvec(&message).unwrap();
+    let deserialized: Message = from_bytes(&serialized).unwrap();
+    assert_eq!(deserialized.arrived_at(), None);
+}
+
+#[test]
+fn test_wire_size_per_payload() {
+    let source_id = Uid::try_from(0x01).unwrap();
+    let destination = Destination::Unicast(Uid::try_from(0x02).unwrap());
+    // Header: message ID, source, destination, TTL, ack and local-only flags take 7 bytes,
+    // COBS framing adds an overhead byte and the delimiter
+    let cases = [
+        (Payload::Data(DataType::new_text("Hi")), 14),
+        (Payload::Data(DataType::new_binary(&[1, 2, 3])), 15),
+        (Payload::Command(CommandType::Ping), 11),
+        (Payload::Ack(AckType::Success { message_id: 42 }), 12),
+        (Payload::Route(RouteType::Request), 11),
+        (
+            Payload::Discovery(DiscoveryType {
+                original_ttl: 3,
+                sender_capabilities: DeviceCapabilities::Lora,
+            }),
+            12,
+        ),
+    ];
+
+    for (payload, expected) in cases {
+        let mut message = Message::new(source_id, destination, payload, 10, false);
+        message.set_message_id(1);
+
+        let size = message.wire_size().unwrap();
+
+        assert_eq!(size, expected);
+        let mut buffer = [0u8; MAX_WIRE_SIZE];
+        assert_eq!(message.encode_into(&mut buffer).unwrap(), size);
+    }
+}
+
+#[test]
+fn test_empty_payloads_roundtrip() {
+    for data in [DataType::new_text(""), DataType::new_binary(&[])] {
+        assert!(data.is_empty());
+        let message = Message::new_data(
+            Uid::try_from(0x01).unwrap(),
+            Destination::Unicast(Uid::try_from(0x02).unwrap()),
+            data.clone(),
+            10,
+            false,
+        );
+
+        assert_roundtrip(&message);
+        let mut buffer = [0u8; MAX_WIRE_SIZE];
+        let size = message.encode_into(&mut buffer).unwrap();
+        let decoded = Message::try_from(&mut buffer[..size]).unwrap();
+        assert_eq!(decoded.payload(), &Payload::Data(data));
+    }
+}
+
+#[test]
+fn test_maximal_message_fits_wire_size() {
+    let text = "a".repeat(MAX_PAYLOAD_SIZE);
+    let mut message = Message::new_data(
+        Uid::try_from(0xFF).unwrap(),
+        Destination::Unicast(Uid::try_from(0xFE).unwrap()),
+        DataType::new_text(&text),
+        10,
+        true,
+    );
+    message.set_message_id(MessageId::MAX);
+
+    assert_eq!(message.wire_size().unwrap(), MAX_WIRE_SIZE);
+    assert_roundtrip(&message);
+}
+
+#[test]
+fn test_local_only_flag_roundtrip() {
+    let mut message = Message::new_data(
+        Uid::try_from(0x01).unwrap(),
+        Destination::Broadcast,
+        DataType::new_text("presence"),
+        1,
+        false,
+    );
+    assert!(!message.is_local_only());
+
+    message.set_local_only(true);
+
+    assert_roundtrip(&message);
+    let mut buffer = [0u8; MAX_WIRE_SIZE];
+    message.encode_into(&mut buffer).unwrap();
+    assert!(Message::try_from(&mut buffer[..]).unwrap().is_local_only());
+}
+
+#[test]
+fn test_message_ids_start_from_configured_value() {
+    const START: MessageId = MessageId::MAX / 2 + 1;
+    // A counter of its own, the global one is shared with the tests running alongside
+    let mut ids = MessageIdCounter::new(START);
+
+    assert_eq!(ids.take(), START);
+    assert_eq!(ids.take(), START + 1);
+    assert_eq!(ids.peek(), START + 2);
+
+    let mut wrapping = MessageIdCounter::new(MessageId::MAX);
+    assert_eq!(wrapping.take(), MessageId::MAX);
+    assert_eq!(wrapping.take(), 0);
+}
+
+#[test]
+fn test_app_payload_roundtrip() {
+    const READING: u8 = 0x10;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        sensor: u8,
+        celsius: i16,
+    }
+
+    let reading = Reading {
+        sensor: 3,
+        celsius: -12,
+    };
+    let message = Message::new(
+        Uid::try_from(0x01).unwrap(),
+        Destination::Unicast(Uid::try_from(0x02).unwrap()),
+        Payload::app(READING, &reading).unwrap(),
+        5,
+        true,
+    );
+
+    let mut buffer = [0u8; MAX_WIRE_SIZE];
+    message.encode_into(&mut buffer).unwrap();
+    let decoded = Message::try_from(&mut buffer[..]).unwrap();
+
+    assert_eq!(decoded, message);
+    assert_eq!(decoded.payload().priority_class(), PriorityClass::Normal);
+    assert_eq!(decoded.payload().app_value::<Reading>(READING).unwrap().unwrap(), reading);
+    assert!(decoded.payload().app_value::<Reading>(READING + 1).is_none());
+    // Slices carry a length prefix, so a full payload of bytes no longer fits
+    assert!(Payload::app(READING, &[0u8; MAX_PAYLOAD_SIZE][..]).is_err());
+}
+
+#[test]
+fn test_message_id_offset_across_wraparound() {
+    assert_eq!(message_id_offset(5, 3), 2);
+    assert_eq!(message_id_offset(3, 5), -2);
+    assert_eq!(message_id_offset(0, MessageId::MAX), 1);
+    assert_eq!(message_id_offset(MessageId::MAX, 1), -2);
+}
+
+/// Encoded messages pinned in `golden.txt`, one `name hex` line each.
+const GOLDEN_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src/message/golden.txt");
+
+/// One message per payload variant, with fixed IDs so their encoding is stable.
+fn golden_messages() -> [(&'static str, Message); 9] {
+    let uid = |uid: u8| Uid::try_from(uid).unwrap();
+    let message = |id, source, destination, ttl, req_ack, payload| {
+        let mut message = Message::new(uid(source), destination, payload, ttl, req_ack);
+        message.set_message_id(id);
+        message
+    };
+    let mut local_only = message(
+        8,
+        1,
+        Destination::Broadcast,
+        1,
+        false,
+        Payload::Data(DataType::new_text("x")),
+    );
+    local_only.set_local_only(true);
+
+    [
+        ("data_text", message(1, 1, Destination::Unicast(uid(2)), 5, true, Payload::Data(DataType::new_text("Hi")))),
+        ("data_binary", message(2, 1, Destination::Broadcast, 3, false, Payload::Data(DataType::new_binary(&[0, 1, 255])))),
+        ("command_ping", message(300, 1, Destination::Unicast(uid(2)), 1, false, Payload::Command(CommandType::Ping))),
+        ("ack_success", message(3, 2, Destination::Unicast(uid(1)), 5, false, Payload::Ack(AckType::Success { message_id: 300 }))),
+        (
+            "ack_discovered",
+            message(4, 2, Destination::Unicast(uid(1)), 3, false, Payload::Ack(AckType::AckDiscovered { hops: 2, last_hop: uid(3) })),
+        ),
+        ("route_request", message(5, 1, Destination::Group(7), 2, false, Payload::Route(RouteType::Request))),
+        (
+            "discovery",
+            message(
+                6,
+                1,
+                Destination::Broadcast,
+                3,
+                true,
+                Payload::Discovery(DiscoveryType {
+                    original_ttl: 3,
+                    sender_capabilities: DeviceCapabilities::LoraWifi,
+                }),
+            ),
+        ),
+        (
+            "app",
+            message(7, 1, Destination::Unicast(uid(2)), 5, false, Payload::App { type_id: 16, bytes: Binary::new(&[0xAB, 0xCD]) }),
+        ),
+        ("local_only", local_only),
+    ]
+}
+
+/// Fails on any change to the wire format of the golden messages.
+///
+/// After an intended format change, run the tests with `QUADRANET_BLESS=1` to rewrite
+/// `golden.txt`, and review its diff.
+#[test]
+fn test_wire_format_matches_golden_file() {
+    let mut encoded = String::new();
+    for (name, message) in golden_messages() {
+        let frame = to_allocvec_cobs(&message).unwrap();
+        let hex: String = frame.iter().map(|byte| format!("{:02x}", byte)).collect();
+        encoded.push_str(&format!("{} {}\n", name, hex));
+    }
+
+    if std::env::var_os("QUADRANET_BLESS").is_some() {
+        std::fs::write(GOLDEN_PATH, &encoded).unwrap();
+        return;
+    }
+    let golden = std::fs::read_to_string(GOLDEN_PATH).unwrap();
+    for (expected, actual) in golden.lines().zip(encoded.lines()) {
+        assert_eq!(actual, expected, "wire format changed, see golden.txt");
+    }
+    assert_eq!(golden.lines().count(), encoded.lines().count());
+}
+
+#[test]
+fn test_filtered_payload_kinds_are_not_handled_locally() {
+    let commands_only = PayloadKinds::COMMAND.with(PayloadKinds::ACK);
+    let data = Payload::Data(DataType::new_text("relay me"));
+    let route = Payload::Route(RouteType::Request);
+
+    assert!(commands_only.contains(&Payload::Command(CommandType::Ping)));
+    assert!(commands_only.contains(&Payload::Ack(AckType::Success { message_id: 1 })));
+    assert!(!commands_only.contains(&data));
+    assert!(!commands_only.contains(&route));
+    assert!(PayloadKinds::ALL.contains(&data) && PayloadKinds::ALL.contains(&route));
+    assert!(!PayloadKinds::NONE.contains(&data));
+}
@@ -0,0 +1,167 @@
+//! Batteries-included facade over `LoraDevice`.
+//!
+//! `MeshNode` owns the default queues and the application channel, so that a node can be
+//! set up in a few lines. The underlying `LoraDevice` stays reachable for everything the
+//! facade does not cover.
+//!
+//! ```no_run
+//! use core::ptr::addr_of_mut;
+//!
+//! use embedded_hal_async::delay::DelayNs;
+//! use lora_phy::mod_traits::RadioKind;
+//! use lora_phy::LoRa;
+//! use quadranet::device::config::device_config::DeviceConfig;
+//! use quadranet::device::config::lora_config::LoraConfig;
+//! use quadranet::device::Uid;
+//! use quadranet::message::payload::data::DataType;
+//! use quadranet::node::{MeshNode, MeshResources};
+//!
+//! static mut RESOURCES: MeshResources = MeshResources::new();
+//!
+//! async fn echo<RK: RadioKind, DLY: DelayNs>(mut radio: LoRa<RK, DLY>) {
+//!     let lora_config = LoraConfig::new(&mut radio);
+//!     let resources = unsafe { &mut *addr_of_mut!(RESOURCES) };
+//!     let uid = Uid::new(1).unwrap();
+//!     let mut node = MeshNode::new(uid, radio, lora_config, DeviceConfig::default(), resources);
+//!
+//!     node.start().await;
+//!     node.broadcast(DataType::new_text("hello")).unwrap();
+//!     loop {
+//!         node.poll().await;
+//!         while let Some(message) = node.recv() {
+//!             if message.source_id() != uid {
+//!                 let _ = node.send(message.source_id(), DataType::new_text("echo"), true);
+//!             }
+//!         }
+//!     }
+//! }
+//! ```
+
+use embedded_hal_async::delay::DelayNs;
+use lora_phy::mod_traits::RadioKind;
+use lora_phy::LoRa;
+
+use crate::device::collections::MessageQueue;
+use crate::device::config::device_config::DeviceConfig;
+use crate::device::config::lora_config::LoraConfig;
+use crate::device::config::mesh_config::MeshConfig;
+use crate::device::device_error::DeviceError;
+use crate::device::{run_quadranet, InQueue, LoraDevice, OutQueue, Uid};
+use crate::message::destination::Destination;
+use crate::message::payload::data::DataType;
+use crate::message::{Message, MessageId};
+
+/// Hops a message sent through `MeshNode` may take.
+pub const DEFAULT_TTL: u8 = 5;
+
+/// Queues backing a `MeshNode`, meant to be allocated in a `static`.
+pub struct MeshResources {
+    inqueue: InQueue,
+    outqueue: OutQueue,
+    app_channel: InQueue,
+}
+
+impl Default for MeshResources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MeshResources {
+    pub const fn new() -> Self {
+        Self {
+            inqueue: InQueue::new(),
+            outqueue: OutQueue::new(),
+            app_channel: InQueue::new(),
+        }
+    }
+}
+
+/// A mesh device with its queues wired, exposing the common operations.
+pub struct MeshNode<RK, DLY>
+where
+    RK: RadioKind,
+    DLY: DelayNs,
+{
+    device: LoraDevice<RK, DLY, InQueue, OutQueue>,
+}
+
+impl<RK, DLY> MeshNode<RK, DLY>
+where
+    RK: RadioKind,
+    DLY: DelayNs,
+{
+    pub fn new(
+        uid: Uid,
+        radio: LoRa<RK, DLY>,
+        lora_config: LoraConfig,
+        device_config: DeviceConfig,
+        resources: &'static mut MeshResources,
+    ) -> Self {
+        let MeshResources {
+            inqueue,
+            outqueue,
+            app_channel,
+        } = resources;
+        let mut device = LoraDevice::new(uid, radio, lora_config, device_config, inqueue, outqueue);
+        device.set_app_channel(app_channel);
+        Self { device }
+    }
+
+    pub fn with_mesh_config(mut self, mesh_config: MeshConfig) -> Self {
+        self.device.set_mesh_config(mesh_config);
+        self
+    }
+
+    pub fn device(&self) -> &LoraDevice<RK, DLY, InQueue, OutQueue> {
+        &self.device
+    }
+
+    pub fn device_mut(&mut self) -> &mut LoraDevice<RK, DLY, InQueue, OutQueue> {
+        &mut self.device
+    }
+
+    /// Queues `data` for `destination`, returning the ID of the message.
+    pub fn send(&mut self, destination: Uid, data: DataType, req_ack: bool) -> Result<MessageId, DeviceError> {
+        self.queue(Destination::Unicast(destination), data, req_ack)
+    }
+
+    /// Queues `data` for every node in range, returning the ID of the message.
+    pub fn broadcast(&mut self, data: DataType) -> Result<MessageId, DeviceError> {
+        self.queue(Destination::Broadcast, data, false)
+    }
+
+    /// Takes the oldest message received by the node, if any.
+    pub fn recv(&mut self) -> Option<Message> {
+        self.device.app_channel()?.dequeue().ok()
+    }
+
+    /// Direct neighbors the node currently has a route to.
+    pub fn neighbors(&self) -> impl Iterator<Item = Uid> + '_ {
+        self.device.neighbors()
+    }
+
+    /// Waits for the startup jitter and runs the initial discovery.
+    pub async fn start(&mut self) {
+        self.device.start().await;
+    }
+
+    /// Runs one iteration of the mesh loop: receive, process the queues and maintenance.
+    pub async fn poll(&mut self) {
+        self.device.poll().await;
+    }
+
+    /// Hands the node over to the mesh loop for good.
+    ///
+    /// Use `start` and `poll` instead to keep sending and receiving from the same task.
+    pub async fn run(self) {
+        run_quadranet(self.device).await;
+    }
+
+    fn queue(&mut self, destination: Destination, data: DataType, req_ack: bool) -> Result<MessageId, DeviceError> {
+        let message = Message::new_data(self.device.uid(), destination, data, DEFAULT_TTL, req_ack);
+        let id = message.message_id();
+        self.device.queue_outgoing(message)?;
+        Ok(id)
+    }
+}
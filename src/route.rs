@@ -1,9 +1,183 @@
+use defmt::Format;
+use embassy_time::{Duration, Instant};
+use serde::{Deserialize, Serialize};
+
 use crate::device::Uid;
 
+#[cfg(feature = "geo")]
+pub mod geo;
+pub mod ring_search;
 pub mod routing_table;
+pub mod store;
+
+/// Time after which a route that has not been refreshed is removed.
+pub const ROUTE_TTL: Duration = Duration::from_secs(300);
+/// Quality of a route that always delivers.
+pub const MAX_QUALITY: u8 = 100;
+/// Every delivery outcome moves the quality of a route by this fraction of the way to
+/// `MAX_QUALITY` or 0, at least one point.
+const QUALITY_STEP: u8 = 4;
 
 #[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Route {
     pub next_hop: Uid,
     pub hop_count: u8,
+    /// Delivery success rate through this route, from 0 to `MAX_QUALITY`, learned from
+    /// the acks of the messages sent through it
+    pub quality: u8,
+    /// Last time the route was confirmed by a discovery
+    pub last_seen: Instant,
+}
+
+/// Weights combining hop count and quality into a single route cost.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub struct RoutePolicy {
+    /// Cost of every hop
+    pub hop_weight: u16,
+    /// Cost of every missing quality point
+    pub quality_weight: u16,
+    /// How a new destination makes room in a full routing table
+    pub eviction: RouteEviction,
+    /// Quality given to a newly discovered route before it proves itself
+    pub initial_quality: u8,
+    /// Whether routes older than `ROUTE_TTL` are still used until they are cleaned up
+    pub stale_routes: StaleRoutes,
+}
+
+impl Default for RoutePolicy {
+    fn default() -> Self {
+        Self {
+            hop_weight: 10,
+            quality_weight: 1,
+            eviction: RouteEviction::KeepBetter,
+            initial_quality: MAX_QUALITY,
+            stale_routes: StaleRoutes::Rediscover,
+        }
+    }
+}
+
+/// Replacement policy of a full routing table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub enum RouteEviction {
+    /// Always evict the least recently seen route
+    LeastRecent,
+    /// Evict the least recently seen route only when the new route is at least as good,
+    /// rejecting the new route otherwise
+    KeepBetter,
+}
+
+/// Handling of a route that expired but was not cleaned up yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Format, Serialize, Deserialize)]
+pub enum StaleRoutes {
+    /// Keep sending through it, hoping its next hop is still there
+    Use,
+    /// Ignore it, so the destination is rediscovered instead of sending to a dead hop
+    Rediscover,
+}
+
+impl Route {
+    /// Whether this route leads to a direct neighbor of the device.
+    pub fn is_direct(&self, destination: u8) -> bool {
+        self.next_hop.get() == destination
+    }
+
+    pub fn is_expired(&self, now: Instant, max_age: Duration) -> bool {
+        now.saturating_duration_since(self.last_seen) > max_age
+    }
+
+    /// Single comparable cost of the route, lower is better.
+    pub fn cost(&self, policy: &RoutePolicy) -> u16 {
+        let missing_quality = MAX_QUALITY.saturating_sub(self.quality) as u16;
+        (self.hop_count as u16)
+            .saturating_mul(policy.hop_weight)
+            .saturating_add(missing_quality.saturating_mul(policy.quality_weight))
+    }
+
+    pub fn is_better_route(&self, other: &Route, policy: &RoutePolicy) -> bool {
+        self.cost(policy) < other.cost(policy)
+    }
+
+    /// Folds the outcome of a delivery through this route into its quality.
+    pub fn record_delivery(&mut self, delivered: bool) {
+        self.quality = if delivered {
+            let missing = MAX_QUALITY.saturating_sub(self.quality);
+            self.quality.saturating_add(missing.div_ceil(QUALITY_STEP))
+        } else {
+            self.quality - self.quality.div_ceil(QUALITY_STEP)
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Instant;
+
+    use crate::device::Uid;
+    use crate::route::{Route, RoutePolicy, MAX_QUALITY};
+
+    fn route(hop_count: u8, quality: u8) -> Route {
+        Route {
+            next_hop: Uid::try_from(2).unwrap(),
+            hop_count,
+            quality,
+            last_seen: Instant::from_secs(0),
+        }
+    }
+
+    #[test]
+    fn test_cost_ordering_matches_is_better_route() {
+        let policy = RoutePolicy::default();
+        let mut routes = [route(3, 100), route(1, 40), route(1, 100), route(2, 90)];
+
+        routes.sort_by_key(|route| route.cost(&policy));
+
+        assert_eq!(routes[0], route(1, 100));
+        for pair in routes.windows(2) {
+            assert!(!pair[1].is_better_route(&pair[0], &policy));
+        }
+        assert!(route(1, 100).is_better_route(&route(1, 40), &policy));
+        assert!(route(1, 100).is_better_route(&route(2, 100), &policy));
+    }
+
+    #[test]
+    fn test_cost_saturates() {
+        let policy = RoutePolicy {
+            hop_weight: u16::MAX,
+            quality_weight: u16::MAX,
+            ..RoutePolicy::default()
+        };
+
+        assert_eq!(route(255, 0).cost(&policy), u16::MAX);
+    }
+
+    #[test]
+    fn test_quality_follows_delivery_outcomes() {
+        let mut failing = route(1, MAX_QUALITY);
+        for _ in 0..3 {
+            failing.record_delivery(false);
+        }
+        assert_eq!(failing.quality, 42);
+
+        // Every outcome moves the quality, so both ends are reached
+        let mut recovering = failing;
+        for _ in 0..32 {
+            recovering.record_delivery(true);
+            failing.record_delivery(false);
+        }
+        assert_eq!(recovering.quality, MAX_QUALITY);
+        assert_eq!(failing.quality, 0);
+    }
+
+    #[test]
+    fn test_neutral_initial_quality_does_not_beat_proven_route() {
+        let policy = RoutePolicy {
+            initial_quality: MAX_QUALITY / 2,
+            ..RoutePolicy::default()
+        };
+        let proven = route(1, MAX_QUALITY);
+        let new = route(1, policy.initial_quality);
+
+        assert!(!new.is_better_route(&proven, &policy));
+        assert!(proven.is_better_route(&new, &policy));
+    }
 }
@@ -0,0 +1,129 @@
+//! Coarse position estimation from the RSSI of anchors with known positions.
+
+/// Position in an application defined planar coordinate system.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Position {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Log-distance path loss model used to turn an RSSI into a distance.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PathLossModel {
+    /// RSSI measured at one distance unit from the transmitter, in dBm
+    pub rssi_at_unit: f32,
+    /// Path loss exponent, 2 in free space and higher with obstacles
+    pub exponent: f32,
+}
+
+impl Default for PathLossModel {
+    fn default() -> Self {
+        Self {
+            rssi_at_unit: -40.0,
+            exponent: 2.7,
+        }
+    }
+}
+
+impl PathLossModel {
+    pub fn distance(&self, rssi: i16) -> f32 {
+        libm::powf(10.0, (self.rssi_at_unit - rssi as f32) / (10.0 * self.exponent))
+    }
+}
+
+/// Estimates a position from `(anchor position, RSSI)` pairs with the default model.
+pub fn estimate_position(anchors: &[(Position, i16)]) -> Option<Position> {
+    estimate_position_with(anchors, &PathLossModel::default())
+}
+
+/// Estimates a position from `(anchor position, RSSI)` pairs.
+///
+/// Uses least-squares trilateration with three anchors or more, and falls back to a
+/// centroid weighted by proximity when the anchors are too few or collinear.
+pub fn estimate_position_with(
+    anchors: &[(Position, i16)],
+    model: &PathLossModel,
+) -> Option<Position> {
+    let (&(last, last_rssi), others) = anchors.split_last()?;
+    let last_distance = model.distance(last_rssi);
+
+    // Subtracting the last circle equation from the others gives a linear system in x and y
+    let (mut a11, mut a12, mut a22, mut b1, mut b2) = (0.0, 0.0, 0.0, 0.0, 0.0);
+    for &(anchor, rssi) in others {
+        let distance = model.distance(rssi);
+        let ax = 2.0 * (last.x - anchor.x);
+        let ay = 2.0 * (last.y - anchor.y);
+        let b = distance * distance - last_distance * last_distance - anchor.x * anchor.x
+            + last.x * last.x
+            - anchor.y * anchor.y
+            + last.y * last.y;
+        a11 += ax * ax;
+        a12 += ax * ay;
+        a22 += ay * ay;
+        b1 += ax * b;
+        b2 += ay * b;
+    }
+
+    let det = a11 * a22 - a12 * a12;
+    if others.len() < 2 || libm::fabsf(det) < f32::EPSILON {
+        return Some(weighted_centroid(anchors, model));
+    }
+    Some(Position {
+        x: (a22 * b1 - a12 * b2) / det,
+        y: (a11 * b2 - a12 * b1) / det,
+    })
+}
+
+fn weighted_centroid(anchors: &[(Position, i16)], model: &PathLossModel) -> Position {
+    let (mut x, mut y, mut total) = (0.0, 0.0, 0.0);
+    for &(anchor, rssi) in anchors {
+        let weight = 1.0 / model.distance(rssi).max(f32::EPSILON);
+        x += anchor.x * weight;
+        y += anchor.y * weight;
+        total += weight;
+    }
+    Position {
+        x: x / total,
+        y: y / total,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::route::geo::{estimate_position, Position};
+
+    fn anchor(x: f32, y: f32, rssi: i16) -> (Position, i16) {
+        (Position { x, y }, rssi)
+    }
+
+    #[test]
+    fn test_trilateration_with_synthetic_anchors() {
+        // RSSI of a node at (3, 4) under the default path loss model
+        let anchors = [
+            anchor(0.0, 0.0, -59),
+            anchor(10.0, 0.0, -64),
+            anchor(0.0, 10.0, -62),
+            anchor(10.0, 10.0, -66),
+        ];
+
+        let position = estimate_position(&anchors).unwrap();
+
+        assert!((position.x - 3.0).abs() < 0.5);
+        assert!((position.y - 4.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_two_anchors_fall_back_to_centroid() {
+        let anchors = [anchor(0.0, 0.0, -60), anchor(10.0, 0.0, -60)];
+
+        let position = estimate_position(&anchors).unwrap();
+
+        assert!((position.x - 5.0).abs() < 0.01);
+        assert!(position.y.abs() < 0.01);
+    }
+
+    #[test]
+    fn test_no_anchor() {
+        assert_eq!(estimate_position(&[]), None);
+    }
+}
@@ -0,0 +1,103 @@
+use embassy_time::{Duration, Instant};
+use heapless::FnvIndexMap;
+
+const MAX_RING_SEARCHES: usize = 8;
+/// Time to wait for a discovery answer before widening the ring.
+pub const RING_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RingSearch {
+    /// TTL of the last discovery flood sent for this destination
+    pub ttl: u8,
+    pub sent_at: Instant,
+}
+
+/// Per-destination state of expanding-ring route discoveries.
+///
+/// A search starts with a TTL 1 flood and widens the ring by one hop every
+/// `RING_TIMEOUT` until a route is found or the maximum TTL has been tried.
+#[derive(Default)]
+pub struct RingSearches {
+    searches: FnvIndexMap<u8, RingSearch, MAX_RING_SEARCHES>,
+}
+
+impl RingSearches {
+    /// Starts a search for `destination`, returning the TTL of the first ring to flood,
+    /// or `None` if a search is already in progress or none can be tracked.
+    pub fn start(&mut self, destination: u8, now: Instant) -> Option<u8> {
+        if self.searches.contains_key(&destination) {
+            return None;
+        }
+        let search = RingSearch { ttl: 1, sent_at: now };
+        self.searches.insert(destination, search).ok()?;
+        Some(search.ttl)
+    }
+
+    /// Ends the search for `destination` once a route to it is known.
+    pub fn resolve(&mut self, destination: u8) {
+        self.searches.remove(&destination);
+    }
+
+    pub fn is_searching(&self, destination: u8) -> bool {
+        self.searches.contains_key(&destination)
+    }
+
+    /// Widens every timed out search, dropping those that already tried `max_ttl`.
+    ///
+    /// Returns the TTL of the flood to send, if any: a single flood at the widest
+    /// ring also covers every narrower one.
+    pub fn advance(&mut self, now: Instant, max_ttl: u8) -> Option<u8> {
+        let mut flood = None;
+        self.searches.retain(|_, search| {
+            if now.saturating_duration_since(search.sent_at) < RING_TIMEOUT {
+                return true;
+            }
+            if search.ttl >= max_ttl {
+                return false;
+            }
+            search.ttl += 1;
+            search.sent_at = now;
+            flood = flood.max(Some(search.ttl));
+            true
+        });
+        flood
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Instant;
+
+    use crate::route::ring_search::{RingSearches, RING_TIMEOUT};
+
+    #[test]
+    fn test_nearby_destination_costs_one_ring() {
+        let mut searches = RingSearches::default();
+        let now = Instant::from_secs(0);
+
+        assert_eq!(searches.start(2, now), Some(1));
+        assert_eq!(searches.start(2, now), None);
+        searches.resolve(2);
+
+        assert_eq!(searches.advance(now + RING_TIMEOUT, 5), None);
+        assert!(!searches.is_searching(2));
+    }
+
+    #[test]
+    fn test_far_destination_widens_until_max() {
+        let mut searches = RingSearches::default();
+        let mut now = Instant::from_secs(0);
+        let mut flooded_hops = searches.start(2, now).unwrap() as u32;
+
+        for expected in 2..=3 {
+            now += RING_TIMEOUT;
+            let ttl = searches.advance(now, 3).unwrap();
+            assert_eq!(ttl, expected);
+            flooded_hops += ttl as u32;
+        }
+        now += RING_TIMEOUT;
+        assert_eq!(searches.advance(now, 3), None);
+        assert!(!searches.is_searching(2));
+        assert_eq!(flooded_hops, 1 + 2 + 3);
+    }
+}
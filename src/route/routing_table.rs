@@ -1,10 +1,16 @@
 use defmt::debug;
+use embassy_time::{Duration, Instant};
 use heapless::FnvIndexMap;
 
-use crate::route::Route;
+use crate::device::Uid;
+use crate::message::MAX_TTL;
+use crate::route::store::{RouteRecord, RouteSnapshot};
+use crate::route::{Route, RouteEviction, RoutePolicy, StaleRoutes, ROUTE_TTL};
+
+pub const MAX_ROUTES: usize = 128;
 
 pub struct RoutingTable {
-    routes: FnvIndexMap<u8, Route, 128>,
+    routes: FnvIndexMap<u8, Route, MAX_ROUTES>,
 }
 
 impl Default for RoutingTable {
@@ -16,17 +22,430 @@ impl Default for RoutingTable {
 }
 
 impl RoutingTable {
-    pub fn update(&mut self, destination: u8, route: Route) {
+    /// Stores `route`, evicting the least recently seen route when the table is full.
+    ///
+    /// Returns the destination of the evicted route, if any.
+    pub fn update(&mut self, destination: u8, route: Route) -> Option<u8> {
+        let mut evicted = None;
         if let Err((destination, route)) = self.routes.insert(destination, route) {
-            // Remove the oldest entry
-            let _ = self.routes.remove(&destination);
-            // Insert the new entry
+            evicted = self.least_recent();
+            if let Some(oldest) = evicted {
+                self.routes.remove(&oldest);
+            }
             let _ = self.routes.insert(destination, route);
         }
         debug!("ROUTING TABLE UPDATE @{}", destination);
+        evicted
+    }
+
+    /// Stores `route` unless a better route through another next hop is already known.
+    ///
+    /// A route through the same next hop always replaces the stored one, refreshing it but
+    /// keeping the quality its deliveries earned.
+    /// A new destination only makes room in a full table as allowed by `policy.eviction`.
+    /// Returns the destination of the evicted route, if any.
+    pub fn update_if_better(&mut self, destination: u8, mut route: Route, policy: &RoutePolicy) -> Option<u8> {
+        match self.routes.get(&destination) {
+            Some(current) if current.next_hop == route.next_hop => {
+                route.quality = current.quality;
+            }
+            Some(current) => {
+                if !route.is_better_route(current, policy) {
+                    return None;
+                }
+            }
+            None if self.routes.len() == self.routes.capacity() => {
+                if policy.eviction == RouteEviction::KeepBetter {
+                    let oldest = self.least_recent().and_then(|oldest| self.routes.get(&oldest));
+                    if oldest.is_some_and(|oldest| oldest.is_better_route(&route, policy)) {
+                        debug!("ROUTING TABLE FULL, REJECTING @{}", destination);
+                        return None;
+                    }
+                }
+            }
+            None => {}
+        }
+        self.update(destination, route)
+    }
+
+    /// Destination of the least recently seen route.
+    ///
+    /// Ties go to the lowest destination, so the choice never depends on the map order.
+    fn least_recent(&self) -> Option<u8> {
+        self.routes
+            .iter()
+            .min_by_key(|(&destination, route)| (route.last_seen, destination))
+            .map(|(&destination, _)| destination)
+    }
+
+    /// Folds the outcome of a delivery to `destination` into the quality of its route.
+    pub fn record_delivery(&mut self, destination: u8, delivered: bool) {
+        if let Some(route) = self.routes.get_mut(&destination) {
+            route.record_delivery(delivered);
+        }
     }
 
     pub fn lookup_route(&self, destination: u8) -> Option<Route> {
         self.routes.get(&destination).copied()
     }
+
+    /// TTL reaching `destination` over its known route with `margin` spare hops, or
+    /// `MAX_TTL` when no route to it is known.
+    pub fn ttl_to(&self, destination: u8, margin: u8) -> u8 {
+        self.routes.get(&destination).map_or(MAX_TTL, |route| {
+            // A direct neighbor has a hop count of 0 and takes one transmission
+            route.hop_count.saturating_add(1).saturating_add(margin).min(MAX_TTL)
+        })
+    }
+
+    pub fn remove(&mut self, destination: u8) -> Option<Route> {
+        self.routes.remove(&destination)
+    }
+
+    /// Route to send through at `now`, leaving out an expired route unless
+    /// `policy.stale_routes` allows it.
+    pub fn active_route(&self, destination: u8, now: Instant, policy: &RoutePolicy) -> Option<Route> {
+        self.lookup_route(destination).filter(|route| {
+            policy.stale_routes == StaleRoutes::Use || !route.is_expired(now, ROUTE_TTL)
+        })
+    }
+
+    /// Every destination with a known route, direct neighbors and multi-hop alike.
+    pub fn destinations(&self) -> impl Iterator<Item = u8> + '_ {
+        self.routes.keys().copied()
+    }
+
+    /// Whether `uid` is reached directly, without any relay.
+    pub fn is_neighbor(&self, uid: Uid) -> bool {
+        self.routes
+            .get(&uid.get())
+            .is_some_and(|route| route.is_direct(uid.get()))
+    }
+
+    /// Destinations reached directly, without any relay.
+    pub fn neighbors(&self) -> impl Iterator<Item = Uid> + '_ {
+        self.routes
+            .iter()
+            .filter(|(&destination, route)| route.is_direct(destination))
+            .filter_map(|(&destination, _)| Uid::new(destination))
+    }
+
+    pub fn export(&self) -> RouteSnapshot {
+        self.routes
+            .iter()
+            .map(|(&destination, route)| RouteRecord {
+                destination,
+                next_hop: route.next_hop,
+                hop_count: route.hop_count,
+                quality: route.quality,
+            })
+            .collect()
+    }
+
+    /// Restores exported routes, considering them seen at `now`.
+    pub fn import(&mut self, snapshot: &RouteSnapshot, now: Instant) {
+        for record in snapshot {
+            self.update(
+                record.destination,
+                Route {
+                    next_hop: record.next_hop,
+                    hop_count: record.hop_count,
+                    quality: record.quality,
+                    last_seen: now,
+                },
+            );
+        }
+    }
+
+    /// Removes every route older than `max_age`, calling `removed` for each of them.
+    pub fn remove_expired<F>(&mut self, now: Instant, max_age: Duration, mut removed: F)
+    where
+        F: FnMut(u8, &Route),
+    {
+        self.routes.retain(|destination, route| {
+            let expired = route.is_expired(now, max_age);
+            if expired {
+                removed(*destination, route);
+            }
+            !expired
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::{Duration, Instant};
+    use heapless::Vec;
+
+    use crate::device::Uid;
+    use crate::message::MAX_TTL;
+    use crate::route::routing_table::{RoutingTable, MAX_ROUTES};
+    use crate::route::{Route, RouteEviction, RoutePolicy, StaleRoutes, ROUTE_TTL};
+
+    #[test]
+    fn test_remove_expired_reports_neighbor_once() {
+        let mut table = RoutingTable::default();
+        let neighbor = Uid::try_from(2).unwrap();
+        table.update(
+            2,
+            Route {
+                next_hop: neighbor,
+                hop_count: 0,
+                quality: 100,
+                last_seen: Instant::from_secs(0),
+            },
+        );
+        table.update(
+            3,
+            Route {
+                next_hop: neighbor,
+                hop_count: 1,
+                quality: 100,
+                last_seen: Instant::from_secs(50),
+            },
+        );
+
+        let mut down = Vec::<u8, 4>::new();
+        let mut collect = |destination: u8, route: &Route| {
+            if route.is_direct(destination) {
+                down.push(destination).unwrap();
+            }
+        };
+        table.remove_expired(Instant::from_secs(100), Duration::from_secs(60), &mut collect);
+        table.remove_expired(Instant::from_secs(101), Duration::from_secs(60), &mut collect);
+
+        assert_eq!(down.as_slice(), &[2]);
+        assert!(table.lookup_route(2).is_none());
+        assert!(table.lookup_route(3).is_some());
+    }
+
+    fn fill(table: &mut RoutingTable) {
+        for destination in 1..=MAX_ROUTES as u8 {
+            table.update(
+                destination,
+                Route {
+                    next_hop: Uid::try_from(destination).unwrap(),
+                    hop_count: 0,
+                    quality: 100,
+                    last_seen: Instant::from_secs(destination as u64),
+                },
+            );
+        }
+    }
+
+    #[test]
+    fn test_failing_route_loses_to_an_alternative() {
+        let mut table = RoutingTable::default();
+        let policy = RoutePolicy::default();
+        let failing = Route {
+            next_hop: Uid::try_from(2).unwrap(),
+            hop_count: 1,
+            quality: 100,
+            last_seen: Instant::from_secs(0),
+        };
+        let alternative = Route {
+            next_hop: Uid::try_from(3).unwrap(),
+            hop_count: 2,
+            ..failing
+        };
+        table.update(9, failing);
+
+        assert_eq!(table.update_if_better(9, alternative, &policy), None);
+        assert_eq!(table.lookup_route(9).unwrap().next_hop, failing.next_hop);
+
+        for _ in 0..3 {
+            table.record_delivery(9, false);
+        }
+        // Rediscovering the failing route refreshes it without restoring its quality
+        let refreshed = Route {
+            last_seen: Instant::from_secs(10),
+            ..failing
+        };
+        table.update_if_better(9, refreshed, &policy);
+        assert!(table.lookup_route(9).unwrap().quality < failing.quality);
+
+        table.update_if_better(9, alternative, &policy);
+        assert_eq!(table.lookup_route(9).unwrap().next_hop, alternative.next_hop);
+    }
+
+    #[test]
+    fn test_weak_route_does_not_evict_established_one() {
+        let mut table = RoutingTable::default();
+        fill(&mut table);
+        let policy = RoutePolicy::default();
+        let weak = Route {
+            next_hop: Uid::try_from(5).unwrap(),
+            hop_count: 4,
+            quality: 20,
+            last_seen: Instant::from_secs(500),
+        };
+
+        table.update_if_better(200, weak, &policy);
+
+        assert!(table.lookup_route(200).is_none());
+        assert!(table.lookup_route(1).is_some());
+
+        let strong = Route {
+            hop_count: 0,
+            quality: 100,
+            ..weak
+        };
+        let evicted = table.update_if_better(200, strong, &policy);
+
+        assert_eq!(evicted, Some(1));
+        assert!(table.lookup_route(200).is_some());
+        assert!(table.lookup_route(1).is_none());
+    }
+
+    #[test]
+    fn test_least_recent_eviction_accepts_any_route() {
+        let mut table = RoutingTable::default();
+        fill(&mut table);
+        let policy = RoutePolicy {
+            eviction: RouteEviction::LeastRecent,
+            ..RoutePolicy::default()
+        };
+        let weak = Route {
+            next_hop: Uid::try_from(5).unwrap(),
+            hop_count: 4,
+            quality: 20,
+            last_seen: Instant::from_secs(500),
+        };
+
+        let evicted = table.update_if_better(200, weak, &policy);
+
+        assert_eq!(evicted, Some(1));
+        assert!(table.lookup_route(200).is_some());
+        assert!(table.lookup_route(1).is_none());
+        assert_eq!(table.update_if_better(200, weak, &policy), None);
+    }
+
+    #[test]
+    fn test_destinations_include_multi_hop_routes() {
+        let mut table = RoutingTable::default();
+        let neighbor = Uid::try_from(2).unwrap();
+        for (destination, hop_count) in [(2, 0), (3, 1), (7, 2), (9, 3)] {
+            table.update(
+                destination,
+                Route {
+                    next_hop: neighbor,
+                    hop_count,
+                    quality: 100,
+                    last_seen: Instant::from_secs(0),
+                },
+            );
+        }
+
+        let mut destinations: Vec<u8, 8> = table.destinations().collect();
+        destinations.sort_unstable();
+
+        assert_eq!(destinations.as_slice(), &[2, 3, 7, 9]);
+        assert!(table.neighbors().eq([neighbor]));
+    }
+
+    #[test]
+    fn test_seeded_route_through_neighbor_is_looked_up() {
+        let mut table = RoutingTable::default();
+        let neighbor = Uid::try_from(2).unwrap();
+        table.update(
+            2,
+            Route {
+                next_hop: neighbor,
+                hop_count: 0,
+                quality: 100,
+                last_seen: Instant::from_secs(0),
+            },
+        );
+
+        assert!(table.is_neighbor(neighbor));
+        assert!(!table.is_neighbor(Uid::try_from(5).unwrap()));
+        table.update(
+            5,
+            Route {
+                next_hop: neighbor,
+                hop_count: 2,
+                quality: 80,
+                last_seen: Instant::from_secs(1),
+            },
+        );
+
+        let route = table.lookup_route(5).unwrap();
+        assert_eq!(route.next_hop, neighbor);
+        assert_eq!(route.hop_count, 2);
+        assert!(!table.is_neighbor(Uid::try_from(5).unwrap()));
+    }
+
+    #[test]
+    fn test_ttl_follows_route_hop_count() {
+        let mut table = RoutingTable::default();
+        let neighbor = Uid::try_from(2).unwrap();
+        for (destination, hop_count) in [(2, 0), (7, 3), (9, 250)] {
+            table.update(
+                destination,
+                Route {
+                    next_hop: neighbor,
+                    hop_count,
+                    quality: 100,
+                    last_seen: Instant::from_secs(0),
+                },
+            );
+        }
+
+        assert_eq!(table.ttl_to(2, 1), 2);
+        assert_eq!(table.ttl_to(7, 1), 5);
+        assert_eq!(table.ttl_to(7, 0), 4);
+        assert_eq!(table.ttl_to(9, 1), MAX_TTL);
+        assert_eq!(table.ttl_to(4, 1), MAX_TTL);
+    }
+
+    #[test]
+    fn test_expired_route_use_follows_policy() {
+        let mut table = RoutingTable::default();
+        table.update(
+            5,
+            Route {
+                next_hop: Uid::try_from(2).unwrap(),
+                hop_count: 1,
+                quality: 100,
+                last_seen: Instant::from_secs(0),
+            },
+        );
+        let fresh = Instant::from_secs(0) + ROUTE_TTL;
+        let expired = fresh + Duration::from_secs(1);
+        let conservative = RoutePolicy::default();
+        let optimistic = RoutePolicy {
+            stale_routes: StaleRoutes::Use,
+            ..RoutePolicy::default()
+        };
+
+        assert_eq!(conservative.stale_routes, StaleRoutes::Rediscover);
+        assert!(table.active_route(5, fresh, &conservative).is_some());
+        assert!(table.active_route(5, expired, &conservative).is_none());
+        assert_eq!(
+            table.active_route(5, expired, &optimistic),
+            table.lookup_route(5)
+        );
+        assert!(table.active_route(6, expired, &optimistic).is_none());
+    }
+
+    #[test]
+    fn test_eviction_ties_go_to_lowest_destination() {
+        let mut table = RoutingTable::default();
+        let seen_at = Instant::from_secs(10);
+        let route = |next_hop: u8| Route {
+            next_hop: Uid::try_from(next_hop).unwrap(),
+            hop_count: 0,
+            quality: 100,
+            last_seen: seen_at,
+        };
+        // Inserted highest first, then shuffled by a removal, so map order is not key order
+        for destination in (1..=MAX_ROUTES as u8).rev() {
+            table.update(destination, route(destination));
+        }
+        table.routes.swap_remove(&(MAX_ROUTES as u8));
+        table.update(MAX_ROUTES as u8, route(1));
+
+        assert_eq!(table.update(200, route(1)), Some(1));
+        assert_eq!(table.update(201, route(1)), Some(2));
+    }
 }
@@ -0,0 +1,77 @@
+use defmt::Format;
+use heapless::Vec;
+use serde::{Deserialize, Serialize};
+
+use crate::device::Uid;
+use crate::route::routing_table::MAX_ROUTES;
+
+/// Persistent form of a route, without its timing information.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Format)]
+pub struct RouteRecord {
+    pub destination: u8,
+    pub next_hop: Uid,
+    pub hop_count: u8,
+    pub quality: u8,
+}
+
+pub type RouteSnapshot = Vec<RouteRecord, MAX_ROUTES>;
+
+/// Platform specific storage, such as flash, keeping routes across reboots.
+///
+/// The device restores the snapshot when the store is attached and saves it
+/// periodically, at most once every `MeshConfig::route_save_interval`.
+pub trait RouteStore {
+    fn load(&mut self) -> Option<RouteSnapshot>;
+    fn save(&mut self, snapshot: &RouteSnapshot);
+}
+
+#[cfg(test)]
+mod test {
+    use embassy_time::Instant;
+
+    use crate::device::Uid;
+    use crate::route::routing_table::RoutingTable;
+    use crate::route::store::{RouteSnapshot, RouteStore};
+    use crate::route::Route;
+
+    #[derive(Default)]
+    struct MockStore {
+        snapshot: Option<RouteSnapshot>,
+        saves: usize,
+    }
+
+    impl RouteStore for MockStore {
+        fn load(&mut self) -> Option<RouteSnapshot> {
+            self.snapshot.clone()
+        }
+
+        fn save(&mut self, snapshot: &RouteSnapshot) {
+            self.snapshot = Some(snapshot.clone());
+            self.saves += 1;
+        }
+    }
+
+    #[test]
+    fn test_routes_survive_store_round_trip() {
+        let mut store = MockStore::default();
+        let mut table = RoutingTable::default();
+        let route = Route {
+            next_hop: Uid::try_from(2).unwrap(),
+            hop_count: 1,
+            quality: 100,
+            last_seen: Instant::from_secs(10),
+        };
+        table.update(3, route);
+        store.save(&table.export());
+
+        let mut restored = RoutingTable::default();
+        restored.import(&store.load().unwrap(), Instant::from_secs(20));
+
+        let restored_route = restored.lookup_route(3).unwrap();
+        assert_eq!(restored_route.next_hop, route.next_hop);
+        assert_eq!(restored_route.hop_count, route.hop_count);
+        assert_eq!(restored_route.quality, route.quality);
+        assert_eq!(restored_route.last_seen, Instant::from_secs(20));
+        assert_eq!(store.saves, 1);
+    }
+}